@@ -0,0 +1,772 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use p12_keystore::{KeyStore, KeyStoreEntry};
+use rsa::{
+    pkcs1v15::SigningKey,
+    pkcs8::DecodePrivateKey,
+    signature::{SignatureEncoding, Signer},
+    RsaPrivateKey,
+};
+use sha2::{Digest, Sha256};
+use std::{io::Write, path::Path};
+
+use crate::{
+    apk_signing_block::{
+        build_apk_signing_block, find_apk_signing_block, APK_SIGNATURE_SCHEME_V2_BLOCK_ID,
+        APK_SIGNATURE_SCHEME_V31_BLOCK_ID, APK_SIGNATURE_SCHEME_V3_BLOCK_ID,
+        VERITY_PADDING_BLOCK_ID,
+    },
+    error::RepackError,
+    sign_config::SignConfig,
+    zip_util::{
+        find_eocd, read_central_directory, read_entry_data, read_u16,
+        write_central_directory_header, write_eocd, write_local_header, ZipEntry,
+        LOCAL_FILE_HEADER_SIZE, METHOD_DEFLATED, METHOD_STORED,
+    },
+};
+
+/// RSASSA-PKCS1-v1_5 with SHA2-256 digest
+const SIGNATURE_RSA_PKCS1_V1_5_WITH_SHA256: u32 = 0x0103;
+
+/// tells v2 verifiers that a v3 signature is expected, protects against stripping
+const STRIPPING_PROTECTION_ATTR_ID: u32 = 0xbeeff00d;
+const SIGNATURE_SCHEME_V3_ID: u32 = 3;
+
+/// v3 is verified from Android 9 (P)
+const V3_MIN_SDK_VERSION: u32 = 28;
+const V3_MAX_SDK_VERSION: u32 = i32::MAX as u32;
+
+const CONTENT_DIGEST_CHUNK_SIZE: usize = 1024 * 1024;
+
+const ALIGNMENT_EXTRA_ID: u16 = 0xd935;
+const ALIGNMENT_EXTRA_MIN_SIZE: usize = 6;
+const STORED_ENTRY_ALIGNMENT: usize = 4;
+const NATIVE_LIB_ALIGNMENT: usize = 4096;
+
+const V1_MANIFEST_NAME: &str = "META-INF/MANIFEST.MF";
+const V1_SIGNATURE_FILE_NAME: &str = "META-INF/CERT.SF";
+const V1_SIGNATURE_BLOCK_NAME: &str = "META-INF/CERT.RSA";
+const V1_CREATED_BY: &str = "1.0 (Android)";
+
+/// 2008-01-01 00:00, DOS format
+const DOS_DATE: u16 = (28 << 9) | (1 << 5) | 1;
+const DOS_TIME: u16 = 0;
+
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+/// entry name and its section of the v1 manifest
+type ManifestSection = (String, Vec<u8>);
+
+pub struct ApkSigningKey {
+    private_key: RsaPrivateKey,
+    /// DER encoded, the signing certificate first
+    certificates: Vec<Vec<u8>>,
+}
+
+/// Loads the RSA key and certificate chain of `key_alias` from a PKCS#12 keystore.
+pub fn load_signing_key(sign_config: &SignConfig) -> Result<ApkSigningKey> {
+    log::info!("load_signing_key");
+
+    let keystore_bytes = std::fs::read(&sign_config.keystore_path).map_err(|e| {
+        anyhow::anyhow!("cannot read keystore {}: {}", sign_config.keystore_path, e)
+    })?;
+
+    // the key of a PKCS#12 keystore is protected by the store password, a
+    // different key password would only fail later with a vague message
+    if !sign_config.key_password.is_empty()
+        && sign_config.key_password != sign_config.store_password
+    {
+        return Err(RepackError::SignFailed {
+            detail: format!(
+                "the key password of {} differs from the store password, PKCS#12 keystores use one password",
+                sign_config.keystore_path
+            ),
+        }
+        .into());
+    }
+
+    let keystore =
+        KeyStore::from_pkcs12(&keystore_bytes, &sign_config.store_password).map_err(|e| {
+            anyhow::anyhow!(
+                "cannot open keystore {}, it must be PKCS#12 and the password must match: {}",
+                sign_config.keystore_path,
+                e
+            )
+        })?;
+
+    let entry = keystore.entry(&sign_config.key_alias).or_else(|| {
+        keystore
+            .entries()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(&sign_config.key_alias))
+            .map(|(_, entry)| entry)
+    });
+
+    let key_chain = match entry {
+        Some(KeyStoreEntry::PrivateKeyChain(key_chain)) => key_chain,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "no private key with alias {} in keystore {}",
+                sign_config.key_alias,
+                sign_config.keystore_path
+            ))
+        }
+    };
+
+    let private_key = RsaPrivateKey::from_pkcs8_der(key_chain.key())
+        .map_err(|e| anyhow::anyhow!("only RSA keys are supported: {}", e))?;
+
+    let certificates = key_chain
+        .chain()
+        .iter()
+        .map(|certificate| certificate.as_der().to_vec())
+        .collect::<Vec<Vec<u8>>>();
+    if certificates.is_empty() {
+        return Err(anyhow::anyhow!(
+            "no certificate for alias {}",
+            sign_config.key_alias
+        ));
+    }
+
+    Ok(ApkSigningKey {
+        private_key,
+        certificates,
+    })
+}
+
+/// Zipaligns and signs the apk in place with v1, v2 and v3 signatures.
+pub fn sign_apk_file(apk_file_path: &Path, signing_key: &ApkSigningKey) -> Result<()> {
+    log::info!("sign_apk_file: {}", apk_file_path.to_string_lossy());
+
+    let apk_data = std::fs::read(apk_file_path)?;
+    let signed_apk = sign_apk_data(&apk_data, signing_key)?;
+
+    let tmp_file_path = apk_file_path.with_extension("signing");
+    std::fs::write(&tmp_file_path, signed_apk)?;
+    std::fs::rename(&tmp_file_path, apk_file_path)?;
+
+    Ok(())
+}
+
+pub fn sign_apk_data(apk_data: &[u8], signing_key: &ApkSigningKey) -> Result<Vec<u8>> {
+    let eocd = find_eocd(apk_data)?;
+    let entries = read_central_directory(apk_data, &eocd)?;
+
+    // keep third party pairs of the old block, e.g. channel info
+    let kept_pairs = find_apk_signing_block(apk_data, &eocd)?
+        .map(|block| {
+            block
+                .pairs
+                .into_iter()
+                .filter(|(id, _)| {
+                    ![
+                        APK_SIGNATURE_SCHEME_V2_BLOCK_ID,
+                        APK_SIGNATURE_SCHEME_V3_BLOCK_ID,
+                        APK_SIGNATURE_SCHEME_V31_BLOCK_ID,
+                        VERITY_PADDING_BLOCK_ID,
+                    ]
+                    .contains(id)
+                })
+                .collect::<Vec<(u32, Vec<u8>)>>()
+        })
+        .unwrap_or_default();
+
+    let entries = entries
+        .into_iter()
+        .filter(|entry| !is_v1_signature_entry(&entry.name))
+        .collect::<Vec<ZipEntry>>();
+
+    // v1 signature
+    let (manifest, sections) = build_v1_manifest(apk_data, &entries)?;
+    let signature_file = build_v1_signature_file(&manifest, &sections);
+    let signature_block = build_v1_signature_block(&signature_file, signing_key)?;
+
+    // zipaligned archive, the v1 files first
+    let mut zip_data = Vec::with_capacity(apk_data.len());
+    let mut cd_entries = vec![];
+    for (name, content) in [
+        (V1_MANIFEST_NAME, manifest.as_slice()),
+        (V1_SIGNATURE_FILE_NAME, signature_file.as_slice()),
+        (V1_SIGNATURE_BLOCK_NAME, signature_block.as_slice()),
+    ] {
+        let (mut entry, compressed) = new_deflated_entry(name, content)?;
+        entry.local_header_offset = zip_data.len();
+        write_local_header(&mut zip_data, &entry, &[]);
+        zip_data.extend_from_slice(&compressed);
+        cd_entries.push(entry);
+    }
+
+    for entry in &entries {
+        let mut entry = entry.clone();
+        let raw_data = entry.raw_data(apk_data)?;
+        let local_header_offset = zip_data.len();
+        let extra = aligned_extra(&entry, local_header_offset);
+        entry.local_header_offset = local_header_offset;
+        write_local_header(&mut zip_data, &entry, &extra);
+        zip_data.extend_from_slice(raw_data);
+        cd_entries.push(entry);
+    }
+
+    let cd_offset = zip_data.len();
+    let mut cd_data = vec![];
+    for entry in &cd_entries {
+        write_central_directory_header(&mut cd_data, entry);
+    }
+    let mut eocd_data = vec![];
+    write_eocd(
+        &mut eocd_data,
+        cd_entries.len(),
+        cd_offset,
+        cd_data.len(),
+        &eocd.comment,
+    );
+
+    // v2 and v3, both over the same content digest
+    let digest = compute_content_digest(&[&zip_data, &cd_data, &eocd_data]);
+    let mut pairs = vec![
+        (
+            APK_SIGNATURE_SCHEME_V2_BLOCK_ID,
+            build_v2_block(&digest, signing_key)?,
+        ),
+        (
+            APK_SIGNATURE_SCHEME_V3_BLOCK_ID,
+            build_v3_block(&digest, signing_key)?,
+        ),
+    ];
+    pairs.extend(kept_pairs);
+    let signing_block = build_apk_signing_block(&pairs);
+
+    let mut eocd_data = vec![];
+    write_eocd(
+        &mut eocd_data,
+        cd_entries.len(),
+        cd_offset + signing_block.len(),
+        cd_data.len(),
+        &eocd.comment,
+    );
+
+    let mut signed_apk = zip_data;
+    signed_apk.extend_from_slice(&signing_block);
+    signed_apk.extend_from_slice(&cd_data);
+    signed_apk.extend_from_slice(&eocd_data);
+
+    Ok(signed_apk)
+}
+
+fn is_v1_signature_entry(name: &str) -> bool {
+    let Some(file_name) = name.strip_prefix("META-INF/") else {
+        return false;
+    };
+    if file_name.contains('/') {
+        return false;
+    }
+
+    let upper = file_name.to_ascii_uppercase();
+    upper == "MANIFEST.MF"
+        || upper.starts_with("SIG-")
+        || [".SF", ".RSA", ".DSA", ".EC"]
+            .iter()
+            .any(|ext| upper.ends_with(ext))
+}
+
+fn new_deflated_entry(name: &str, content: &[u8]) -> Result<(ZipEntry, Vec<u8>)> {
+    let mut encoder =
+        flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(content)?;
+    let compressed = encoder.finish()?;
+
+    let entry = ZipEntry {
+        name: name.to_string(),
+        version_made_by: 20,
+        version_needed: 20,
+        flags: 0,
+        method: METHOD_DEFLATED,
+        time: DOS_TIME,
+        date: DOS_DATE,
+        crc32: crc32fast::hash(content),
+        compressed_size: compressed.len(),
+        uncompressed_size: content.len(),
+        internal_attrs: 0,
+        external_attrs: 0,
+        cd_extra: vec![],
+        comment: vec![],
+        local_header_offset: 0,
+        local_extra: vec![],
+        data_offset: 0,
+    };
+
+    Ok((entry, compressed))
+}
+
+/// Local extra field for an entry written at `local_header_offset`, padded so
+/// stored data starts 4 byte aligned, or page aligned for native libraries.
+fn aligned_extra(entry: &ZipEntry, local_header_offset: usize) -> Vec<u8> {
+    // drop any previous alignment padding, keep the rest if well formed
+    let mut extra = vec![];
+    let mut pos = 0;
+    while pos + 4 <= entry.local_extra.len() {
+        let id = read_u16(&entry.local_extra, pos).unwrap_or(0);
+        let size = read_u16(&entry.local_extra, pos + 2).unwrap_or(0) as usize;
+        let end = pos + 4 + size;
+        if end > entry.local_extra.len() {
+            extra.clear();
+            break;
+        }
+        if id != ALIGNMENT_EXTRA_ID && id != 0 {
+            extra.extend_from_slice(&entry.local_extra[pos..end]);
+        }
+        pos = end;
+    }
+
+    if entry.method != METHOD_STORED {
+        return extra;
+    }
+
+    let alignment = if entry.name.ends_with(".so") {
+        NATIVE_LIB_ALIGNMENT
+    } else {
+        STORED_ENTRY_ALIGNMENT
+    };
+
+    let data_offset = local_header_offset
+        + LOCAL_FILE_HEADER_SIZE
+        + entry.name.len()
+        + extra.len()
+        + ALIGNMENT_EXTRA_MIN_SIZE;
+    let padding = (alignment - data_offset % alignment) % alignment;
+
+    extra.extend_from_slice(&ALIGNMENT_EXTRA_ID.to_le_bytes());
+    extra.extend_from_slice(&((2 + padding) as u16).to_le_bytes());
+    extra.extend_from_slice(&(alignment as u16).to_le_bytes());
+    extra.resize(extra.len() + padding, 0);
+
+    extra
+}
+
+fn sha256_base64(data: &[u8]) -> String {
+    BASE64.encode(Sha256::digest(data))
+}
+
+/// Writes a manifest header, wrapping lines longer than 72 bytes.
+fn write_manifest_attribute(out: &mut Vec<u8>, name: &str, value: &str) {
+    let line = format!("{}: {}", name, value);
+    let bytes = line.as_bytes();
+
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let max_len = if pos == 0 { 72 } else { 71 };
+        let end = (pos + max_len).min(bytes.len());
+        if pos != 0 {
+            out.push(b' ');
+        }
+        out.extend_from_slice(&bytes[pos..end]);
+        out.extend_from_slice(b"\r\n");
+        pos = end;
+    }
+}
+
+fn manifest_entry_section(name: &str, digest: &str) -> Vec<u8> {
+    let mut section = vec![];
+    write_manifest_attribute(&mut section, "Name", name);
+    write_manifest_attribute(&mut section, "SHA-256-Digest", digest);
+    section.extend_from_slice(b"\r\n");
+    section
+}
+
+/// Returns the manifest and each entry section, keyed by entry name.
+fn build_v1_manifest(
+    apk_data: &[u8],
+    entries: &[ZipEntry],
+) -> Result<(Vec<u8>, Vec<ManifestSection>)> {
+    let mut manifest = vec![];
+    write_manifest_attribute(&mut manifest, "Manifest-Version", "1.0");
+    write_manifest_attribute(&mut manifest, "Created-By", V1_CREATED_BY);
+    manifest.extend_from_slice(b"\r\n");
+
+    let mut files = entries
+        .iter()
+        .filter(|entry| !entry.is_dir())
+        .collect::<Vec<&ZipEntry>>();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut sections = vec![];
+    for entry in files {
+        let content = read_entry_data(apk_data, entry)?;
+        let section = manifest_entry_section(&entry.name, &sha256_base64(&content));
+        manifest.extend_from_slice(&section);
+        sections.push((entry.name.clone(), section));
+    }
+
+    Ok((manifest, sections))
+}
+
+/// The signature file digests the whole manifest and each of its sections.
+fn build_v1_signature_file(manifest: &[u8], sections: &[ManifestSection]) -> Vec<u8> {
+    let mut signature_file = vec![];
+    write_manifest_attribute(&mut signature_file, "Signature-Version", "1.0");
+    write_manifest_attribute(&mut signature_file, "Created-By", V1_CREATED_BY);
+    write_manifest_attribute(
+        &mut signature_file,
+        "SHA-256-Digest-Manifest",
+        &sha256_base64(manifest),
+    );
+    write_manifest_attribute(&mut signature_file, "X-Android-APK-Signed", "2, 3");
+    signature_file.extend_from_slice(b"\r\n");
+
+    for (name, section) in sections {
+        signature_file.extend(manifest_entry_section(name, &sha256_base64(section)));
+    }
+
+    signature_file
+}
+
+fn der_length(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        return vec![len as u8];
+    }
+
+    let bytes = (len as u64)
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|byte| *byte == 0)
+        .collect::<Vec<u8>>();
+    let mut out = vec![0x80 | bytes.len() as u8];
+    out.extend(bytes);
+    out
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    out.extend(der_length(content.len()));
+    out.extend_from_slice(content);
+    out
+}
+
+fn der_algorithm(oid: &[u8]) -> Vec<u8> {
+    der(0x30, &[der(0x06, oid), vec![0x05, 0x00]].concat())
+}
+
+/// PKCS#7 SignedData with a detached signature over the signature file.
+fn build_v1_signature_block(signature_file: &[u8], signing_key: &ApkSigningKey) -> Result<Vec<u8>> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(&signing_key.certificates[0])
+        .map_err(|e| anyhow::anyhow!("bad signing certificate: {}", e))?;
+    let tbs = &certificate.tbs_certificate;
+
+    let signature = rsa_sha256_sign(signature_file, signing_key);
+
+    let issuer_and_serial = der(
+        0x30,
+        &[tbs.issuer.as_raw().to_vec(), der(0x02, tbs.raw_serial())].concat(),
+    );
+    let signer_info = der(
+        0x30,
+        &[
+            der(0x02, &[1]),
+            issuer_and_serial,
+            der_algorithm(OID_SHA256),
+            der_algorithm(OID_RSA_ENCRYPTION),
+            der(0x04, &signature),
+        ]
+        .concat(),
+    );
+
+    let signed_data = der(
+        0x30,
+        &[
+            der(0x02, &[1]),
+            der(0x31, &der_algorithm(OID_SHA256)),
+            der(0x30, &der(0x06, OID_DATA)),
+            der(0xa0, &signing_key.certificates.concat()),
+            der(0x31, &signer_info),
+        ]
+        .concat(),
+    );
+
+    Ok(der(
+        0x30,
+        &[der(0x06, OID_SIGNED_DATA), der(0xa0, &signed_data)].concat(),
+    ))
+}
+
+fn rsa_sha256_sign(data: &[u8], signing_key: &ApkSigningKey) -> Vec<u8> {
+    SigningKey::<Sha256>::new(signing_key.private_key.clone())
+        .sign(data)
+        .to_vec()
+}
+
+/// Chunked SHA-256 over the zip entries, central directory and EOCD.
+fn compute_content_digest(sections: &[&[u8]]) -> Vec<u8> {
+    let mut chunk_count: u32 = 0;
+    let mut chunk_digests = vec![];
+    for section in sections {
+        for chunk in section.chunks(CONTENT_DIGEST_CHUNK_SIZE) {
+            let mut hasher = Sha256::new();
+            hasher.update([0xa5]);
+            hasher.update((chunk.len() as u32).to_le_bytes());
+            hasher.update(chunk);
+            chunk_digests.extend(hasher.finalize());
+            chunk_count += 1;
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update([0x5a]);
+    hasher.update(chunk_count.to_le_bytes());
+    hasher.update(&chunk_digests);
+    hasher.finalize().to_vec()
+}
+
+fn length_prefixed(data: &[u8]) -> Vec<u8> {
+    let mut out = (data.len() as u32).to_le_bytes().to_vec();
+    out.extend_from_slice(data);
+    out
+}
+
+fn length_prefixed_sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    length_prefixed(
+        &items
+            .iter()
+            .flat_map(|item| length_prefixed(item))
+            .collect::<Vec<u8>>(),
+    )
+}
+
+fn signer_public_key(signing_key: &ApkSigningKey) -> Result<Vec<u8>> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(&signing_key.certificates[0])
+        .map_err(|e| anyhow::anyhow!("bad signing certificate: {}", e))?;
+    Ok(certificate.tbs_certificate.subject_pki.raw.to_vec())
+}
+
+fn signer_digests_and_certificates(digest: &[u8], signing_key: &ApkSigningKey) -> Vec<u8> {
+    let digest_item = [
+        SIGNATURE_RSA_PKCS1_V1_5_WITH_SHA256.to_le_bytes().to_vec(),
+        length_prefixed(digest),
+    ]
+    .concat();
+
+    [
+        length_prefixed_sequence(&[digest_item]),
+        length_prefixed_sequence(&signing_key.certificates),
+    ]
+    .concat()
+}
+
+fn signer_signatures(signed_data: &[u8], signing_key: &ApkSigningKey) -> Vec<u8> {
+    let signature_item = [
+        SIGNATURE_RSA_PKCS1_V1_5_WITH_SHA256.to_le_bytes().to_vec(),
+        length_prefixed(&rsa_sha256_sign(signed_data, signing_key)),
+    ]
+    .concat();
+
+    length_prefixed_sequence(&[signature_item])
+}
+
+fn build_v2_block(digest: &[u8], signing_key: &ApkSigningKey) -> Result<Vec<u8>> {
+    let stripping_protection = [
+        STRIPPING_PROTECTION_ATTR_ID.to_le_bytes(),
+        SIGNATURE_SCHEME_V3_ID.to_le_bytes(),
+    ]
+    .concat();
+
+    let signed_data = [
+        signer_digests_and_certificates(digest, signing_key),
+        length_prefixed_sequence(&[stripping_protection]),
+    ]
+    .concat();
+
+    let signer = [
+        length_prefixed(&signed_data),
+        signer_signatures(&signed_data, signing_key),
+        length_prefixed(&signer_public_key(signing_key)?),
+    ]
+    .concat();
+
+    Ok(length_prefixed_sequence(&[signer]))
+}
+
+fn build_v3_block(digest: &[u8], signing_key: &ApkSigningKey) -> Result<Vec<u8>> {
+    let sdk_range = [
+        V3_MIN_SDK_VERSION.to_le_bytes(),
+        V3_MAX_SDK_VERSION.to_le_bytes(),
+    ]
+    .concat();
+
+    let signed_data = [
+        signer_digests_and_certificates(digest, signing_key),
+        sdk_range.clone(),
+        length_prefixed_sequence(&[]),
+    ]
+    .concat();
+
+    let signer = [
+        length_prefixed(&signed_data),
+        sdk_range,
+        signer_signatures(&signed_data, signing_key),
+        length_prefixed(&signer_public_key(signing_key)?),
+    ]
+    .concat();
+
+    Ok(length_prefixed_sequence(&[signer]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        apk_signing_block::find_apk_signing_block,
+        constants::{KEYSTORE_ALIAS_NAME, KEYSTORE_BYTES, KEYSTORE_PWD},
+        test_util::{build_zip, TestFolder},
+    };
+    use rsa::{pkcs1v15::VerifyingKey, signature::Verifier};
+
+    /// a channel pair of another tool, kept by the signer
+    const OTHER_BLOCK_ID: u32 = 0x71777777;
+
+    fn bundled_sign_config(folder: &TestFolder) -> SignConfig {
+        SignConfig {
+            keystore_path: folder
+                .write("key.keystore", KEYSTORE_BYTES)
+                .to_string_lossy()
+                .to_string(),
+            store_password: KEYSTORE_PWD.to_string(),
+            key_alias: KEYSTORE_ALIAS_NAME.to_string(),
+            key_password: KEYSTORE_PWD.to_string(),
+        }
+    }
+
+    fn manifest_value<'a>(section: &'a str, name: &str) -> &'a str {
+        section
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            .unwrap_or_else(|| panic!("no {} in {}", name, section))
+    }
+
+    fn read_u32_at(data: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn signs_v1_v2_v3_and_aligns() {
+        let folder = TestFolder::new("sign".as_ref());
+        let signing_key = load_signing_key(&bundled_sign_config(&folder)).unwrap();
+
+        let files: &[(&str, &[u8], bool)] = &[
+            ("AndroidManifest.xml", b"<manifest/>", false),
+            ("resources.arsc", b"arsc", true),
+            ("res/raw/odd-name.txt", b"odd", true),
+            ("lib/arm64-v8a/libdemo.so", b"\x7fELF", true),
+            ("META-INF/OLD.SF", b"stale", false),
+        ];
+        let apk = build_zip(files, &[(OTHER_BLOCK_ID, b"channel".to_vec())]);
+        let signed = sign_apk_data(&apk, &signing_key).unwrap();
+
+        let eocd = find_eocd(&signed).unwrap();
+        let entries = read_central_directory(&signed, &eocd).unwrap();
+        let names = entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            names[..3],
+            [
+                V1_MANIFEST_NAME,
+                V1_SIGNATURE_FILE_NAME,
+                V1_SIGNATURE_BLOCK_NAME
+            ]
+        );
+        assert!(!names.contains(&"META-INF/OLD.SF"));
+
+        // zipalign
+        for entry in &entries {
+            assert_eq!(
+                read_entry_data(&signed, entry).unwrap().len(),
+                entry.uncompressed_size
+            );
+            if entry.method == METHOD_STORED {
+                let alignment = if entry.name.ends_with(".so") { 4096 } else { 4 };
+                assert_eq!(entry.data_offset % alignment, 0, "{}", entry.name);
+            }
+        }
+
+        // v1: the manifest digests every entry, the signature file the manifest
+        let read = |name: &str| {
+            let entry = entries.iter().find(|entry| entry.name == name).unwrap();
+            String::from_utf8(read_entry_data(&signed, entry).unwrap()).unwrap()
+        };
+        let manifest = read(V1_MANIFEST_NAME);
+        let signature_file = read(V1_SIGNATURE_FILE_NAME);
+        assert_eq!(
+            manifest_value(&signature_file, "SHA-256-Digest-Manifest"),
+            sha256_base64(manifest.as_bytes())
+        );
+        for (name, content, _) in &files[..4] {
+            let section_start = manifest.find(&format!("Name: {}\r\n", name)).unwrap();
+            let section_len = manifest[section_start..].find("\r\n\r\n").unwrap() + 4;
+            let section = &manifest[section_start..section_start + section_len];
+            assert_eq!(
+                manifest_value(section, "SHA-256-Digest"),
+                sha256_base64(content)
+            );
+
+            let signed_section = signature_file
+                .split("\r\n\r\n")
+                .find(|signed_section| signed_section.contains(&format!("Name: {}\r\n", name)))
+                .unwrap();
+            assert_eq!(
+                manifest_value(signed_section, "SHA-256-Digest"),
+                sha256_base64(section.as_bytes())
+            );
+        }
+
+        // v2 and v3, the pair of the other tool is kept
+        let block = find_apk_signing_block(&signed, &eocd).unwrap().unwrap();
+        assert_eq!(block.get(OTHER_BLOCK_ID), Some(b"channel".as_slice()));
+        assert!(block.get(APK_SIGNATURE_SCHEME_V3_BLOCK_ID).is_some());
+        let v2 = block.get(APK_SIGNATURE_SCHEME_V2_BLOCK_ID).unwrap();
+
+        // signers > signer > signed data > digests > digest
+        let signed_data_len = read_u32_at(v2, 8);
+        let signed_data = &v2[12..12 + signed_data_len];
+        assert_eq!(
+            read_u32_at(signed_data, 8),
+            SIGNATURE_RSA_PKCS1_V1_5_WITH_SHA256 as usize
+        );
+        let digest = &signed_data[16..16 + read_u32_at(signed_data, 12)];
+
+        let mut eocd_data = signed[eocd.offset..].to_vec();
+        eocd_data[16..20].copy_from_slice(&(block.offset as u32).to_le_bytes());
+        assert_eq!(
+            digest,
+            compute_content_digest(&[
+                &signed[..block.offset],
+                &signed[eocd.cd_offset..eocd.offset],
+                &eocd_data,
+            ])
+        );
+
+        // signatures > signature > algorithm, signature bytes
+        let signatures = &v2[12 + signed_data_len..];
+        let signature = &signatures[16..16 + read_u32_at(signatures, 12)];
+        VerifyingKey::<Sha256>::new(signing_key.private_key.to_public_key())
+            .verify(
+                signed_data,
+                &rsa::pkcs1v15::Signature::try_from(signature).unwrap(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn key_password_must_match_store_password() {
+        let folder = TestFolder::new("key-password".as_ref());
+        let mut sign_config = bundled_sign_config(&folder);
+
+        sign_config.key_password = String::new();
+        assert!(load_signing_key(&sign_config).is_ok());
+
+        sign_config.key_password = "another".to_string();
+        let error = load_signing_key(&sign_config).err().unwrap();
+        assert_eq!(RepackError::from(error).code(), "SignFailed");
+    }
+}
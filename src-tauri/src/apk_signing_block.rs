@@ -0,0 +1,168 @@
+use anyhow::Result;
+
+use crate::zip_util::{read_u32, read_u64, ZipEocd};
+
+pub const APK_SIG_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+
+pub const APK_SIGNATURE_SCHEME_V2_BLOCK_ID: u32 = 0x7109871a;
+pub const APK_SIGNATURE_SCHEME_V3_BLOCK_ID: u32 = 0xf05368c0;
+pub const APK_SIGNATURE_SCHEME_V31_BLOCK_ID: u32 = 0x1b93ad61;
+pub const VERITY_PADDING_BLOCK_ID: u32 = 0x42726577;
+
+/// size field (8) + magic (16)
+const APK_SIG_BLOCK_FOOTER_SIZE: usize = 24;
+
+#[derive(Debug, Clone)]
+pub struct ApkSigningBlock {
+    /// offset of the block in the apk, the central directory follows it
+    pub offset: usize,
+    pub pairs: Vec<(u32, Vec<u8>)>,
+}
+
+impl ApkSigningBlock {
+    pub fn get(&self, id: u32) -> Option<&[u8]> {
+        self.pairs
+            .iter()
+            .find(|(pair_id, _)| *pair_id == id)
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+/// Locates the APK Signing Block right before the central directory.
+pub fn find_apk_signing_block(data: &[u8], eocd: &ZipEocd) -> Result<Option<ApkSigningBlock>> {
+    let cd_offset = eocd.cd_offset;
+    if cd_offset < APK_SIG_BLOCK_FOOTER_SIZE + 8 {
+        return Ok(None);
+    }
+
+    if &data[cd_offset - 16..cd_offset] != APK_SIG_BLOCK_MAGIC {
+        return Ok(None);
+    }
+
+    let block_size = read_u64(data, cd_offset - APK_SIG_BLOCK_FOOTER_SIZE)? as usize;
+    if block_size < APK_SIG_BLOCK_FOOTER_SIZE || block_size + 8 > cd_offset {
+        return Err(anyhow::anyhow!("bad apk signing block size {}", block_size));
+    }
+
+    let offset = cd_offset - block_size - 8;
+    if read_u64(data, offset)? as usize != block_size {
+        return Err(anyhow::anyhow!("apk signing block sizes do not match"));
+    }
+
+    let pairs_end = cd_offset - APK_SIG_BLOCK_FOOTER_SIZE;
+    let mut pairs = vec![];
+    let mut pos = offset + 8;
+    while pos < pairs_end {
+        let pair_len = read_u64(data, pos)? as usize;
+        let value_start = pos + 8 + 4;
+        let next = pos + 8 + pair_len;
+        if pair_len < 4 || next > pairs_end {
+            return Err(anyhow::anyhow!("bad apk signing block pair at {}", pos));
+        }
+
+        let id = read_u32(data, pos + 8)?;
+        pairs.push((id, data[value_start..next].to_vec()));
+        pos = next;
+    }
+
+    Ok(Some(ApkSigningBlock { offset, pairs }))
+}
+
+pub fn build_apk_signing_block(pairs: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let pairs_size = pairs
+        .iter()
+        .map(|(_, value)| 8 + 4 + value.len())
+        .sum::<usize>();
+    let block_size = (pairs_size + APK_SIG_BLOCK_FOOTER_SIZE) as u64;
+
+    let mut block = Vec::with_capacity(block_size as usize + 8);
+    block.extend_from_slice(&block_size.to_le_bytes());
+    for (id, value) in pairs {
+        block.extend_from_slice(&((4 + value.len()) as u64).to_le_bytes());
+        block.extend_from_slice(&id.to_le_bytes());
+        block.extend_from_slice(value);
+    }
+    block.extend_from_slice(&block_size.to_le_bytes());
+    block.extend_from_slice(APK_SIG_BLOCK_MAGIC);
+
+    block
+}
+
+/// Rebuilds the apk with a new signing block holding `pairs`, the central
+/// directory offset in the EOCD is moved accordingly.
+pub fn replace_apk_signing_block(
+    data: &[u8],
+    eocd: &ZipEocd,
+    block: &ApkSigningBlock,
+    pairs: &[(u32, Vec<u8>)],
+) -> Result<Vec<u8>> {
+    let new_block = build_apk_signing_block(pairs);
+    let new_cd_offset = block.offset + new_block.len();
+    if new_cd_offset > u32::MAX as usize {
+        return Err(anyhow::anyhow!("apk too large"));
+    }
+
+    let mut out = Vec::with_capacity(data.len() + new_block.len());
+    out.extend_from_slice(&data[..block.offset]);
+    out.extend_from_slice(&new_block);
+    out.extend_from_slice(&data[eocd.cd_offset..eocd.offset]);
+
+    let eocd_start = out.len();
+    out.extend_from_slice(&data[eocd.offset..]);
+    out[eocd_start + 16..eocd_start + 20].copy_from_slice(&(new_cd_offset as u32).to_le_bytes());
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::build_zip,
+        zip_util::{find_eocd, read_central_directory, read_entry_data},
+    };
+
+    #[test]
+    fn signing_block_round_trip() {
+        let files: &[(&str, &[u8], bool)] = &[("classes.dex", b"dex", false)];
+        let pairs = vec![
+            (APK_SIGNATURE_SCHEME_V2_BLOCK_ID, b"v2".to_vec()),
+            (0x71777777, vec![]),
+        ];
+
+        let apk = build_zip(files, &[]);
+        assert!(find_apk_signing_block(&apk, &find_eocd(&apk).unwrap())
+            .unwrap()
+            .is_none());
+
+        let apk = build_zip(files, &pairs);
+        let eocd = find_eocd(&apk).unwrap();
+        let block = find_apk_signing_block(&apk, &eocd).unwrap().unwrap();
+        assert_eq!(block.pairs, pairs);
+        assert_eq!(block.get(0x71777777), Some([].as_slice()));
+
+        // a longer block moves the central directory
+        let new_pairs = vec![(APK_SIGNATURE_SCHEME_V3_BLOCK_ID, vec![7; 100])];
+        let replaced = replace_apk_signing_block(&apk, &eocd, &block, &new_pairs).unwrap();
+        let eocd = find_eocd(&replaced).unwrap();
+        assert_eq!(
+            find_apk_signing_block(&replaced, &eocd)
+                .unwrap()
+                .unwrap()
+                .pairs,
+            new_pairs
+        );
+        let entries = read_central_directory(&replaced, &eocd).unwrap();
+        assert_eq!(read_entry_data(&replaced, &entries[0]).unwrap(), b"dex");
+    }
+
+    #[test]
+    fn bad_signing_block_is_an_error() {
+        let mut apk = build_zip(&[("classes.dex", b"dex", true)], &[(1, b"x".to_vec())]);
+        let eocd = find_eocd(&apk).unwrap();
+        let block = find_apk_signing_block(&apk, &eocd).unwrap().unwrap();
+        // the leading size no longer matches the trailing one
+        apk[block.offset] ^= 1;
+        assert!(find_apk_signing_block(&apk, &eocd).is_err());
+    }
+}
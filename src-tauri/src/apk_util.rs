@@ -1,192 +1,175 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::BTreeMap,
     path::{Path, PathBuf},
 };
 
 use crate::{
-    constants::{
-        get_apksigner_jar_path, get_apktool_jar_path, get_cache_folder, get_java_exe,
-        get_key_store_path, get_vasdolly_jar_path, KEYSTORE_ALIAS_NAME, KEYSTORE_PWD,
-    },
+    apk_signer::{load_signing_key, sign_apk_file},
+    constants::get_apktool_jar_path,
+    decode_cache::{restore_decode, store_decode, DecodeKey},
+    error::{RepackError, RepackResultExt},
+    icon_util::{get_apk_icon_preview, get_apk_round_icon_preview},
+    java_util::get_java_exe,
+    job::{job_workspace, report_progress, JobProgress, JobStage, StageStatus},
+    meta_data_util::{edit_meta_data, list_meta_data, MetaData, MetaDataEdit},
     run_command::run_command,
-    xml_util::{xml_exchange_value, xml_find_value},
+    settings::get_settings,
+    sign_config::SignConfig,
+    strings_util::{read_string_resource, write_string_resource},
+    xml_util::{xml_exchange_value, xml_find_value, xml_map_values},
+    yml_util::{yml_exchange_value, yml_find_value, yml_quote},
 };
 
-pub async fn unpack_apk(apk_file_path: &str) -> Result<PathBuf> {
-    log::info!("unpack_apk");
+const APKTOOL_YML: &str = "apktool.yml";
 
-    let java_exe = get_java_exe()?;
+/// values folder without qualifiers, the fallback of every locale
+const DEFAULT_VALUES_DIR: &str = "values";
+/// the largest version code google play accepts
+const MAX_VERSION_CODE: u32 = 2100000000;
+
+/// `-Xms` and `-Xmx` of apktool from the settings.
+fn jvm_memory_args() -> [String; 2] {
+    let settings = get_settings();
+    [
+        format!("-Xms{}m", settings.jvm_initial_memory_mb),
+        format!("-Xmx{}m", settings.jvm_max_memory_mb),
+    ]
+}
+
+/// Tools get paths as utf-8 arguments, other paths fail instead of being
+/// mangled.
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow::anyhow!("{} is not a UTF-8 path", path.to_string_lossy()))
+}
+
+/// Decodes into `original` in the workspace of the job, or copies the
+/// decode of the same apk from the decode cache.
+pub async fn unpack_apk(job_id: &str, apk_file_path: &str) -> Result<PathBuf> {
+    log::info!("unpack_apk");
     log::info!("apk_file_path: {}", apk_file_path);
 
-    let cache_folder = get_cache_folder();
-    let original = cache_folder.join("original");
+    let original = job_workspace(job_id)?.join("original");
     if original.exists() {
         std::fs::remove_dir_all(&original)?;
     }
 
-    // get exe folder
+    let decode_key = DecodeKey::new(Path::new(apk_file_path)).or_repack_error(|detail| {
+        RepackError::DecodeFailed {
+            apk: apk_file_path.to_string(),
+            detail,
+        }
+    })?;
+    if restore_decode(&decode_key, &original) {
+        report_progress(JobProgress {
+            job_id: job_id.to_string(),
+            stage: JobStage::Decode,
+            status: StageStatus::Running,
+            message: "decoded apk found in the cache".to_string(),
+            elapsed_ms: None,
+        });
+        return Ok(original);
+    }
+
+    // java is only needed to decode, a cached decode works without it
+    let java_exe = get_java_exe()?;
     let apktool_jar_path = get_apktool_jar_path();
+    let [initial_memory, max_memory] = jvm_memory_args();
 
     // exec command
-    let _ = run_command(
-        &java_exe,
-        &[
-            "-jar",
-            "-Xms512m",
-            "-Xmx1024m",
-            apktool_jar_path.to_str().unwrap(),
-            "--only-main-classes",
-            "d",
-            "-b",
-            "-f",
-            apk_file_path,
-            "-o",
-            original.to_str().unwrap(),
-        ],
-    )
-    .await?;
+    let decode = async {
+        run_command(
+            job_id,
+            JobStage::Decode,
+            &java_exe,
+            &[
+                "-jar",
+                &initial_memory,
+                &max_memory,
+                path_str(&apktool_jar_path)?,
+                "--only-main-classes",
+                "d",
+                "-b",
+                "-f",
+                apk_file_path,
+                "-o",
+                path_str(&original)?,
+            ],
+        )
+        .await
+    };
+    let _ = decode
+        .await
+        .or_repack_error(|detail| RepackError::DecodeFailed {
+            apk: apk_file_path.to_string(),
+            detail,
+        })?;
+    store_decode(&decode_key, job_id, apk_file_path, &original);
 
     Ok(original)
 }
 
-pub async fn pack_apk(apk_folder: &Path) -> Result<PathBuf> {
+/// Builds next to the decoded folder, in the same workspace.
+pub async fn pack_apk(job_id: &str, apk_folder: &Path) -> Result<PathBuf> {
     log::info!("pack_apk");
+    log::info!("apk_folder: {}", apk_folder.to_string_lossy());
 
-    let java_exe = get_java_exe()?;
-    log::info!("apk_folder: {}", apk_folder.to_str().unwrap());
-
-    let cache_folder = get_cache_folder();
-    let apk_base_file_name = apk_folder.file_name().unwrap().to_str().unwrap();
-    let original_apk = cache_folder.join(format!("{}.repacked.apk", apk_base_file_name));
+    let apk_base_file_name = apk_folder
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("bad apk folder {}", apk_folder.to_string_lossy()))?
+        .to_string_lossy();
+    let original_apk = apk_folder.with_file_name(format!("{}.repacked.apk", apk_base_file_name));
     if original_apk.exists() {
         std::fs::remove_file(&original_apk)?;
     }
 
+    // a workspace apktool can not be given fails before java is looked up
+    let build_failed = |detail| RepackError::BuildFailed { detail };
+    let apk_folder_arg = path_str(apk_folder).or_repack_error(build_failed)?;
+    let original_apk_arg = path_str(&original_apk).or_repack_error(build_failed)?;
+    let java_exe = get_java_exe()?;
+
     // get exe folder
     let apktool_jar_path = get_apktool_jar_path();
+    let [initial_memory, max_memory] = jvm_memory_args();
 
     // exec command
-    let _ = run_command(
-        &java_exe,
-        &[
-            "-jar",
-            "-Xms512m",
-            "-Xmx1024m",
-            apktool_jar_path.to_str().unwrap(),
-            "--only-main-classes",
-            "b",
-            "-f",
-            apk_folder.to_str().unwrap(),
-            "-o",
-            original_apk.to_str().unwrap(),
-        ],
-    )
-    .await?;
+    let build = async {
+        run_command(
+            job_id,
+            JobStage::Build,
+            &java_exe,
+            &[
+                "-jar",
+                &initial_memory,
+                &max_memory,
+                path_str(&apktool_jar_path)?,
+                "--only-main-classes",
+                "b",
+                "-f",
+                apk_folder_arg,
+                "-o",
+                original_apk_arg,
+            ],
+        )
+        .await
+    };
+    let _ = build
+        .await
+        .or_repack_error(|detail| RepackError::BuildFailed { detail })?;
 
     Ok(original_apk)
 }
 
-pub async fn sign_apk(apk_file_path: &Path) -> Result<()> {
+pub async fn sign_apk(apk_file_path: &Path, sign_config: &SignConfig) -> Result<()> {
     log::info!("sign_apk");
 
-    // sign apk
-    let key_store_path = get_key_store_path();
-    let java_exe = get_java_exe()?;
-    let apksigner_jar_path = get_apksigner_jar_path();
-    let _ = run_command(
-        &java_exe,
-        &[
-            "-jar",
-            "-Xms512m",
-            "-Xmx1024m",
-            apksigner_jar_path.to_str().unwrap(),
-            "--allowResign",
-            "--overwrite",
-            "-ks",
-            key_store_path.to_str().unwrap(),
-            "--ksPass",
-            KEYSTORE_PWD,
-            "--ksAlias",
-            KEYSTORE_ALIAS_NAME,
-            "--ksKeyPass",
-            KEYSTORE_PWD,
-            "-a",
-            apk_file_path.to_str().unwrap(),
-        ],
-    )
-    .await?;
-
-    Ok(())
-}
-
-pub async fn get_apk_vasdolly_channel(apk_file: &str) -> Result<String> {
-    log::info!("get_apk_vasdolly_channel");
-
-    let java_exe = get_java_exe()?;
-    let vasdolly_jar_path = get_vasdolly_jar_path();
-
-    let output = run_command(
-        &java_exe,
-        &[
-            "-jar",
-            "-Xms512m",
-            "-Xmx1024m",
-            vasdolly_jar_path.to_str().unwrap(),
-            "get",
-            "-c",
-            apk_file,
-        ],
-    )
-    .await?;
-
-    let channel_index = output.rfind("Channel: ").unwrap();
-    let channel = String::from(&output[channel_index + 9..])
-        .trim()
-        .to_string();
-
-    Ok(channel)
-}
-
-pub async fn set_apk_vasdolly_channel(apk_file: &Path, channel: &str) -> Result<()> {
-    log::info!("set_apk_vasdolly_channel");
-
-    let apk_file_path = apk_file.to_str().unwrap();
-    let java_exe = get_java_exe()?;
-    let vasdolly_jar_path = get_vasdolly_jar_path();
-
-    // remove channel first
-    let _ = run_command(
-        &java_exe,
-        &[
-            "-jar",
-            "-Xms512m",
-            "-Xmx1024m",
-            vasdolly_jar_path.to_str().unwrap(),
-            "remove",
-            "-c",
-            apk_file_path,
-        ],
-    )
-    .await?;
-
-    let _ = run_command(
-        &java_exe,
-        &[
-            "-jar",
-            "-Xms512m",
-            "-Xmx1024m",
-            vasdolly_jar_path.to_str().unwrap(),
-            "put",
-            "-c",
-            channel,
-            "-f",
-            apk_file_path,
-            apk_file_path,
-        ],
-    )
-    .await?;
+    let signing_key = load_signing_key(sign_config)
+        .or_repack_error(|detail| RepackError::SignFailed { detail })?;
+    sign_apk_file(apk_file_path, &signing_key)
+        .or_repack_error(|detail| RepackError::SignFailed { detail })?;
 
     Ok(())
 }
@@ -195,193 +178,203 @@ pub fn get_apk_package_name(apk_folder: &Path) -> Result<String> {
     let manifest_file_path = apk_folder.join("AndroidManifest.xml");
     let manifest_file_content = std::fs::read_to_string(manifest_file_path)?;
 
-    let package_name = xml_find_value(&manifest_file_content, &["manifest"], "package")?;
+    let package_name = xml_find_value(&manifest_file_content, "manifest", "package")
+        .map_err(|e| anyhow::anyhow!("bad AndroidManifest.xml: {}", e))?;
+    let package_name = package_name
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("error find package name"))?;
 
-    if package_name.is_empty() {
-        return Err(anyhow::anyhow!("error find package name"));
-    }
-
-    Ok(package_name[0].to_owned())
+    Ok(package_name.to_owned())
 }
 
-pub fn get_apk_display_name(apk_folder: &Path) -> Result<String> {
+/// `@string/name` of the application label, `None` for a literal label.
+fn get_apk_label_string_name(apk_folder: &Path) -> Result<Option<String>> {
     let manifest_file_path = apk_folder.join("AndroidManifest.xml");
     let manifest_file_content = std::fs::read_to_string(manifest_file_path)?;
 
-    let display_name = xml_find_value(&manifest_file_content, &["application"], "android:label")?;
-    if display_name.is_empty() {
-        return Err(anyhow::anyhow!("error find display name"));
-    }
-    let mut display_name = display_name[0].to_owned();
-
-    if display_name.starts_with("@string/") {
-        let string_name = display_name[8..].to_string();
-        let string_name = string_name.replace('@', "");
-        let string_name = string_name.replace('/', "_");
-        log::info!("string_name: {}", string_name);
-
-        let mut values_dirs = vec![];
-        let res_dir = std::fs::read_dir(apk_folder.join("res")).unwrap();
-        for entry in res_dir {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            let folder_name = path.file_name().unwrap().to_string_lossy().to_string();
-            // log::info!("path: {} {}", path.to_str().unwrap(), folder_name);
-            if folder_name.starts_with("values") && path.is_dir() {
-                values_dirs.push(path);
-            }
-        }
-
-        if values_dirs.is_empty() {
-            return Err(anyhow::anyhow!("error find values dirs"));
-        }
+    let label = xml_find_value(
+        &manifest_file_content,
+        "manifest/application",
+        "android:label",
+    )?;
+    let label = label
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("error find display name"))?;
 
-        let mut str_display_name = "".to_string();
+    Ok(label.strip_prefix("@string/").map(str::to_string))
+}
 
-        let re_str = format!(r##"<string name="{}">(.+)</string>"##, string_name);
-        let re = regex::Regex::new(re_str.as_str()).unwrap();
+/// `values`, `values-zh-rCN`, ... folders of the decoded resources.
+fn get_values_dirs(apk_folder: &Path) -> Result<Vec<PathBuf>> {
+    let mut values_dirs = vec![];
+    for entry in std::fs::read_dir(apk_folder.join("res"))? {
+        let path = entry?.path();
+        let folder_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if path.is_dir() && (folder_name == "values" || folder_name.starts_with("values-")) {
+            values_dirs.push(path);
+        }
+    }
+    values_dirs.sort();
 
-        for values_dir in values_dirs {
-            let string_file_path = values_dir.join("strings.xml");
-            if !string_file_path.exists() {
-                continue;
-            }
+    Ok(values_dirs)
+}
 
-            let string_file_content = std::fs::read_to_string(&string_file_path);
-            if string_file_content.is_err() {
-                continue;
-            }
+/// The label per values folder, e.g. `values` => `Demo`, `values-ja` => `デモ`.
+/// A literal label is returned under `values`.
+pub fn get_apk_display_names(apk_folder: &Path) -> Result<BTreeMap<String, String>> {
+    let mut display_names = BTreeMap::new();
+
+    let Some(string_name) = get_apk_label_string_name(apk_folder)? else {
+        let manifest_file_path = apk_folder.join("AndroidManifest.xml");
+        let manifest_file_content = std::fs::read_to_string(manifest_file_path)?;
+        let label = xml_find_value(
+            &manifest_file_content,
+            "manifest/application",
+            "android:label",
+        )?;
+        display_names.insert(DEFAULT_VALUES_DIR.to_string(), label.join(""));
+        return Ok(display_names);
+    };
+    log::info!("string_name: {}", string_name);
+
+    for values_dir in get_values_dirs(apk_folder)? {
+        if let Some(name) = read_string_resource(&values_dir, &string_name)? {
+            let folder_name = values_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            log::info!("found name {} {}", folder_name, name);
+            display_names.insert(folder_name, name);
+        }
+    }
 
-            log::info!("string_file_path: {}", string_file_path.to_str().unwrap());
+    if display_names.is_empty() {
+        return Err(anyhow::anyhow!("error find display name {}", string_name));
+    }
 
-            let string_file_content = string_file_content.unwrap();
-            for (_, [name]) in re.captures_iter(&string_file_content).map(|c| c.extract()) {
-                log::info!("found name {}", name);
-                str_display_name.push_str(name);
-                str_display_name.push(',');
-            }
-        }
+    Ok(display_names)
+}
 
-        if str_display_name.is_empty() {
-            return Ok("error find display name".to_string());
-        }
+/// The default label, or the first localized one.
+pub fn get_apk_display_name(apk_folder: &Path) -> Result<String> {
+    let mut display_names = get_apk_display_names(apk_folder)?;
 
-        display_name = str_display_name.trim_end_matches(',').to_string();
-        log::info!("display_name: {}", display_name);
-    }
+    let display_name = match display_names.remove(DEFAULT_VALUES_DIR) {
+        Some(display_name) => display_name,
+        None => display_names
+            .into_values()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("error find display name"))?,
+    };
+    log::info!("display_name: {}", display_name);
 
     Ok(display_name)
 }
 
-pub fn get_apk_icon_names(apk_folder: &Path) -> Result<Vec<String>> {
-    let mut name_list: Vec<String> = vec![];
-
-    // read manifest android:icon
-    let manifest_file_path = apk_folder.join("AndroidManifest.xml");
-    let manifest_file_content = std::fs::read_to_string(manifest_file_path)?;
+/// `versionInfo` and `sdkInfo` of apktool.yml, empty when missing
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ApkVersionInfo {
+    pub version_code: String,
+    pub version_name: String,
+    pub min_sdk_version: String,
+    pub target_sdk_version: String,
+}
 
-    let mut manifest_icon_names =
-        xml_find_value(&manifest_file_content, &["application"], "android:icon")?
-            .iter()
-            .map(|icon_name| {
-                log::info!("application android:icon: {}", icon_name);
-                let icon_name_index = icon_name.rfind('/').unwrap();
-                let icon_name = format!("{}.png", &icon_name[icon_name_index + 1..]);
-                icon_name
-            })
-            .collect::<Vec<String>>();
-
-    name_list.append(&mut manifest_icon_names);
-
-    // read res/mipmap-anydpi-v26 foreground android:drawable
-    let v26_folder = apk_folder.join("res").join("mipmap-anydpi-v26");
-    if v26_folder.exists() {
-        let v26_dir = std::fs::read_dir(v26_folder)?;
-        for entry in v26_dir {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                let file_name = path.file_name().unwrap().to_str().unwrap();
-                if file_name.starts_with("ic_launcher") {
-                    let xml_content = std::fs::read_to_string(&path)?;
-
-                    let mut foreground_icon_names =
-                        xml_find_value(&xml_content, &["foreground"], "android:drawable")?
-                            .iter()
-                            .map(|icon_name| {
-                                log::info!("foreground android:drawable: {}", icon_name);
-                                let icon_name_index = icon_name.rfind('/').unwrap();
-                                let icon_name =
-                                    format!("{}.png", &icon_name[icon_name_index + 1..]);
-                                icon_name
-                            })
-                            .collect::<Vec<String>>();
-
-                    name_list.append(&mut foreground_icon_names);
-                }
-            }
-        }
-    }
+pub fn get_apk_version_info(apk_folder: &Path) -> Result<ApkVersionInfo> {
+    let yml_content = std::fs::read_to_string(apk_folder.join(APKTOOL_YML))?;
+    let find = |key_path: &[&str]| yml_find_value(&yml_content, key_path).unwrap_or_default();
 
-    // unique
-    Ok(name_list
-        .into_iter()
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .collect())
+    Ok(ApkVersionInfo {
+        version_code: find(&["versionInfo", "versionCode"]),
+        version_name: find(&["versionInfo", "versionName"]),
+        min_sdk_version: find(&["sdkInfo", "minSdkVersion"]),
+        target_sdk_version: find(&["sdkInfo", "targetSdkVersion"]),
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApkInfo {
     pub package_name: String,
     pub display_name: String,
+    /// label per values folder, e.g. `values-ja`
+    pub display_names: BTreeMap<String, String>,
     pub icon_path: String,
+    /// empty when the app has no separate round icon
+    pub round_icon_path: String,
+    pub version_code: String,
+    pub version_name: String,
+    pub min_sdk_version: String,
+    pub target_sdk_version: String,
+    pub meta_data: Vec<MetaData>,
 }
 
 pub async fn get_apk_info(apk_folder: &Path) -> Result<ApkInfo> {
     let package_name = get_apk_package_name(apk_folder)?;
     let display_name = get_apk_display_name(apk_folder)?;
-
-    let icon_names = get_apk_icon_names(apk_folder)?;
-
-    // list dir of res
-    let res_path = apk_folder.join("res");
-    let res_dir = std::fs::read_dir(res_path).unwrap();
-    let mut max_w: u32 = 0;
-    let mut max_icon_path = "".to_string();
-    for entry in res_dir {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_dir() {
-            for icon_name in &icon_names {
-                let icon_path = path.join(icon_name);
-                log::info!("icon_path: {}", icon_path.to_str().unwrap());
-                if icon_path.exists() {
-                    let old_image = image::open(&icon_path).unwrap();
-                    let w = old_image.width();
-                    if w > max_w {
-                        max_w = w;
-                        max_icon_path = icon_path.to_str().unwrap().to_string();
-                    }
-                }
-            }
-        }
-    }
+    let display_names = get_apk_display_names(apk_folder)?;
+    let version_info = get_apk_version_info(apk_folder)?;
+
+    let icon_path = match get_apk_icon_preview(apk_folder)? {
+        Some(icon_path) => icon_path.to_string_lossy().to_string(),
+        None => String::new(),
+    };
+    log::info!("icon_path: {}", icon_path);
+    let round_icon_path = match get_apk_round_icon_preview(apk_folder)? {
+        Some(round_icon_path) => round_icon_path.to_string_lossy().to_string(),
+        None => String::new(),
+    };
+    log::info!("round_icon_path: {}", round_icon_path);
 
     Ok(ApkInfo {
         package_name,
         display_name,
-        icon_path: max_icon_path,
+        display_names,
+        icon_path,
+        round_icon_path,
+        version_code: version_info.version_code,
+        version_name: version_info.version_name,
+        min_sdk_version: version_info.min_sdk_version,
+        target_sdk_version: version_info.target_sdk_version,
+        meta_data: get_apk_meta_data(apk_folder)?,
     })
 }
 
+/// `com.example.app`, at least two segments that start with a letter and
+/// hold letters, digits and `_`.
+pub fn is_valid_package_name(name: &str) -> bool {
+    let segments = name.split('.').collect::<Vec<&str>>();
+    segments.len() >= 2
+        && segments.iter().all(|segment| {
+            segment.starts_with(|c: char| c.is_ascii_alphabetic())
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+fn verify_package_name(name: &str) -> Result<()> {
+    if !is_valid_package_name(name) {
+        return Err(RepackError::InvalidPackageName {
+            name: name.to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
 pub fn exchange_apk_package_name(apk_folder: &Path, new_package_name: &str) -> Result<()> {
+    verify_package_name(new_package_name)?;
+
     let manifest_file_path = apk_folder.join("AndroidManifest.xml");
     let manifest_file_content = std::fs::read_to_string(&manifest_file_path)?;
 
     let result = xml_exchange_value(
         &manifest_file_content,
-        &["manifest"],
+        "manifest",
         "package",
         new_package_name,
     )?;
@@ -391,50 +384,593 @@ pub fn exchange_apk_package_name(apk_folder: &Path, new_package_name: &str) -> R
     Ok(())
 }
 
-pub fn exchange_apk_display_name(apk_folder: &Path, new_display_name: &str) -> Result<()> {
+/// attributes holding class names, resolved against the manifest package
+const CLASS_NAME_ATTRS: &[(&str, &str)] = &[
+    ("application", "android:name"),
+    ("application", "android:backupAgent"),
+    ("application", "android:manageSpaceActivity"),
+    ("application", "android:appComponentFactory"),
+    ("application", "android:zygotePreloadName"),
+    ("activity", "android:name"),
+    ("activity", "android:parentActivityName"),
+    ("activity-alias", "android:name"),
+    ("activity-alias", "android:targetActivity"),
+    ("activity-alias", "android:parentActivityName"),
+    ("service", "android:name"),
+    ("receiver", "android:name"),
+    ("provider", "android:name"),
+    ("instrumentation", "android:name"),
+];
+
+/// attributes that must be unique per installed package
+const PACKAGE_SCOPED_ATTRS: &[(&str, &str)] = &[
+    ("permission", "android:name"),
+    ("permission-group", "android:name"),
+    ("permission-tree", "android:name"),
+    ("uses-permission", "android:name"),
+    ("uses-permission-sdk-23", "android:name"),
+    ("permission", "android:permissionGroup"),
+    ("application", "android:taskAffinity"),
+    ("activity", "android:taskAffinity"),
+    ("application", "android:process"),
+    ("activity", "android:process"),
+    ("service", "android:process"),
+    ("receiver", "android:process"),
+    ("provider", "android:process"),
+];
+
+/// permissions required by a component or a path, on any element
+const PERMISSION_ATTRS: &[&str] = &[
+    "android:permission",
+    "android:readPermission",
+    "android:writePermission",
+];
+
+/// `old.pkg`, `old.pkg.X` and `old.pkg:X` are derived from the old package
+fn replace_package_prefix(
+    value: &str,
+    old_package_name: &str,
+    new_package_name: &str,
+) -> Option<String> {
+    let rest = value.strip_prefix(old_package_name)?;
+    if rest.is_empty() || rest.starts_with('.') || rest.starts_with(':') {
+        Some(format!("{}{}", new_package_name, rest))
+    } else {
+        None
+    }
+}
+
+/// `.Main` and `Main` are relative to the manifest package
+fn absolute_class_name(value: &str, old_package_name: &str) -> Option<String> {
+    if value.starts_with('.') {
+        Some(format!("{}{}", old_package_name, value))
+    } else if !value.is_empty() && !value.contains('.') && !value.starts_with('@') {
+        Some(format!("{}.{}", old_package_name, value))
+    } else {
+        None
+    }
+}
+
+/// Renames the package and every manifest attribute derived from it:
+/// provider authorities, permissions, task affinities and process names.
+/// Relative class names are made absolute so they still point at the
+/// original classes.
+pub fn rename_apk_package(apk_folder: &Path, new_package_name: &str) -> Result<()> {
+    log::info!("rename_apk_package: {}", new_package_name);
+    verify_package_name(new_package_name)?;
+
+    let old_package_name = get_apk_package_name(apk_folder)?;
+
+    let manifest_file_path = apk_folder.join("AndroidManifest.xml");
+    let manifest_file_content = std::fs::read_to_string(&manifest_file_path)?;
+
+    let result = xml_map_values(&manifest_file_content, |elem_name, attr_key, value| {
+        let matches = |attrs: &[(&str, &str)]| {
+            attrs
+                .iter()
+                .any(|(elem, attr)| *elem == elem_name && *attr == attr_key)
+        };
+
+        if elem_name == "manifest" && attr_key == "package" {
+            Some(new_package_name.to_string())
+        } else if matches(CLASS_NAME_ATTRS) {
+            absolute_class_name(value, &old_package_name)
+        } else if matches(PACKAGE_SCOPED_ATTRS) || PERMISSION_ATTRS.contains(&attr_key) {
+            replace_package_prefix(value, &old_package_name, new_package_name)
+        } else if attr_key == "android:authorities" {
+            let authorities = value
+                .split(';')
+                .map(|authority| {
+                    replace_package_prefix(authority, &old_package_name, new_package_name)
+                        .unwrap_or_else(|| authority.to_string())
+                })
+                .collect::<Vec<String>>()
+                .join(";");
+            (authorities != value).then_some(authorities)
+        } else {
+            None
+        }
+    })?;
+
+    std::fs::write(manifest_file_path, result)?;
+
+    Ok(())
+}
+
+/// Turns a literal label into `@string/app_label` so it can be localized,
+/// returns the string name.
+fn make_apk_label_string_resource(apk_folder: &Path) -> Result<String> {
+    if let Some(string_name) = get_apk_label_string_name(apk_folder)? {
+        return Ok(string_name);
+    }
+
+    let label = get_apk_display_name(apk_folder)?;
+    let default_values_dir = apk_folder.join("res").join(DEFAULT_VALUES_DIR);
+
+    let mut string_name = "app_label".to_string();
+    let mut index = 1;
+    while read_string_resource(&default_values_dir, &string_name)?.is_some() {
+        index += 1;
+        string_name = format!("app_label_{}", index);
+    }
+    write_string_resource(&default_values_dir, &string_name, &label)?;
+
     let manifest_file_path = apk_folder.join("AndroidManifest.xml");
     let manifest_file_content = std::fs::read_to_string(&manifest_file_path)?;
 
     let result = xml_exchange_value(
         &manifest_file_content,
-        &["application"],
+        "manifest/application",
         "android:label",
-        new_display_name,
+        &format!("@string/{}", string_name),
     )?;
 
     std::fs::write(manifest_file_path, result)?;
 
+    Ok(string_name)
+}
+
+/// Sets the label of the default locale to `new_display_name`, translated
+/// labels are kept, see `exchange_apk_display_names`.
+pub fn exchange_apk_display_name(apk_folder: &Path, new_display_name: &str) -> Result<()> {
+    log::info!("exchange_apk_display_name: {}", new_display_name);
+
+    let display_names =
+        BTreeMap::from([(DEFAULT_VALUES_DIR.to_string(), new_display_name.to_string())]);
+
+    exchange_apk_display_names(apk_folder, &display_names)
+}
+
+/// Sets the label per values folder, e.g. `values-zh-rCN` => `演示`.
+/// Folders missing from `display_names` keep their label, new folders are
+/// created.
+pub fn exchange_apk_display_names(
+    apk_folder: &Path,
+    display_names: &BTreeMap<String, String>,
+) -> Result<()> {
+    log::info!("exchange_apk_display_names: {:?}", display_names);
+
+    for folder_name in display_names.keys() {
+        if folder_name != DEFAULT_VALUES_DIR && !folder_name.starts_with("values-")
+            || folder_name.contains(['/', '\\'])
+        {
+            return Err(anyhow::anyhow!("bad values folder {}", folder_name));
+        }
+    }
+
+    let string_name = make_apk_label_string_resource(apk_folder)?;
+    for (folder_name, display_name) in display_names {
+        let values_dir = apk_folder.join("res").join(folder_name);
+        write_string_resource(&values_dir, &string_name, display_name)?;
+    }
+
+    Ok(())
+}
+
+fn exchange_apktool_yml_value(apk_folder: &Path, key_path: &[&str], value: &str) -> Result<()> {
+    let yml_file_path = apk_folder.join(APKTOOL_YML);
+    let yml_content = std::fs::read_to_string(&yml_file_path)?;
+
+    let result = yml_exchange_value(&yml_content, key_path, value)?;
+
+    std::fs::write(yml_file_path, result)?;
+
     Ok(())
 }
 
-pub fn exchange_apk_icon(apk_folder: &Path, new_icon_path: &str) -> Result<()> {
-    let icon_names = get_apk_icon_names(apk_folder)?;
-
-    let icon_image = image::open(new_icon_path).unwrap();
-
-    // list dir of res
-    let res_path = apk_folder.join("res");
-    let res_dir = std::fs::read_dir(res_path).unwrap();
-    for entry in res_dir {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_dir() {
-            for icon_name in &icon_names {
-                let icon_path = path.join(icon_name);
-                log::info!("icon_path: {}", icon_path.to_str().unwrap());
-                if icon_path.exists() {
-                    let old_image = image::open(&icon_path).unwrap();
-                    let w = old_image.width();
-                    let h = old_image.height();
-                    std::fs::remove_file(&icon_path).unwrap();
-
-                    let new_image =
-                        icon_image.resize_exact(w, h, image::imageops::FilterType::Nearest);
-                    new_image.save(&icon_path).unwrap();
-                }
-            }
+/// apktool moves the version into apktool.yml, some decoders leave it in the
+/// manifest as well, keep both in sync.
+fn exchange_manifest_version_value(apk_folder: &Path, attr_key: &str, value: &str) -> Result<()> {
+    let manifest_file_path = apk_folder.join("AndroidManifest.xml");
+    let manifest_file_content = std::fs::read_to_string(&manifest_file_path)?;
+
+    let found = xml_find_value(&manifest_file_content, "manifest", attr_key)?;
+    if found.iter().all(|v| v.is_empty()) {
+        return Ok(());
+    }
+
+    let result = xml_exchange_value(&manifest_file_content, "manifest", attr_key, value)?;
+
+    std::fs::write(manifest_file_path, result)?;
+
+    Ok(())
+}
+
+pub fn exchange_apk_version_code(apk_folder: &Path, version_code: u32) -> Result<()> {
+    log::info!("exchange_apk_version_code: {}", version_code);
+
+    if version_code == 0 || version_code > MAX_VERSION_CODE {
+        return Err(anyhow::anyhow!(
+            "invalid version code {}, must be 1 to {}",
+            version_code,
+            MAX_VERSION_CODE
+        ));
+    }
+
+    let value = version_code.to_string();
+    exchange_apktool_yml_value(
+        apk_folder,
+        &["versionInfo", "versionCode"],
+        &yml_quote(&value),
+    )?;
+    exchange_manifest_version_value(apk_folder, "android:versionCode", &value)
+}
+
+/// Bumps the version code by one, returns the new version code.
+pub fn increase_apk_version_code(apk_folder: &Path) -> Result<u32> {
+    let version_code = get_apk_version_info(apk_folder)?.version_code;
+    let version_code = version_code
+        .parse::<u32>()
+        .map_err(|_| anyhow::anyhow!("bad version code '{}'", version_code))?;
+
+    let new_version_code = version_code
+        .checked_add(1)
+        .filter(|code| *code <= MAX_VERSION_CODE)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "version code {} cannot be increased, {} is the largest",
+                version_code,
+                MAX_VERSION_CODE
+            )
+        })?;
+
+    exchange_apk_version_code(apk_folder, new_version_code)?;
+
+    Ok(new_version_code)
+}
+
+pub fn exchange_apk_version_name(apk_folder: &Path, version_name: &str) -> Result<()> {
+    log::info!("exchange_apk_version_name: {}", version_name);
+
+    exchange_apktool_yml_value(
+        apk_folder,
+        &["versionInfo", "versionName"],
+        &yml_quote(version_name),
+    )?;
+    exchange_manifest_version_value(apk_folder, "android:versionName", version_name)
+}
+
+/// `None` keeps the value. Fails when the new min sdk is above the target sdk.
+pub fn exchange_apk_sdk_versions(
+    apk_folder: &Path,
+    min_sdk_version: Option<u32>,
+    target_sdk_version: Option<u32>,
+) -> Result<()> {
+    log::info!(
+        "exchange_apk_sdk_versions: {:?} {:?}",
+        min_sdk_version,
+        target_sdk_version
+    );
+
+    let version_info = get_apk_version_info(apk_folder)?;
+    let min = min_sdk_version.or(version_info.min_sdk_version.parse().ok());
+    let target = target_sdk_version.or(version_info.target_sdk_version.parse().ok());
+    if let (Some(min), Some(target)) = (min, target) {
+        if min > target {
+            return Err(anyhow::anyhow!(
+                "min sdk {} is above target sdk {}",
+                min,
+                target
+            ));
         }
     }
 
+    if let Some(min_sdk_version) = min_sdk_version {
+        let value = yml_quote(&min_sdk_version.to_string());
+        exchange_apktool_yml_value(apk_folder, &["sdkInfo", "minSdkVersion"], &value)?;
+    }
+    if let Some(target_sdk_version) = target_sdk_version {
+        let value = yml_quote(&target_sdk_version.to_string());
+        exchange_apktool_yml_value(apk_folder, &["sdkInfo", "targetSdkVersion"], &value)?;
+    }
+
     Ok(())
 }
+
+pub fn get_apk_meta_data(apk_folder: &Path) -> Result<Vec<MetaData>> {
+    let manifest_file_path = apk_folder.join("AndroidManifest.xml");
+    let manifest_file_content = std::fs::read_to_string(manifest_file_path)?;
+
+    list_meta_data(&manifest_file_content)
+}
+
+/// Applies the edits in order, the manifest is only written when all succeed.
+pub fn exchange_apk_meta_data(apk_folder: &Path, edits: &[MetaDataEdit]) -> Result<()> {
+    log::info!("exchange_apk_meta_data: {:?}", edits);
+
+    let manifest_file_path = apk_folder.join("AndroidManifest.xml");
+    let mut manifest_file_content = std::fs::read_to_string(&manifest_file_path)?;
+
+    for edit in edits {
+        manifest_file_content = edit_meta_data(&manifest_file_content, edit)?;
+    }
+
+    std::fs::write(manifest_file_path, manifest_file_content)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{icon_util::exchange_apk_icon, test_util::TestFolder};
+
+    /// An empty decoded apk folder.
+    fn decoded_apk_folder(name: &std::ffi::OsStr) -> TestFolder {
+        let folder = TestFolder::new(name);
+        std::fs::create_dir_all(folder.0.join("res").join("values")).unwrap();
+        folder
+    }
+
+    const MANIFEST: &str = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.demo">
+    <application android:icon="@mipmap/ic_launcher" android:label="Demo"/>
+</manifest>"#;
+
+    #[test]
+    fn display_name_changes_the_default_locale_only() {
+        let folder = decoded_apk_folder("display-name".as_ref());
+        folder.write(
+            "AndroidManifest.xml",
+            MANIFEST
+                .replace("\"Demo\"", "\"@string/app_name\"")
+                .as_bytes(),
+        );
+        folder.write(
+            "res/values/strings.xml",
+            b"<resources>\n    <string name=\"app_name\">Demo</string>\n</resources>\n",
+        );
+        folder.write(
+            "res/values-ja/strings.xml",
+            "<resources>\n    <string name=\"app_name\">デモ</string>\n</resources>\n".as_bytes(),
+        );
+
+        exchange_apk_display_name(&folder.0, "New").unwrap();
+
+        assert_eq!(
+            get_apk_display_names(&folder.0).unwrap(),
+            BTreeMap::from([
+                ("values".to_string(), "New".to_string()),
+                ("values-ja".to_string(), "デモ".to_string()),
+            ])
+        );
+    }
+
+    const APKTOOL_YML_WITHOUT_VERSIONS: &str = "!!brut.androlib.meta.MetaInfo
+apkFileName: demo.apk
+isFrameworkApk: false
+usesFramework:
+  ids:
+  - 1
+version: 2.9.3
+";
+
+    #[test]
+    fn versions_are_added_to_apktool_yml() {
+        let folder = decoded_apk_folder("versions".as_ref());
+        folder.write("AndroidManifest.xml", MANIFEST.as_bytes());
+        folder.write(APKTOOL_YML, APKTOOL_YML_WITHOUT_VERSIONS.as_bytes());
+
+        exchange_apk_version_code(&folder.0, 7).unwrap();
+        exchange_apk_version_name(&folder.0, "1.0 'beta'").unwrap();
+        exchange_apk_sdk_versions(&folder.0, Some(21), Some(34)).unwrap();
+        assert_eq!(increase_apk_version_code(&folder.0).unwrap(), 8);
+
+        let version_info = get_apk_version_info(&folder.0).unwrap();
+        assert_eq!(version_info.version_code, "8");
+        assert_eq!(version_info.version_name, "1.0 'beta'");
+        assert_eq!(version_info.min_sdk_version, "21");
+        assert_eq!(version_info.target_sdk_version, "34");
+        assert_eq!(
+            std::fs::read_to_string(folder.0.join(APKTOOL_YML)).unwrap(),
+            format!(
+                "{}versionInfo:\n  versionCode: '8'\n  versionName: '1.0 ''beta'''\nsdkInfo:\n  minSdkVersion: '21'\n  targetSdkVersion: '34'\n",
+                APKTOOL_YML_WITHOUT_VERSIONS
+            )
+        );
+
+        // the manifest has no version, it is left alone
+        assert_eq!(
+            std::fs::read_to_string(folder.0.join("AndroidManifest.xml")).unwrap(),
+            MANIFEST
+        );
+        assert!(exchange_apk_sdk_versions(&folder.0, Some(35), None).is_err());
+    }
+
+    #[test]
+    fn version_code_stays_in_range() {
+        let folder = decoded_apk_folder("version-range".as_ref());
+        folder.write("AndroidManifest.xml", MANIFEST.as_bytes());
+
+        for version_code in [MAX_VERSION_CODE, u32::MAX] {
+            folder.write(
+                APKTOOL_YML,
+                format!(
+                    "{}versionInfo:\n  versionCode: '{}'\n",
+                    APKTOOL_YML_WITHOUT_VERSIONS, version_code
+                )
+                .as_bytes(),
+            );
+            assert!(increase_apk_version_code(&folder.0).is_err());
+        }
+        assert!(exchange_apk_version_code(&folder.0, 0).is_err());
+        assert!(exchange_apk_version_code(&folder.0, MAX_VERSION_CODE + 1).is_err());
+        exchange_apk_version_code(&folder.0, MAX_VERSION_CODE).unwrap();
+    }
+
+    const RENAME_MANIFEST: &str = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.demo">
+    <permission android:name="com.example.demo.permission.C2D_MESSAGE" android:protectionLevel="signature"/>
+    <uses-permission android:name="com.example.demo.permission.C2D_MESSAGE"/>
+    <uses-permission android:name="android.permission.INTERNET"/>
+    <uses-permission android:name="com.example.demonstration.permission.READ"/>
+    <application android:name=".DemoApplication" android:label="Demo" android:taskAffinity="com.example.demo">
+        <activity android:name=".MainActivity" android:process=":remote"/>
+        <activity android:name="SettingsActivity" android:parentActivityName=".MainActivity"/>
+        <activity android:name="com.thirdparty.sdk.SdkActivity" android:process="com.example.demo:sdk"/>
+        <provider android:name="androidx.core.content.FileProvider" android:authorities="com.example.demo.fileprovider;com.example.demo.androidx-startup;com.thirdparty.sdk.provider" android:readPermission="com.example.demo.permission.C2D_MESSAGE"/>
+        <receiver android:name="com.thirdparty.push.PushReceiver" android:permission="com.thirdparty.push.permission.SEND"/>
+    </application>
+</manifest>"#;
+
+    #[test]
+    fn rename_rewrites_references_of_the_package() {
+        let folder = decoded_apk_folder("rename".as_ref());
+        folder.write("AndroidManifest.xml", RENAME_MANIFEST.as_bytes());
+
+        rename_apk_package(&folder.0, "com.example.qa").unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(folder.0.join("AndroidManifest.xml")).unwrap(),
+            RENAME_MANIFEST
+                .replace("package=\"com.example.demo\"", "package=\"com.example.qa\"")
+                // relative classes keep pointing at the original ones
+                .replace(
+                    "\".DemoApplication\"",
+                    "\"com.example.demo.DemoApplication\""
+                )
+                .replace("\".MainActivity\"", "\"com.example.demo.MainActivity\"")
+                .replace(
+                    "\"SettingsActivity\"",
+                    "\"com.example.demo.SettingsActivity\""
+                )
+                .replace("com.example.demo.permission", "com.example.qa.permission")
+                .replace("\"com.example.demo\">", "\"com.example.qa\">")
+                .replace("com.example.demo:sdk", "com.example.qa:sdk")
+                .replace(
+                    "com.example.demo.fileprovider",
+                    "com.example.qa.fileprovider"
+                )
+                .replace(
+                    "com.example.demo.androidx-startup",
+                    "com.example.qa.androidx-startup"
+                )
+        );
+        assert_eq!(get_apk_package_name(&folder.0).unwrap(), "com.example.qa");
+        assert!(rename_apk_package(&folder.0, "1bad").is_err());
+    }
+
+    #[test]
+    fn malformed_manifest_is_an_error() {
+        let folder = decoded_apk_folder("malformed".as_ref());
+        for manifest in [
+            "",
+            "<manifest package=\"com.example.demo\"><application>",
+            "<manifest package=\"com.example.demo\"></application>",
+            "<manifest><application android:label=\"Demo\"/></manifest>",
+            "<manifest package=\"com.example.demo\" package=\"x\"/>",
+        ] {
+            folder.write("AndroidManifest.xml", manifest.as_bytes());
+            assert!(get_apk_package_name(&folder.0).is_err(), "{}", manifest);
+            assert!(rename_apk_package(&folder.0, "com.example.other").is_err());
+        }
+
+        folder.write("AndroidManifest.xml", b"<manifest package=\"\xff\"/>");
+        assert!(get_apk_package_name(&folder.0).is_err());
+        assert!(get_apk_display_names(&folder.0).is_err());
+        assert!(get_apk_meta_data(&folder.0).is_err());
+    }
+
+    #[test]
+    fn missing_label_and_apktool_yml_are_errors() {
+        let folder = decoded_apk_folder("missing".as_ref());
+        folder.write(
+            "AndroidManifest.xml",
+            b"<manifest package=\"com.example.demo\"><application/></manifest>",
+        );
+        assert!(get_apk_display_name(&folder.0).is_err());
+        assert!(get_apk_version_info(&folder.0).is_err());
+        assert!(increase_apk_version_code(&folder.0).is_err());
+    }
+
+    #[test]
+    fn corrupt_icon_has_no_preview() {
+        let folder = decoded_apk_folder("corrupt-icon".as_ref());
+        folder.write("AndroidManifest.xml", MANIFEST.as_bytes());
+        folder.write(
+            "res/mipmap-xxhdpi/ic_launcher.png",
+            b"\x89PNG\r\n\x1a\nnot a png",
+        );
+
+        assert_eq!(get_apk_icon_preview(&folder.0).unwrap(), None);
+
+        // a corrupt new icon
+        let corrupt = folder.0.join("res/mipmap-xxhdpi/ic_launcher.png");
+        let error = exchange_apk_icon(&folder.0, &corrupt.to_string_lossy()).unwrap_err();
+        assert_eq!(RepackError::from(error).code(), "InvalidIcon");
+
+        // a good new icon over the corrupt one of the apk
+        let icon = folder.0.join("icon.png");
+        image::RgbaImage::new(512, 512).save(&icon).unwrap();
+        assert!(exchange_apk_icon(&folder.0, &icon.to_string_lossy()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_path_is_reported_not_panicking() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let workspace = TestFolder::new(std::ffi::OsStr::from_bytes(b"non-utf8-\xff"));
+        let apk_folder = workspace.0.join("original");
+        workspace.write("original/AndroidManifest.xml", MANIFEST.as_bytes());
+        let icon_file = workspace
+            .0
+            .join("original/res/mipmap-xxhdpi/ic_launcher.png");
+        std::fs::create_dir_all(icon_file.parent().unwrap()).unwrap();
+        image::RgbaImage::new(144, 144).save(&icon_file).unwrap();
+
+        assert!(path_str(&apk_folder).is_err());
+        assert_eq!(
+            get_apk_package_name(&apk_folder).unwrap(),
+            "com.example.demo"
+        );
+        assert_eq!(get_apk_display_name(&apk_folder).unwrap(), "Demo");
+
+        // icons are found and replaced, only apktool needs a utf-8 path
+        assert_eq!(
+            get_apk_icon_preview(&apk_folder).unwrap(),
+            Some(icon_file.clone())
+        );
+        let icons = TestFolder::new("non-utf8-icon".as_ref());
+        let new_icon = icons.0.join("new.png");
+        image::RgbaImage::from_pixel(512, 512, image::Rgba([255, 0, 0, 255]))
+            .save(&new_icon)
+            .unwrap();
+        exchange_apk_icon(&apk_folder, &new_icon.to_string_lossy()).unwrap();
+        assert_eq!(
+            image::open(&icon_file)
+                .unwrap()
+                .to_rgba8()
+                .get_pixel(72, 72)
+                .0,
+            [255, 0, 0, 255]
+        );
+
+        let error = crate::cli::block_on(pack_apk("non-utf8", &apk_folder)).unwrap_err();
+        let error = RepackError::from(error);
+        assert_eq!(error.code(), "BuildFailed");
+        assert!(
+            error.to_string().contains("is not a UTF-8 path"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use ab_glyph::{point, Font, FontRef, OutlinedGlyph, PxScale, Rect, ScaleFont};
+use anyhow::Result;
+use image::{DynamicImage, GrayImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::{FRAC_1_SQRT_2, SQRT_2},
+    path::Path,
+};
+
+use crate::{
+    icon_util::{
+        get_apk_visible_icon_files, parse_color, px, save_image, vector_size_dp, IconFile,
+        IconFileKind, ADAPTIVE_SAFE_ZONE, DENSITIES,
+    },
+    xml_util::{XmlDocument, XmlElement, XmlNode, ANDROID_NAMESPACE},
+};
+
+/// bundled so badges look the same on every machine
+static BADGE_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans-Bold.ttf");
+
+/// distance of the ribbon center line from the corner and its half width,
+/// relative to the badge area
+const RIBBON_OFFSET: f32 = 0.32;
+const RIBBON_HALF_WIDTH: f32 = 0.1;
+/// height of the text banner of the tint style
+const BANNER_HEIGHT: f32 = 0.26;
+const TINT_STRENGTH: f32 = 0.35;
+/// text height relative to the band it is written on
+const TEXT_FILL: f32 = 0.66;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BadgeStyle {
+    /// diagonal ribbon across the top right corner
+    #[default]
+    Ribbon,
+    /// the icon tinted with the badge color, the text on a bottom banner
+    Tint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IconBadge {
+    /// e.g. `DEV`, `QA`, `BETA`
+    pub text: String,
+    pub style: BadgeStyle,
+    pub color: String,
+    pub text_color: String,
+}
+
+impl Default for IconBadge {
+    fn default() -> Self {
+        IconBadge {
+            text: "DEV".to_string(),
+            style: BadgeStyle::Ribbon,
+            color: "#E53935".to_string(),
+            text_color: "#FFFFFF".to_string(),
+        }
+    }
+}
+
+struct BadgePaint<'a> {
+    font: FontRef<'a>,
+    text: &'a str,
+    style: BadgeStyle,
+    color: Rgba<u8>,
+    text_color: Rgba<u8>,
+}
+
+fn layout_text(font: &FontRef, text: &str, scale: f32) -> Vec<OutlinedGlyph> {
+    let scaled_font = font.as_scaled(PxScale::from(scale));
+
+    let mut glyphs = vec![];
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled_font.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled_font.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(scale, point(caret, scaled_font.ascent()));
+        caret += scaled_font.h_advance(id);
+        previous = Some(id);
+
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            glyphs.push(outlined);
+        }
+    }
+
+    glyphs
+}
+
+fn text_bounds(glyphs: &[OutlinedGlyph]) -> Option<Rect> {
+    glyphs
+        .iter()
+        .map(|glyph| glyph.px_bounds())
+        .reduce(|a, b| Rect {
+            min: point(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+            max: point(a.max.x.max(b.max.x), a.max.y.max(b.max.y)),
+        })
+}
+
+/// Coverage of `text` cropped to its ink, scaled to fit the given box.
+fn render_text(font: &FontRef, text: &str, max_width: f32, max_height: f32) -> GrayImage {
+    // measure at a reference size, then scale to fit
+    let reference = 100.0;
+    let Some(bounds) = text_bounds(&layout_text(font, text, reference)) else {
+        return GrayImage::new(0, 0);
+    };
+    let scale = reference * (max_width / bounds.width()).min(max_height / bounds.height());
+
+    let glyphs = layout_text(font, text, scale);
+    let Some(bounds) = text_bounds(&glyphs) else {
+        return GrayImage::new(0, 0);
+    };
+
+    let mut image = GrayImage::new(bounds.width().ceil() as u32, bounds.height().ceil() as u32);
+    for glyph in &glyphs {
+        let glyph_bounds = glyph.px_bounds();
+        let offset_x = (glyph_bounds.min.x - bounds.min.x).round() as u32;
+        let offset_y = (glyph_bounds.min.y - bounds.min.y).round() as u32;
+        glyph.draw(|x, y, coverage| {
+            let (x, y) = (x + offset_x, y + offset_y);
+            if x < image.width() && y < image.height() {
+                let pixel = image.get_pixel_mut(x, y);
+                pixel.0[0] = pixel.0[0].max((coverage.clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+        });
+    }
+
+    image
+}
+
+/// Bilinear coverage at `(x, y)`, zero outside the image.
+fn sample(image: &GrayImage, x: f32, y: f32) -> f32 {
+    let value = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+            0.0
+        } else {
+            image.get_pixel(x as u32, y as u32).0[0] as f32 / 255.0
+        }
+    };
+
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let top = value(x0, y0) * (1.0 - fx) + value(x0 + 1, y0) * fx;
+    let bottom = value(x0, y0 + 1) * (1.0 - fx) + value(x0 + 1, y0 + 1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Draws `color` over the pixel with `alpha` coverage.
+fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, alpha: f32) {
+    let alpha = alpha * color.0[3] as f32 / 255.0;
+    if alpha <= 0.0 {
+        return;
+    }
+
+    let dest_alpha = pixel.0[3] as f32 / 255.0;
+    let out_alpha = alpha + dest_alpha * (1.0 - alpha);
+    for i in 0..3 {
+        let value = (color.0[i] as f32 * alpha + pixel.0[i] as f32 * dest_alpha * (1.0 - alpha))
+            / out_alpha;
+        pixel.0[i] = value.round() as u8;
+    }
+    pixel.0[3] = (out_alpha * 255.0).round() as u8;
+}
+
+fn draw_ribbon(canvas: &mut RgbaImage, area: (u32, u32, u32), paint: &BadgePaint) {
+    let (area_x, area_y, size) = area;
+    let offset = size as f32 * RIBBON_OFFSET;
+    let half_width = size as f32 * RIBBON_HALF_WIDTH;
+    // the inner edge of the ribbon is the shortest, 2 * distance to the corner
+    let text = render_text(
+        &paint.font,
+        paint.text,
+        2.0 * (offset - half_width) * 0.9,
+        2.0 * half_width * TEXT_FILL,
+    );
+
+    let corner_x = (area_x + size) as f32;
+    let corner_y = area_y as f32;
+    for y in area_y..area_y + size {
+        for x in area_x..area_x + size {
+            let dx = x as f32 + 0.5 - corner_x;
+            let dy = y as f32 + 0.5 - corner_y;
+            // text runs down to the right, its top faces the corner
+            let along = (dx + dy) * FRAC_1_SQRT_2;
+            let across = (dy - dx) / SQRT_2 - offset;
+
+            let coverage = (half_width - across.abs() + 0.5).clamp(0.0, 1.0);
+            if coverage <= 0.0 {
+                continue;
+            }
+            let pixel = canvas.get_pixel_mut(x, y);
+            blend(pixel, paint.color, coverage);
+            let ink = sample(
+                &text,
+                along + text.width() as f32 / 2.0,
+                across + text.height() as f32 / 2.0,
+            );
+            blend(pixel, paint.text_color, ink * coverage);
+        }
+    }
+}
+
+fn draw_banner(canvas: &mut RgbaImage, area: (u32, u32, u32), paint: &BadgePaint) {
+    let (area_x, area_y, size) = area;
+    let banner_height = ((size as f32 * BANNER_HEIGHT).round() as u32).max(1);
+    let banner_y = area_y + size - banner_height;
+    let text = render_text(
+        &paint.font,
+        paint.text,
+        size as f32 * 0.9,
+        banner_height as f32 * TEXT_FILL,
+    );
+    let text_x = area_x as f32 + (size as f32 - text.width() as f32) / 2.0;
+    let text_y = banner_y as f32 + (banner_height as f32 - text.height() as f32) / 2.0;
+
+    for y in banner_y..area_y + size {
+        for x in area_x..area_x + size {
+            let pixel = canvas.get_pixel_mut(x, y);
+            blend(pixel, paint.color, 1.0);
+            let ink = sample(&text, x as f32 + 0.5 - text_x, y as f32 + 0.5 - text_y);
+            blend(pixel, paint.text_color, ink);
+        }
+    }
+}
+
+/// Mixes the badge color into the drawn pixels, transparent ones stay so.
+fn tint(canvas: &mut RgbaImage, color: Rgba<u8>) {
+    for pixel in canvas.pixels_mut() {
+        for i in 0..3 {
+            let value =
+                pixel.0[i] as f32 * (1.0 - TINT_STRENGTH) + color.0[i] as f32 * TINT_STRENGTH;
+            pixel.0[i] = value.round() as u8;
+        }
+    }
+}
+
+/// Square the badge is drawn in: the square inside the safe zone circle of
+/// adaptive foregrounds or inside the circle of round icons, else the whole
+/// icon.
+fn badge_area(w: u32, h: u32, file: &IconFile, round: bool) -> (u32, u32, u32) {
+    let ratio = if file.layer.as_deref() == Some("foreground") {
+        ADAPTIVE_SAFE_ZONE * FRAC_1_SQRT_2
+    } else if round {
+        FRAC_1_SQRT_2
+    } else {
+        1.0
+    };
+    let size = ((w.min(h) as f32 * ratio).round() as u32).max(1);
+    ((w - size) / 2, (h - size) / 2, size)
+}
+
+fn draw_badge(canvas: &mut RgbaImage, area: (u32, u32, u32), paint: &BadgePaint) {
+    match paint.style {
+        BadgeStyle::Ribbon => draw_ribbon(canvas, area, paint),
+        BadgeStyle::Tint => {
+            tint(canvas, paint.color);
+            if !paint.text.trim().is_empty() {
+                draw_banner(canvas, area, paint);
+            }
+        }
+    }
+}
+
+fn badge_bitmap_icon(file: &IconFile, round: bool, paint: &BadgePaint) -> Result<()> {
+    log::info!("badge_bitmap_icon: {}", file.path.to_string_lossy());
+
+    let mut icon = image::open(&file.path)
+        .map_err(|e| anyhow::anyhow!("bad icon {}: {}", file.path.to_string_lossy(), e))?
+        .to_rgba8();
+    let area = badge_area(icon.width(), icon.height(), file, round);
+    draw_badge(&mut icon, area, paint);
+    save_image(&DynamicImage::ImageRgba8(icon), &file.path)
+}
+
+fn layer_list_xml(drawables: &[String]) -> Result<String> {
+    let mut layer_list =
+        XmlElement::new("layer-list").with_attr("xmlns:android", ANDROID_NAMESPACE);
+    for drawable in drawables {
+        layer_list.children.push(XmlNode::Element(
+            XmlElement::new("item").with_attr("android:drawable", drawable),
+        ));
+    }
+
+    let mut document =
+        XmlDocument::parse("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"no\"?>")?;
+    document.nodes.push(XmlNode::Element(layer_list));
+    document.to_xml_string()
+}
+
+/// Vectors are kept, the files become a layer list of the vector and a badge
+/// bitmap per density.
+fn badge_vector_icon(
+    apk_folder: &Path,
+    files: &[&IconFile],
+    round: bool,
+    paint: &BadgePaint,
+) -> Result<()> {
+    let Some(first) = files.first() else {
+        return Ok(());
+    };
+    log::info!("badge_vector_icon: @{}/{}", first.res_type, first.name);
+
+    // the overlay is transparent, only the banner of the tint style shows
+    if paint.style == BadgeStyle::Tint {
+        log::warn!("vector @{}/{} is not tinted", first.res_type, first.name);
+    }
+
+    let base_name = format!("{}_base", first.name);
+    let badge_name = format!("{}_badge", first.name);
+    let layer_list = layer_list_xml(&[
+        format!("@{}/{}", first.res_type, base_name),
+        format!("@{}/{}", first.res_type, badge_name),
+    ])?;
+    for file in files {
+        let base_path = file.path.with_file_name(format!("{}.xml", base_name));
+        std::fs::rename(&file.path, base_path)?;
+        std::fs::write(&file.path, &layer_list)?;
+    }
+
+    let size_dp = vector_size_dp(first)?;
+    for (density, scale) in DENSITIES {
+        let dir = apk_folder
+            .join("res")
+            .join(format!("{}-{}", first.res_type, density));
+        std::fs::create_dir_all(&dir)?;
+
+        let size = px(size_dp, *scale);
+        let mut overlay = RgbaImage::new(size, size);
+        let area = badge_area(size, size, first, round);
+        draw_badge(&mut overlay, area, paint);
+        overlay.save(dir.join(format!("{}.png", badge_name)))?;
+    }
+
+    Ok(())
+}
+
+/// Stamps a text badge on every density of the icon and round icon, inside
+/// the safe zone of adaptive foregrounds.
+pub fn add_apk_icon_badge(apk_folder: &Path, badge: &IconBadge) -> Result<()> {
+    log::info!("add_apk_icon_badge: {:?}", badge);
+
+    let paint = BadgePaint {
+        font: FontRef::try_from_slice(BADGE_FONT)
+            .map_err(|e| anyhow::anyhow!("bad badge font: {}", e))?,
+        text: badge.text.trim(),
+        style: badge.style,
+        color: parse_color(&badge.color)?,
+        text_color: parse_color(&badge.text_color)?,
+    };
+
+    let files = get_apk_visible_icon_files(apk_folder)?;
+    let mut vectors: Vec<(&str, &str)> = vec![];
+    for (file, round) in &files {
+        match file.kind {
+            IconFileKind::Bitmap => badge_bitmap_icon(file, *round, &paint)?,
+            IconFileKind::Vector => {
+                let resource = (file.res_type.as_str(), file.name.as_str());
+                if vectors.contains(&resource) {
+                    continue;
+                }
+                vectors.push(resource);
+
+                // every qualifier of the vector at once
+                let resource_files = files
+                    .iter()
+                    .filter(|(other, _)| {
+                        other.kind == IconFileKind::Vector
+                            && other.res_type == file.res_type
+                            && other.name == file.name
+                    })
+                    .map(|(other, _)| other)
+                    .collect::<Vec<&IconFile>>();
+                badge_vector_icon(apk_folder, &resource_files, *round, &paint)?;
+            }
+            IconFileKind::AdaptiveIcon => {}
+            IconFileKind::Xml => {
+                log::warn!("no badge on {}", file.path.to_string_lossy());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    #[test]
+    fn foreground_badge_stays_in_the_safe_zone() {
+        let folder = TestFolder::new("badge-foreground".as_ref());
+        folder.write(
+            "AndroidManifest.xml",
+            br#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.demo">
+    <application android:icon="@mipmap/ic_launcher"/>
+</manifest>"#,
+        );
+        folder.write(
+            "res/mipmap-anydpi-v26/ic_launcher.xml",
+            br#"<adaptive-icon xmlns:android="http://schemas.android.com/apk/res/android">
+    <background android:drawable="@color/ic_launcher_background"/>
+    <foreground android:drawable="@mipmap/ic_launcher_foreground"/>
+</adaptive-icon>"#,
+        );
+        let foreground_path = folder
+            .0
+            .join("res/mipmap-xxhdpi/ic_launcher_foreground.png");
+        std::fs::create_dir_all(foreground_path.parent().unwrap()).unwrap();
+        let before = RgbaImage::from_pixel(324, 324, Rgba([40, 80, 120, 255]));
+        before.save(&foreground_path).unwrap();
+
+        add_apk_icon_badge(&folder.0, &IconBadge::default()).unwrap();
+
+        let after = image::open(&foreground_path).unwrap().to_rgba8();
+        let radius = 324.0 * ADAPTIVE_SAFE_ZONE / 2.0;
+        let mut changed = 0;
+        for (x, y, pixel) in after.enumerate_pixels() {
+            if pixel == before.get_pixel(x, y) {
+                continue;
+            }
+            changed += 1;
+            let dx = x as f32 + 0.5 - 162.0;
+            let dy = y as f32 + 0.5 - 162.0;
+            assert!((dx * dx + dy * dy).sqrt() <= radius, "{} {} changed", x, y);
+        }
+        assert!(changed > 0, "no badge drawn");
+    }
+}
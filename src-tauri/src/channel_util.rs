@@ -0,0 +1,477 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    apk_signing_block::{
+        find_apk_signing_block, replace_apk_signing_block, APK_SIGNATURE_SCHEME_V2_BLOCK_ID,
+    },
+    zip_util::{find_eocd, read_u16, EOCD_MIN_SIZE},
+};
+
+/// VasDolly stores the v2 channel as an ID-value pair of the APK Signing Block
+pub const VASDOLLY_V2_CHANNEL_BLOCK_ID: u32 = 0x881155ff;
+
+/// VasDolly v1 channel lives in the zip comment: channel, u16 length, magic
+pub const VASDOLLY_V1_MAGIC: &[u8; 8] = b"ltlovezh";
+
+/// Meituan Walle stores a JSON object as an ID-value pair of the APK Signing Block
+pub const WALLE_CHANNEL_BLOCK_ID: u32 = 0x71777777;
+
+const WALLE_CHANNEL_KEY: &str = "channel";
+
+fn is_v2_signed(apk_data: &[u8]) -> Result<bool> {
+    let eocd = find_eocd(apk_data)?;
+    Ok(find_apk_signing_block(apk_data, &eocd)?
+        .is_some_and(|block| block.get(APK_SIGNATURE_SCHEME_V2_BLOCK_ID).is_some()))
+}
+
+pub fn read_vasdolly_v2_channel(apk_data: &[u8]) -> Result<Option<String>> {
+    let eocd = find_eocd(apk_data)?;
+    let Some(block) = find_apk_signing_block(apk_data, &eocd)? else {
+        return Ok(None);
+    };
+
+    match block.get(VASDOLLY_V2_CHANNEL_BLOCK_ID) {
+        Some(value) => Ok(Some(String::from_utf8(value.to_vec())?)),
+        None => Ok(None),
+    }
+}
+
+pub fn read_vasdolly_v1_channel(apk_data: &[u8]) -> Result<Option<String>> {
+    let eocd = find_eocd(apk_data)?;
+    let comment = &eocd.comment;
+    if !comment.ends_with(VASDOLLY_V1_MAGIC) || comment.len() < VASDOLLY_V1_MAGIC.len() + 2 {
+        return Ok(None);
+    }
+
+    let length_offset = comment.len() - VASDOLLY_V1_MAGIC.len() - 2;
+    let channel_len = read_u16(comment, length_offset)? as usize;
+    if channel_len > length_offset {
+        return Err(anyhow::anyhow!("bad vasdolly v1 channel length"));
+    }
+
+    let channel = &comment[length_offset - channel_len..length_offset];
+    Ok(Some(String::from_utf8(channel.to_vec())?))
+}
+
+pub fn write_vasdolly_v2_channel(apk_data: &[u8], channel: Option<&str>) -> Result<Vec<u8>> {
+    let eocd = find_eocd(apk_data)?;
+    let block = find_apk_signing_block(apk_data, &eocd)?
+        .ok_or_else(|| anyhow::anyhow!("apk has no v2 signature, cannot write v2 channel"))?;
+
+    let mut pairs = block
+        .pairs
+        .iter()
+        .filter(|(id, _)| *id != VASDOLLY_V2_CHANNEL_BLOCK_ID)
+        .cloned()
+        .collect::<Vec<(u32, Vec<u8>)>>();
+    if let Some(channel) = channel {
+        pairs.push((VASDOLLY_V2_CHANNEL_BLOCK_ID, channel.as_bytes().to_vec()));
+    }
+
+    replace_apk_signing_block(apk_data, &eocd, &block, &pairs)
+}
+
+/// The zip comment is covered by v2 signatures, so v1 channels only fit v1
+/// signed apks, or must be written before v2 signing.
+pub fn write_vasdolly_v1_channel(apk_data: &[u8], channel: Option<&str>) -> Result<Vec<u8>> {
+    if is_v2_signed(apk_data)? {
+        return Err(anyhow::anyhow!(
+            "apk has a v2 signature, writing a v1 channel would break it"
+        ));
+    }
+
+    let eocd = find_eocd(apk_data)?;
+    let mut comment = eocd.comment.clone();
+    if let Some(old_channel) = read_vasdolly_v1_channel(apk_data)? {
+        comment.truncate(comment.len() - VASDOLLY_V1_MAGIC.len() - 2 - old_channel.len());
+    }
+
+    if let Some(channel) = channel {
+        comment.extend_from_slice(channel.as_bytes());
+        comment.extend_from_slice(&(channel.len() as u16).to_le_bytes());
+        comment.extend_from_slice(VASDOLLY_V1_MAGIC);
+    }
+
+    if comment.len() > u16::MAX as usize {
+        return Err(anyhow::anyhow!("zip comment too long"));
+    }
+
+    let mut out = apk_data[..eocd.offset + EOCD_MIN_SIZE].to_vec();
+    let comment_len_offset = eocd.offset + 20;
+    out[comment_len_offset..comment_len_offset + 2]
+        .copy_from_slice(&(comment.len() as u16).to_le_bytes());
+    out.extend_from_slice(&comment);
+
+    Ok(out)
+}
+
+/// extra values of a Walle payload, kept as they were written
+pub type WalleExtras = BTreeMap<String, serde_json::Value>;
+
+/// channel and extras of a Walle payload
+type WallePayload = (Option<String>, WalleExtras);
+
+/// Walle JSON payload, a string `channel` plus any extra values. A payload of
+/// extras only has no channel.
+pub fn read_walle_channel(apk_data: &[u8]) -> Result<Option<WallePayload>> {
+    let eocd = find_eocd(apk_data)?;
+    let Some(block) = find_apk_signing_block(apk_data, &eocd)? else {
+        return Ok(None);
+    };
+    let Some(value) = block.get(WALLE_CHANNEL_BLOCK_ID) else {
+        return Ok(None);
+    };
+
+    let payload: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(value)?;
+    let mut channel = None;
+    let mut extras = BTreeMap::new();
+    for (key, value) in payload {
+        if key != WALLE_CHANNEL_KEY {
+            extras.insert(key, value);
+            continue;
+        }
+
+        match value {
+            serde_json::Value::String(value) => channel = Some(value),
+            value => return Err(anyhow::anyhow!("walle channel is not a string: {}", value)),
+        }
+    }
+
+    Ok(Some((channel, extras)))
+}
+
+/// Writes the payload when there is a channel or an extra, else removes it.
+pub fn write_walle_channel(
+    apk_data: &[u8],
+    channel: Option<&str>,
+    extras: &WalleExtras,
+) -> Result<Vec<u8>> {
+    let eocd = find_eocd(apk_data)?;
+    let block = find_apk_signing_block(apk_data, &eocd)?
+        .ok_or_else(|| anyhow::anyhow!("apk has no v2 signature, cannot write walle channel"))?;
+
+    let mut pairs = block
+        .pairs
+        .iter()
+        .filter(|(id, _)| *id != WALLE_CHANNEL_BLOCK_ID)
+        .cloned()
+        .collect::<Vec<(u32, Vec<u8>)>>();
+    let mut payload = serde_json::Map::new();
+    for (key, value) in extras {
+        if key != WALLE_CHANNEL_KEY {
+            payload.insert(key.clone(), value.clone());
+        }
+    }
+    if let Some(channel) = channel {
+        payload.insert(
+            WALLE_CHANNEL_KEY.to_string(),
+            serde_json::Value::String(channel.to_string()),
+        );
+    }
+    if !payload.is_empty() {
+        pairs.push((WALLE_CHANNEL_BLOCK_ID, serde_json::to_vec(&payload)?));
+    }
+
+    replace_apk_signing_block(apk_data, &eocd, &block, &pairs)
+}
+
+fn rewrite_apk_file(apk_file: &Path, rewrite: impl FnOnce(&[u8]) -> Result<Vec<u8>>) -> Result<()> {
+    let apk_data = std::fs::read(apk_file)?;
+    let new_apk_data = rewrite(&apk_data)?;
+
+    let tmp_file_path = apk_file.with_extension("channel");
+    std::fs::write(&tmp_file_path, new_apk_data)?;
+    std::fs::rename(&tmp_file_path, apk_file)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelScheme {
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "vasdolly_v1")]
+    VasDollyV1,
+    #[serde(rename = "vasdolly_v2")]
+    VasDollyV2,
+    #[serde(rename = "walle")]
+    Walle,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelInfo {
+    pub scheme: ChannelScheme,
+    pub channel: Option<String>,
+    /// Walle only, other schemes cannot carry extra key/values
+    pub extras: WalleExtras,
+}
+
+/// Returns the channel of the apk and the scheme holding it,
+/// VasDolly v2 first, then Walle, then VasDolly v1.
+pub fn get_apk_channel(apk_file: &Path) -> Result<ChannelInfo> {
+    log::info!("get_apk_channel");
+
+    let apk_data = std::fs::read(apk_file)?;
+    if let Some(channel) = read_vasdolly_v2_channel(&apk_data)? {
+        return Ok(ChannelInfo {
+            scheme: ChannelScheme::VasDollyV2,
+            channel: Some(channel),
+            extras: BTreeMap::new(),
+        });
+    }
+
+    if let Some((channel, extras)) = read_walle_channel(&apk_data)? {
+        return Ok(ChannelInfo {
+            scheme: ChannelScheme::Walle,
+            channel,
+            extras,
+        });
+    }
+
+    if let Some(channel) = read_vasdolly_v1_channel(&apk_data)? {
+        return Ok(ChannelInfo {
+            scheme: ChannelScheme::VasDollyV1,
+            channel: Some(channel),
+            extras: BTreeMap::new(),
+        });
+    }
+
+    Ok(ChannelInfo::default())
+}
+
+/// Writes the channel in the scheme of `channel_info`, channels of the other
+/// schemes are removed. VasDolly v1 needs an apk without v2 signature.
+/// `ChannelScheme::None` or a `None` channel removes every channel, Walle
+/// keeps its extras without a channel.
+pub fn set_apk_channel(apk_file: &Path, channel_info: &ChannelInfo) -> Result<()> {
+    log::info!(
+        "set_apk_channel: {:?} {:?}",
+        channel_info.scheme,
+        channel_info.channel
+    );
+
+    let channel = match channel_info.scheme {
+        ChannelScheme::None => None,
+        _ => channel_info.channel.as_deref(),
+    };
+
+    rewrite_apk_file(apk_file, |apk_data| {
+        let eocd = find_eocd(apk_data)?;
+        let mut apk_data = apk_data.to_vec();
+
+        if let Some(block) = find_apk_signing_block(&apk_data, &eocd)? {
+            let pairs = block
+                .pairs
+                .iter()
+                .filter(|(id, _)| {
+                    *id != VASDOLLY_V2_CHANNEL_BLOCK_ID && *id != WALLE_CHANNEL_BLOCK_ID
+                })
+                .cloned()
+                .collect::<Vec<(u32, Vec<u8>)>>();
+            if pairs.len() != block.pairs.len() {
+                apk_data = replace_apk_signing_block(&apk_data, &eocd, &block, &pairs)?;
+            }
+        }
+
+        // a v1 channel of a v2 signed apk is covered by the signature, keep it
+        if read_vasdolly_v1_channel(&apk_data)?.is_some() && !is_v2_signed(&apk_data)? {
+            apk_data = write_vasdolly_v1_channel(&apk_data, None)?;
+        }
+
+        match (channel_info.scheme, channel) {
+            (ChannelScheme::Walle, channel) => {
+                write_walle_channel(&apk_data, channel, &channel_info.extras)
+            }
+            (ChannelScheme::VasDollyV1, Some(channel)) => {
+                write_vasdolly_v1_channel(&apk_data, Some(channel))
+            }
+            (ChannelScheme::VasDollyV2, Some(channel)) => {
+                write_vasdolly_v2_channel(&apk_data, Some(channel))
+            }
+            _ => Ok(apk_data),
+        }
+    })
+}
+
+/// One channel per line, csv lines use the first column, `#` starts a comment.
+pub fn parse_channel_list(content: &str) -> Vec<String> {
+    let mut channels: Vec<String> = vec![];
+
+    for (index, line) in content.trim_start_matches('\u{feff}').lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let channel = line
+            .split([',', ';', '\t'])
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .trim_matches('"')
+            .trim();
+
+        // csv header
+        if index == 0 && channel.eq_ignore_ascii_case("channel") {
+            continue;
+        }
+
+        if !channel.is_empty() && !channels.iter().any(|c| c == channel) {
+            channels.push(channel.to_string());
+        }
+    }
+
+    channels
+}
+
+pub fn read_channel_file(channel_file: &Path) -> Result<Vec<String>> {
+    log::info!("read_channel_file: {}", channel_file.to_string_lossy());
+
+    let content = std::fs::read_to_string(channel_file)?;
+    Ok(parse_channel_list(&content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{build_zip, TestFolder},
+        zip_util::{read_central_directory, read_entry_data},
+    };
+
+    const FILES: &[(&str, &[u8], bool)] = &[("classes.dex", b"dex", false)];
+
+    /// a stand-in v2 signature, the channel code only looks for the pair
+    fn v2_signed_apk() -> Vec<u8> {
+        build_zip(FILES, &[(APK_SIGNATURE_SCHEME_V2_BLOCK_ID, b"v2".to_vec())])
+    }
+
+    fn assert_entries_intact(apk_data: &[u8]) {
+        let eocd = find_eocd(apk_data).unwrap();
+        let entries = read_central_directory(apk_data, &eocd).unwrap();
+        assert_eq!(read_entry_data(apk_data, &entries[0]).unwrap(), b"dex");
+    }
+
+    #[test]
+    fn vasdolly_v1_comment_round_trip() {
+        let apk = build_zip(FILES, &[]);
+        assert_eq!(read_vasdolly_v1_channel(&apk).unwrap(), None);
+
+        let apk = write_vasdolly_v1_channel(&apk, Some("huawei")).unwrap();
+        assert_eq!(
+            read_vasdolly_v1_channel(&apk).unwrap().as_deref(),
+            Some("huawei")
+        );
+        assert_entries_intact(&apk);
+
+        // a new channel replaces the old one
+        let apk = write_vasdolly_v1_channel(&apk, Some("xiaomi")).unwrap();
+        assert_eq!(
+            read_vasdolly_v1_channel(&apk).unwrap().as_deref(),
+            Some("xiaomi")
+        );
+        assert_eq!(find_eocd(&apk).unwrap().comment.len(), 6 + 2 + 8);
+
+        let apk = write_vasdolly_v1_channel(&apk, None).unwrap();
+        assert_eq!(read_vasdolly_v1_channel(&apk).unwrap(), None);
+        assert!(find_eocd(&apk).unwrap().comment.is_empty());
+
+        assert!(write_vasdolly_v1_channel(&v2_signed_apk(), Some("huawei")).is_err());
+    }
+
+    #[test]
+    fn vasdolly_v2_block_round_trip() {
+        assert!(write_vasdolly_v2_channel(&build_zip(FILES, &[]), Some("huawei")).is_err());
+
+        let apk = write_vasdolly_v2_channel(&v2_signed_apk(), Some("huawei")).unwrap();
+        assert_eq!(
+            read_vasdolly_v2_channel(&apk).unwrap().as_deref(),
+            Some("huawei")
+        );
+        assert!(is_v2_signed(&apk).unwrap());
+        assert_entries_intact(&apk);
+
+        let apk = write_vasdolly_v2_channel(&apk, None).unwrap();
+        assert_eq!(read_vasdolly_v2_channel(&apk).unwrap(), None);
+        assert!(is_v2_signed(&apk).unwrap());
+    }
+
+    #[test]
+    fn walle_block_round_trip() {
+        let extras = WalleExtras::from([
+            ("buildId".to_string(), serde_json::json!("42")),
+            ("flavors".to_string(), serde_json::json!(["cn", "eu"])),
+        ]);
+
+        let apk = write_walle_channel(&v2_signed_apk(), Some("huawei"), &extras).unwrap();
+        let (channel, read_extras) = read_walle_channel(&apk).unwrap().unwrap();
+        assert_eq!(channel.as_deref(), Some("huawei"));
+        assert_eq!(read_extras, extras);
+        assert_entries_intact(&apk);
+
+        let apk = write_walle_channel(&apk, None, &BTreeMap::new()).unwrap();
+        assert_eq!(read_walle_channel(&apk).unwrap(), None);
+    }
+
+    #[test]
+    fn walle_channel_must_be_a_string() {
+        let apk = build_zip(
+            FILES,
+            &[
+                (APK_SIGNATURE_SCHEME_V2_BLOCK_ID, b"v2".to_vec()),
+                (WALLE_CHANNEL_BLOCK_ID, br#"{"channel":7}"#.to_vec()),
+            ],
+        );
+
+        let e = read_walle_channel(&apk).unwrap_err();
+        assert_eq!(e.to_string(), "walle channel is not a string: 7");
+    }
+
+    #[test]
+    fn walle_extras_without_channel() {
+        let folder = TestFolder::new("walle-extras".as_ref());
+        let payload = br#"{"buildId":"42","debug":true}"#.to_vec();
+        let apk_file = folder.write(
+            "extras.apk",
+            &build_zip(
+                FILES,
+                &[
+                    (APK_SIGNATURE_SCHEME_V2_BLOCK_ID, b"v2".to_vec()),
+                    (WALLE_CHANNEL_BLOCK_ID, payload),
+                ],
+            ),
+        );
+
+        let channel_info = get_apk_channel(&apk_file).unwrap();
+        assert_eq!(channel_info.scheme, ChannelScheme::Walle);
+        assert_eq!(channel_info.channel, None);
+        assert_eq!(channel_info.extras["buildId"], "42");
+        assert_eq!(channel_info.extras["debug"], true);
+
+        // the extras survive a rewrite without a channel, with their types
+        let extras = channel_info.extras.clone();
+        set_apk_channel(&apk_file, &channel_info).unwrap();
+        let channel_info = get_apk_channel(&apk_file).unwrap();
+        assert_eq!(channel_info.channel, None);
+        assert_eq!(channel_info.extras, extras);
+
+        set_apk_channel(
+            &apk_file,
+            &ChannelInfo {
+                scheme: ChannelScheme::VasDollyV2,
+                channel: Some("huawei".to_string()),
+                extras: BTreeMap::new(),
+            },
+        )
+        .unwrap();
+        let channel_info = get_apk_channel(&apk_file).unwrap();
+        assert_eq!(channel_info.scheme, ChannelScheme::VasDollyV2);
+        assert!(read_walle_channel(&std::fs::read(&apk_file).unwrap())
+            .unwrap()
+            .is_none());
+    }
+}
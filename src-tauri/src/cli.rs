@@ -0,0 +1,635 @@
+use anyhow::Result;
+use std::{
+    future::Future,
+    path::Path,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+};
+
+use crate::{
+    apk_util::{get_apk_info, unpack_apk},
+    badge_util::{BadgeStyle, IconBadge},
+    channel_util::{get_apk_channel, read_channel_file, ChannelScheme},
+    constants::TOOL_TIMEOUT_ENV,
+    decode_cache::clear_decode_cache,
+    error::RepackError,
+    icon_util::{AdaptiveIconOptions, IconBackground},
+    java_util::{check_java_home, find_java_runtimes},
+    job::{create_job, finish_job, set_progress_sink, JobProgress, StageStatus},
+    meta_data_util::MetaDataEdit,
+    prepare::extract_tools,
+    recipe::{load_recipe, save_recipe, Recipe},
+    repack::{do_change_content_and_repack, RepackParams},
+    settings::{get_settings, get_settings_path, override_settings, update_settings, Settings},
+    sign_config::{SignConfig, KEY_PASSWORD_ENV, STORE_PASSWORD_ENV},
+};
+
+pub const EXIT_OK: i32 = 0;
+/// the job ran and failed
+pub const EXIT_FAILED: i32 = 1;
+/// bad command line, nothing ran
+pub const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str = "usage: tauri-repack <command> [options]
+
+commands:
+  repack     unpack, edit, pack, sign and write channels, prints the outputs
+  info       print package, names, version and icons of an apk as json
+  channel    print the channel of an apk as json
+  java       list the javas found on this machine as json
+  settings [<json>]
+             print the saved settings as json, or check and save the
+             settings of the json file
+  clear-cache
+             delete the decoded apks kept between runs
+  help       print this help
+
+common options:
+  --in <apk>                    input apk, required
+  --verbose                     log every step to stderr
+  --tool-timeout <seconds>      kill apktool after this, or REPACK_TOOL_TIMEOUT,
+                                30 minutes by default
+  --java-home <dir>             run apktool with this java, by default the one
+                                of the settings, JAVA_HOME or the newest found
+
+decoded apks are reused by later runs, REPACK_DECODE_CACHE_MB sets the size of
+the cache, 2048 by default, 0 turns it off
+
+repack options:
+  --recipe <toml|json>          run the steps of a recipe, the options below
+                                override them
+  --save-recipe <toml|json>     also write the options as a recipe, without
+                                the input apk and passwords
+  --out <dir>                   output folder, next to the input by default
+  --package <name>              new package name
+  --rename-references           also rename authorities, permissions, ...
+  --name <label>                new display name of the default locale
+  --name-locale <values-xx>=<label>
+                                display name of one values folder, repeatable
+  --icon <png|webp|jpg|svg>     new icon
+  --adaptive-icon               build adaptive icon layers from the icon
+  --icon-background <color|path>
+                                background of the adaptive icon, #FFFFFF
+  --monochrome                  add a monochrome layer for themed icons
+  --badge <text>                stamp a badge on the icon, e.g. DEV
+  --badge-style <ribbon|tint>
+  --badge-color <color>
+  --badge-text-color <color>
+  --version-code <n>            new version code
+  --bump-version-code           version code + 1
+  --version-name <name>
+  --min-sdk <n>
+  --target-sdk <n>
+  --meta-set <name>=<value>     change application meta-data, repeatable
+  --meta-add <name>=<value>     add application meta-data, repeatable
+  --meta-remove <name>          remove application meta-data, repeatable
+  --channel <name>              one output per channel, repeatable
+  --channel-file <path>         channels one per line
+  --channel-scheme <none|vasdolly_v1|vasdolly_v2|walle>
+  --channel-extra <key>=<value> walle extra value, repeatable
+  --output-name <template>      e.g. {package}-{channel}.apk
+  --keystore <path>             sign with this keystore instead of the bundled one
+  --store-pass <password>       or REPACK_STORE_PASSWORD
+  --key-alias <alias>
+  --key-pass <password>         or REPACK_KEY_PASSWORD
+
+exit codes: 0 success, 1 the job failed, 2 bad command line";
+
+const USAGE_HINT: &str = "run `tauri-repack help` for the options";
+
+/// Whether the arguments ask for the command line instead of the window.
+#[cfg(feature = "gui")]
+pub fn is_cli_command(args: &[String]) -> bool {
+    args.first().is_some_and(|command| {
+        matches!(
+            command.as_str(),
+            "repack"
+                | "info"
+                | "channel"
+                | "java"
+                | "settings"
+                | "clear-cache"
+                | "help"
+                | "--help"
+                | "-h"
+        )
+    })
+}
+
+/// Logs to stderr, stdout only has the results.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future on this thread, the pipeline has no runtime of its own.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+fn split_pair<'a>(flag: &str, value: &'a str) -> Result<(&'a str, &'a str)> {
+    value
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("{} expects <key>=<value>, got {}", flag, value))
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<u32> {
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("{} expects a number, got {}", flag, value))
+}
+
+fn parse_enum<T: serde::de::DeserializeOwned>(flag: &str, value: &str) -> Result<T> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| anyhow::anyhow!("bad {} value: {}", flag, value))
+}
+
+struct CliOptions {
+    params: RepackParams,
+    verbose: bool,
+    save_recipe_path: Option<String>,
+    tool_timeout: Option<u32>,
+    java_home: Option<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<CliOptions> {
+    // the recipe is the base, flags next to it override its steps
+    let mut params = match args.iter().position(|arg| arg == "--recipe") {
+        Some(index) => {
+            let recipe_path = args
+                .get(index + 1)
+                .ok_or_else(|| anyhow::anyhow!("--recipe expects a value"))?;
+            load_recipe(Path::new(recipe_path))?.to_params("")?
+        }
+        None => RepackParams::default(),
+    };
+    let mut verbose = false;
+    let mut save_recipe_path = None;
+    let mut tool_timeout = None;
+    let mut java_home = None;
+    let mut icon_background = None;
+    let mut monochrome = false;
+    let mut adaptive_icon = false;
+    let mut badge: Option<IconBadge> = params.icon_badge.clone();
+    let mut keystore_path: Option<String> = None;
+    let mut store_password: Option<String> = None;
+    let mut key_alias: Option<String> = None;
+    let mut key_password: Option<String> = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(String::as_str)
+                .ok_or_else(|| anyhow::anyhow!("{} expects a value", flag))
+        };
+        let flag = flag.as_str();
+
+        match flag {
+            "--verbose" => verbose = true,
+            "--tool-timeout" => tool_timeout = Some(parse_number(flag, value()?)?),
+            "--java-home" => java_home = Some(value()?.to_string()),
+            "--in" => params.apk_file_path = value()?.to_string(),
+            "--recipe" => {
+                value()?;
+            }
+            "--save-recipe" => save_recipe_path = Some(value()?.to_string()),
+            "--out" => params.output_folder = value()?.to_string(),
+            "--package" => params.apk_package_name = value()?.to_string(),
+            "--rename-references" => params.rename_package_references = true,
+            "--name" => params.apk_display_name = value()?.to_string(),
+            "--name-locale" => {
+                let (folder, name) = split_pair(flag, value()?)?;
+                params
+                    .apk_display_names
+                    .insert(folder.to_string(), name.to_string());
+            }
+            "--icon" => params.apk_icon_file_path = value()?.to_string(),
+            "--adaptive-icon" => adaptive_icon = true,
+            "--icon-background" => icon_background = Some(value()?.to_string()),
+            "--monochrome" => monochrome = true,
+            "--badge" => badge.get_or_insert_with(IconBadge::default).text = value()?.to_string(),
+            "--badge-style" => {
+                badge.get_or_insert_with(IconBadge::default).style =
+                    parse_enum::<BadgeStyle>(flag, value()?)?
+            }
+            "--badge-color" => {
+                badge.get_or_insert_with(IconBadge::default).color = value()?.to_string()
+            }
+            "--badge-text-color" => {
+                badge.get_or_insert_with(IconBadge::default).text_color = value()?.to_string()
+            }
+            "--version-code" => params.apk_version_code = Some(parse_number(flag, value()?)?),
+            "--bump-version-code" => params.auto_increase_version_code = true,
+            "--version-name" => params.apk_version_name = value()?.to_string(),
+            "--min-sdk" => params.apk_min_sdk_version = Some(parse_number(flag, value()?)?),
+            "--target-sdk" => params.apk_target_sdk_version = Some(parse_number(flag, value()?)?),
+            "--meta-set" | "--meta-add" => {
+                let (name, meta_value) = split_pair(flag, value()?)?;
+                let (component, name, value) =
+                    (String::new(), name.to_string(), meta_value.to_string());
+                params.meta_data_edits.push(if flag == "--meta-set" {
+                    MetaDataEdit::Set {
+                        component,
+                        name,
+                        value,
+                    }
+                } else {
+                    MetaDataEdit::Add {
+                        component,
+                        name,
+                        value,
+                    }
+                });
+            }
+            "--meta-remove" => params.meta_data_edits.push(MetaDataEdit::Remove {
+                component: String::new(),
+                name: value()?.to_string(),
+            }),
+            "--channel" => params.channels.push(value()?.to_string()),
+            "--channel-file" => params
+                .channels
+                .append(&mut read_channel_file(Path::new(value()?))?),
+            "--channel-scheme" => {
+                params.channel_scheme = Some(parse_enum::<ChannelScheme>(flag, value()?)?)
+            }
+            "--channel-extra" => {
+                let (key, extra) = split_pair(flag, value()?)?;
+                params
+                    .channel_extras
+                    .insert(key.to_string(), extra.to_string());
+            }
+            "--output-name" => params.output_name_template = value()?.to_string(),
+            "--keystore" => keystore_path = Some(value()?.to_string()),
+            "--store-pass" => store_password = Some(value()?.to_string()),
+            "--key-alias" => key_alias = Some(value()?.to_string()),
+            "--key-pass" => key_password = Some(value()?.to_string()),
+            _ => return Err(anyhow::anyhow!("unknown option {}", flag)),
+        }
+    }
+
+    if params.apk_file_path.is_empty() {
+        return Err(anyhow::anyhow!("--in is required"));
+    }
+
+    if adaptive_icon {
+        params.adaptive_icon = Some(AdaptiveIconOptions {
+            background: match icon_background {
+                Some(background) if background.starts_with('#') => {
+                    IconBackground::Color(background)
+                }
+                Some(background) => IconBackground::Image(background),
+                None => IconBackground::default(),
+            },
+            monochrome,
+        });
+    } else if icon_background.is_some() || monochrome {
+        return Err(anyhow::anyhow!(
+            "--icon-background and --monochrome need --adaptive-icon"
+        ));
+    }
+    params.icon_badge = badge;
+
+    if let Some(keystore_path) = keystore_path {
+        let store_password = store_password
+            .or_else(|| std::env::var(STORE_PASSWORD_ENV).ok())
+            .unwrap_or_default();
+        params.sign_config = Some(SignConfig {
+            keystore_path,
+            key_password: key_password
+                .or_else(|| std::env::var(KEY_PASSWORD_ENV).ok())
+                // keystores made by keytool often share one password
+                .unwrap_or_else(|| store_password.clone()),
+            store_password,
+            key_alias: key_alias.unwrap_or_default(),
+        });
+    } else if let Some(sign_config) = &mut params.sign_config {
+        if let Some(store_password) = store_password {
+            sign_config.store_password = store_password;
+        }
+        if let Some(key_alias) = key_alias {
+            sign_config.key_alias = key_alias;
+        }
+        if let Some(key_password) = key_password {
+            sign_config.key_password = key_password;
+        }
+    } else if store_password.is_some() || key_alias.is_some() || key_password.is_some() {
+        return Err(anyhow::anyhow!(
+            "signing options need --keystore or a sign step in the recipe"
+        ));
+    }
+
+    Ok(CliOptions {
+        params,
+        verbose,
+        save_recipe_path,
+        tool_timeout,
+        java_home,
+    })
+}
+
+fn init_logger(verbose: bool) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(if verbose {
+            log::LevelFilter::Info
+        } else {
+            log::LevelFilter::Warn
+        });
+    }
+}
+
+/// Stage timings on stderr, stdout only lists the outputs.
+fn print_progress(progress: &JobProgress) {
+    if progress.status == StageStatus::Finished {
+        let elapsed_ms = progress.elapsed_ms.unwrap_or_default();
+        eprintln!(
+            "{:?} done in {:.1}s",
+            progress.stage,
+            elapsed_ms as f64 / 1000.0
+        );
+    }
+}
+
+fn run_repack(params: &RepackParams) -> Result<()> {
+    extract_tools()?;
+    set_progress_sink(Box::new(print_progress));
+    let job_id = create_job()?;
+    let outputs = block_on(do_change_content_and_repack(params, &job_id));
+    // the failure of the pipeline is the one to report
+    if let Err(e) = finish_job(&job_id) {
+        log::warn!("{:?}", e);
+    }
+
+    for output in outputs? {
+        println!("{}", output.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+fn run_info(params: &RepackParams) -> Result<()> {
+    extract_tools()?;
+    let job_id = create_job()?;
+    let apk_info = block_on(async {
+        let apk_folder = unpack_apk(&job_id, &params.apk_file_path).await?;
+        get_apk_info(&apk_folder).await
+    });
+    if let Err(e) = finish_job(&job_id) {
+        log::warn!("{:?}", e);
+    }
+
+    println!("{}", serde_json::to_string_pretty(&apk_info?)?);
+
+    Ok(())
+}
+
+fn run_channel(params: &RepackParams) -> Result<()> {
+    let channel_info = get_apk_channel(Path::new(&params.apk_file_path))?;
+    println!("{}", serde_json::to_string_pretty(&channel_info)?);
+
+    Ok(())
+}
+
+/// Prints the settings, or saves the ones of `settings_path` when they are
+/// valid.
+fn run_settings(settings_path: Option<&str>) -> Result<()> {
+    let settings = match settings_path {
+        Some(settings_path) => {
+            let content = std::fs::read_to_string(settings_path)?;
+            update_settings(serde_json::from_str::<Settings>(&content)?)?
+        }
+        None => get_settings(),
+    };
+    eprintln!("{}", get_settings_path()?.to_string_lossy());
+    println!("{}", serde_json::to_string_pretty(&settings)?);
+
+    Ok(())
+}
+
+/// Runs a command line and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    let Some((command, options)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return EXIT_USAGE;
+    };
+
+    let runner: fn(&RepackParams) -> Result<()> = match command.as_str() {
+        "repack" => run_repack,
+        "info" => run_info,
+        "channel" => run_channel,
+        "java" => {
+            return match serde_json::to_string_pretty(&find_java_runtimes()) {
+                Ok(runtimes) => {
+                    println!("{}", runtimes);
+                    EXIT_OK
+                }
+                Err(e) => {
+                    eprintln!("error: {:#}", e);
+                    EXIT_FAILED
+                }
+            };
+        }
+        "settings" => {
+            let settings_path = match options {
+                [] => None,
+                [settings_path] => Some(settings_path.as_str()),
+                _ => {
+                    eprintln!("settings takes one json file\n{}", USAGE_HINT);
+                    return EXIT_USAGE;
+                }
+            };
+            return match run_settings(settings_path) {
+                Ok(()) => EXIT_OK,
+                Err(e) => {
+                    let error = RepackError::from(e);
+                    eprintln!("error[{}]: {}", error.code(), error);
+                    EXIT_FAILED
+                }
+            };
+        }
+        "clear-cache" => {
+            return match clear_decode_cache() {
+                Ok(size) => {
+                    println!("{:.1} MB freed", size as f64 / 1024.0 / 1024.0);
+                    EXIT_OK
+                }
+                Err(e) => {
+                    eprintln!("error: {:#}", e);
+                    EXIT_FAILED
+                }
+            };
+        }
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            return EXIT_OK;
+        }
+        _ => {
+            eprintln!("unknown command {}\n{}", command, USAGE_HINT);
+            return EXIT_USAGE;
+        }
+    };
+
+    let options = match parse_options(options) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE_HINT);
+            return EXIT_USAGE;
+        }
+    };
+    init_logger(options.verbose);
+    if let Some(tool_timeout) = options.tool_timeout {
+        std::env::set_var(TOOL_TIMEOUT_ENV, tool_timeout.to_string());
+    }
+    if let Some(java_home) = &options.java_home {
+        if let Err(e) = check_java_home(java_home) {
+            let error = RepackError::from(e);
+            eprintln!("error[{}]: {}", error.code(), error);
+            return EXIT_FAILED;
+        }
+        override_settings(|settings| settings.java_home = java_home.clone());
+    }
+
+    if let Some(recipe_path) = &options.save_recipe_path {
+        let recipe = Recipe::from_params(&options.params);
+        if let Err(e) = save_recipe(Path::new(recipe_path), &recipe) {
+            eprintln!("error: {:#}", e);
+            return EXIT_FAILED;
+        }
+    }
+
+    match runner(&options.params) {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            let error = RepackError::from(e);
+            eprintln!("error[{}]: {}", error.code(), error);
+            EXIT_FAILED
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn options_fill_the_params() {
+        let options = parse_options(&args(
+            "--in demo.apk --verbose --package com.example.qa --name-locale values-ja=デモ \
+             --badge QA --badge-style tint --version-code 42 --meta-remove CHANNEL \
+             --channel google --channel huawei --channel-scheme walle --channel-extra region=eu \
+             --tool-timeout 60",
+        ))
+        .unwrap();
+
+        assert!(options.verbose);
+        assert_eq!(options.tool_timeout, Some(60));
+        let params = options.params;
+        assert_eq!(params.apk_file_path, "demo.apk");
+        assert_eq!(params.apk_package_name, "com.example.qa");
+        assert_eq!(params.apk_display_names["values-ja"], "デモ");
+        let badge = params.icon_badge.unwrap();
+        assert_eq!((badge.text.as_str(), badge.style), ("QA", BadgeStyle::Tint));
+        assert_eq!(params.apk_version_code, Some(42));
+        assert!(matches!(
+            params.meta_data_edits.as_slice(),
+            [MetaDataEdit::Remove { name, .. }] if name == "CHANNEL"
+        ));
+        assert_eq!(params.channels, ["google", "huawei"]);
+        assert_eq!(params.channel_scheme, Some(ChannelScheme::Walle));
+        assert_eq!(params.channel_extras["region"], "eu");
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        for line in [
+            "--package com.example",
+            "--in demo.apk --unknown",
+            "--in demo.apk --version-code many",
+            "--in demo.apk --channel-scheme apk",
+            "--in demo.apk --name-locale values-ja",
+            "--in demo.apk --monochrome",
+            "--in demo.apk --key-alias release",
+            "--in demo.apk --out",
+        ] {
+            assert!(parse_options(&args(line)).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn flags_override_the_recipe() {
+        let folder = TestFolder::new("cli-recipe".as_ref());
+        let recipe_path = folder.write(
+            "recipe.json",
+            br#"{"steps": [
+                {"op": "package", "name": "com.example.recipe"},
+                {"op": "badge", "text": "QA"},
+                {"op": "channels", "names": ["google"]}
+            ]}"#,
+        );
+
+        let options = parse_options(&args(&format!(
+            "--recipe {} --in demo.apk --package com.example.flag --badge-color #000000",
+            recipe_path.to_string_lossy()
+        )))
+        .unwrap();
+
+        let params = options.params;
+        assert_eq!(params.apk_file_path, "demo.apk");
+        assert_eq!(params.apk_package_name, "com.example.flag");
+        let badge = params.icon_badge.unwrap();
+        assert_eq!(
+            (badge.text.as_str(), badge.color.as_str()),
+            ("QA", "#000000")
+        );
+        assert_eq!(params.channels, ["google"]);
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(run(&args("help")), EXIT_OK);
+        assert_eq!(run(&[]), EXIT_USAGE);
+        assert_eq!(run(&args("unpack --in demo.apk")), EXIT_USAGE);
+        assert_eq!(run(&args("channel")), EXIT_USAGE);
+        assert_eq!(run(&args("settings a.json b.json")), EXIT_USAGE);
+
+        let folder = TestFolder::new("cli-exit".as_ref());
+        let apk_path = folder.0.join("missing.apk");
+        let line = format!("channel --in {}", apk_path.to_string_lossy());
+        assert_eq!(run(&args(&line)), EXIT_FAILED);
+    }
+}
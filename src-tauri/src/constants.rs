@@ -1,23 +1,34 @@
 use std::sync::OnceLock;
 
+use crate::settings::get_settings;
+
 pub const APKTOOL_JAR_BYTES: &[u8] = include_bytes!("../apktool.jar");
 pub const APKTOOL_NAME: &str = "apktool.jar";
 
-pub const VASDOLLY_JAR_BYTES: &[u8] = include_bytes!("../vasdolly.jar");
-pub const VASDOLLY_NAME: &str = "vasdolly.jar";
-
-pub const APKSIGNER_JAR_BYTES: &[u8] = include_bytes!("../uber-apk-signer.jar");
-pub const APKSIGNER_NAME: &str = "uber-apk-signer.jar";
-
 pub const KEYSTORE_BYTES: &[u8] = include_bytes!("../key.keystore");
 pub const KEYSTORE_NAME: &str = "key.keystore";
 pub const KEYSTORE_PWD: &str = "123456";
 pub const KEYSTORE_ALIAS_NAME: &str = "key";
 
+pub const DEFAULT_OUTPUT_NAME_TEMPLATE: &str = "{package}-{channel}.apk";
+
+/// seconds a tool may run before it is killed
+pub const TOOL_TIMEOUT_ENV: &str = "REPACK_TOOL_TIMEOUT";
+pub const DEFAULT_TOOL_TIMEOUT_SECS: u64 = 30 * 60;
+
+/// megabytes of decoded apks kept between runs, 0 turns the cache off
+pub const DECODE_CACHE_LIMIT_ENV: &str = "REPACK_DECODE_CACHE_MB";
+pub const DEFAULT_DECODE_CACHE_LIMIT_MB: u64 = 2048;
+const DECODE_CACHE_NAME: &str = "decode-cache";
+
 pub fn exe_folder() -> &'static std::path::PathBuf {
     static EXE_FOLDER: OnceLock<std::path::PathBuf> = OnceLock::new();
     EXE_FOLDER.get_or_init(|| {
-        let mut path = std::env::current_exe().unwrap();
+        // relative to the working directory when the exe path is unknown
+        let mut path = std::env::current_exe().unwrap_or_else(|e| {
+            log::error!("current_exe: {}", e);
+            std::path::PathBuf::from("./repack")
+        });
         path.pop();
         if path.ends_with("deps") {
             path.pop();
@@ -30,14 +41,6 @@ pub fn get_apktool_jar_path() -> std::path::PathBuf {
     exe_folder().join(APKTOOL_NAME)
 }
 
-pub fn get_vasdolly_jar_path() -> std::path::PathBuf {
-    exe_folder().join(VASDOLLY_NAME)
-}
-
-pub fn get_apksigner_jar_path() -> std::path::PathBuf {
-    exe_folder().join(APKSIGNER_NAME)
-}
-
 pub fn get_key_store_path() -> std::path::PathBuf {
     exe_folder().join(KEYSTORE_NAME)
 }
@@ -48,30 +51,42 @@ pub fn get_cache_folder() -> &'static std::path::PathBuf {
         let mut path = exe_folder().clone();
         let time_str = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
         path.push(time_str);
-        if !path.exists() {
-            std::fs::create_dir_all(&path).unwrap();
+        // creating a job fails later with the reason
+        if let Err(e) = std::fs::create_dir_all(&path) {
+            log::error!("create {}: {}", path.to_string_lossy(), e);
         }
         path
     })
 }
 
-pub fn get_java_exe() -> anyhow::Result<String> {
-    let java_home = std::env::var("JAVA_HOME").unwrap_or("".to_string());
-    if java_home.is_empty() {
-        return Err(anyhow::anyhow!("JAVA_HOME not found"));
-    }
+/// Decoded apks outlive the cache folder of a run, so they sit next to the
+/// exe.
+pub fn get_decode_cache_folder() -> std::path::PathBuf {
+    exe_folder().join(DECODE_CACHE_NAME)
+}
 
-    let java_path = if cfg!(target_os = "windows") {
-        std::path::Path::new(&java_home)
-            .join("bin")
-            .join("javaw.exe")
-    } else {
-        std::path::Path::new(&java_home).join("bin").join("java")
+/// `REPACK_TOOL_TIMEOUT` seconds, the settings when unset.
+pub fn get_tool_timeout() -> std::time::Duration {
+    let settings_secs = || get_settings().tool_timeout_secs;
+    let secs = match std::env::var(TOOL_TIMEOUT_ENV) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!("bad {}: {}", TOOL_TIMEOUT_ENV, value);
+            settings_secs()
+        }),
+        Err(_) => settings_secs(),
     };
+    std::time::Duration::from_secs(secs.max(1))
+}
 
-    if !java_path.exists() {
-        return Err(anyhow::anyhow!("java not found"));
-    }
-
-    Ok(java_path.to_str().unwrap().to_string())
+/// `REPACK_DECODE_CACHE_MB` in bytes, the settings when unset.
+pub fn get_decode_cache_limit() -> u64 {
+    let settings_mb = || get_settings().decode_cache_mb;
+    let mb = match std::env::var(DECODE_CACHE_LIMIT_ENV) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!("bad {}: {}", DECODE_CACHE_LIMIT_ENV, value);
+            settings_mb()
+        }),
+        Err(_) => settings_mb(),
+    };
+    mb * 1024 * 1024
 }
@@ -0,0 +1,409 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    path::Path,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::constants::{get_decode_cache_folder, get_decode_cache_limit, APKTOOL_JAR_BYTES};
+
+// Every entry is a folder named `{apk sha256}-{tool version}` holding the
+// untouched apktool output in `original` and `entry.json`. Entries are
+// written aside and renamed into place, so a folder with `entry.json` is
+// always complete. Jobs copy `original` into their workspace and edit the
+// copy.
+
+/// bump when the decode arguments change, older entries are dropped
+const DECODE_FORMAT: u32 = 1;
+const ENTRY_FILE: &str = "entry.json";
+const DECODED_FOLDER: &str = "original";
+const TMP_INFIX: &str = ".tmp-";
+/// leftovers of a store that never finished
+const TMP_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    apk_sha256: String,
+    tool_version: String,
+    /// where the apk was when it was decoded, for the log
+    apk_file_path: String,
+    /// bytes of `original`
+    size: u64,
+    /// unix seconds, the least recently used entries go first
+    last_used: u64,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// The bundled apktool and the decode format, a new one makes every entry
+/// stale.
+fn tool_version() -> &'static str {
+    static TOOL_VERSION: OnceLock<String> = OnceLock::new();
+    TOOL_VERSION.get_or_init(|| {
+        let jar_sha256 = to_hex(&Sha256::digest(APKTOOL_JAR_BYTES));
+        format!("apktool{}.{}", &jar_sha256[..12], DECODE_FORMAT)
+    })
+}
+
+fn apk_sha256(apk_file_path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(apk_file_path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Names the cache entry of an apk by its content, a changed apk never
+/// reuses an old decode.
+pub struct DecodeKey {
+    apk_sha256: String,
+}
+
+impl DecodeKey {
+    pub fn new(apk_file_path: &Path) -> Result<Self> {
+        Ok(DecodeKey {
+            apk_sha256: apk_sha256(apk_file_path)?,
+        })
+    }
+
+    fn folder_name(&self) -> String {
+        format!("{}-{}", self.apk_sha256, tool_version())
+    }
+}
+
+fn read_entry(entry_folder: &Path) -> Result<CacheEntry> {
+    let content = std::fs::read_to_string(entry_folder.join(ENTRY_FILE))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn write_entry(entry_folder: &Path, entry: &CacheEntry) -> Result<()> {
+    std::fs::write(
+        entry_folder.join(ENTRY_FILE),
+        serde_json::to_string_pretty(entry)?,
+    )?;
+    Ok(())
+}
+
+/// Copies a folder tree, returns the bytes copied.
+fn copy_dir(src: &Path, des: &Path) -> Result<u64> {
+    std::fs::create_dir_all(des)?;
+
+    let mut size = 0;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let des = des.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            size += copy_dir(&entry.path(), &des)?;
+        } else {
+            size += std::fs::copy(entry.path(), des)?;
+        }
+    }
+
+    Ok(size)
+}
+
+fn dir_size(folder: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(folder)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(size)
+}
+
+/// Copies the cached decode to `des`, false when there is none or the cache
+/// is off. A broken entry is dropped and the apk decoded again.
+pub fn restore_decode(key: &DecodeKey, des: &Path) -> bool {
+    if get_decode_cache_limit() == 0 {
+        return false;
+    }
+
+    restore_decode_from(&get_decode_cache_folder(), key, des)
+}
+
+fn restore_decode_from(cache_folder: &Path, key: &DecodeKey, des: &Path) -> bool {
+    let key = key.folder_name();
+    let entry_folder = cache_folder.join(&key);
+    let Ok(mut entry) = read_entry(&entry_folder) else {
+        return false;
+    };
+
+    if let Err(e) = copy_dir(&entry_folder.join(DECODED_FOLDER), des) {
+        log::warn!("restore decode {}: {}", key, e);
+        let _ = std::fs::remove_dir_all(des);
+        let _ = std::fs::remove_dir_all(&entry_folder);
+        return false;
+    }
+    log::info!("decode of {} restored from {}", entry.apk_file_path, key);
+
+    entry.last_used = now_secs();
+    if let Err(e) = write_entry(&entry_folder, &entry) {
+        log::warn!("touch decode {}: {}", key, e);
+    }
+
+    true
+}
+
+/// Keeps a copy of a fresh decode in `src` before it is edited, then trims
+/// the cache to its limit. Failures only cost the next decode.
+pub fn store_decode(key: &DecodeKey, job_id: &str, apk_file_path: &str, src: &Path) {
+    let limit = get_decode_cache_limit();
+    if limit == 0 {
+        return;
+    }
+
+    let cache_folder = get_decode_cache_folder();
+    if let Err(e) = try_store_decode(&cache_folder, key, job_id, apk_file_path, src, limit) {
+        log::warn!("store decode {}: {}", key.folder_name(), e);
+    }
+}
+
+fn write_entry_folder(
+    key: &DecodeKey,
+    apk_file_path: &str,
+    src: &Path,
+    entry_folder: &Path,
+    limit: u64,
+) -> Result<()> {
+    let size = copy_dir(src, &entry_folder.join(DECODED_FOLDER))?;
+    if size > limit {
+        return Err(anyhow::anyhow!(
+            "{} bytes decoded, above the cache limit",
+            size
+        ));
+    }
+
+    write_entry(
+        entry_folder,
+        &CacheEntry {
+            apk_sha256: key.apk_sha256.clone(),
+            tool_version: tool_version().to_string(),
+            apk_file_path: apk_file_path.to_string(),
+            size,
+            last_used: now_secs(),
+        },
+    )
+}
+
+fn try_store_decode(
+    cache_folder: &Path,
+    key: &DecodeKey,
+    job_id: &str,
+    apk_file_path: &str,
+    src: &Path,
+    limit: u64,
+) -> Result<()> {
+    let folder_name = key.folder_name();
+    let entry_folder = cache_folder.join(&folder_name);
+    if read_entry(&entry_folder).is_ok() {
+        return Ok(());
+    }
+
+    // processes of the command line all number their jobs from 1
+    let tmp_folder = cache_folder.join(format!(
+        "{}{}{}-{}",
+        folder_name,
+        TMP_INFIX,
+        std::process::id(),
+        job_id
+    ));
+    let _ = std::fs::remove_dir_all(&tmp_folder);
+
+    let stored = write_entry_folder(key, apk_file_path, src, &tmp_folder, limit).and_then(|()| {
+        if entry_folder.exists() {
+            std::fs::remove_dir_all(&entry_folder)?;
+        }
+        Ok(std::fs::rename(&tmp_folder, &entry_folder)?)
+    });
+    if stored.is_err() {
+        let _ = std::fs::remove_dir_all(&tmp_folder);
+    }
+    stored?;
+    log::info!("decode of {} stored as {}", apk_file_path, folder_name);
+
+    prune_decode_cache(cache_folder, limit)
+}
+
+/// Drops entries of other tool versions, broken ones and stale leftovers,
+/// then the least recently used entries until the cache fits `limit`.
+fn prune_decode_cache(cache_folder: &Path, limit: u64) -> Result<()> {
+    let mut entries = vec![];
+    for dir_entry in std::fs::read_dir(cache_folder)? {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+        let name = dir_entry.file_name().to_string_lossy().to_string();
+
+        // another job may still be writing it
+        if name.contains(TMP_INFIX) {
+            let age = dir_entry
+                .metadata()?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if age > TMP_MAX_AGE {
+                log::info!("drop decode leftover {}", name);
+                std::fs::remove_dir_all(&path)?;
+            }
+            continue;
+        }
+
+        match read_entry(&path) {
+            Ok(entry) if entry.tool_version == tool_version() => entries.push((path, entry)),
+            _ => {
+                log::info!("drop decode {}", name);
+                if path.is_dir() {
+                    std::fs::remove_dir_all(&path)?;
+                } else {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        }
+    }
+
+    entries.sort_by_key(|(_, entry)| Reverse(entry.last_used));
+    let mut total = 0;
+    for (path, entry) in entries {
+        total += entry.size;
+        if total > limit {
+            log::info!("drop decode of {}, cache full", entry.apk_file_path);
+            std::fs::remove_dir_all(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes every cached decode, returns the bytes freed.
+pub fn clear_decode_cache() -> Result<u64> {
+    log::info!("clear_decode_cache");
+
+    let cache_folder = get_decode_cache_folder();
+    if !cache_folder.exists() {
+        return Ok(0);
+    }
+
+    let size = dir_size(&cache_folder)?;
+    std::fs::remove_dir_all(&cache_folder)?;
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    fn key(apk_sha256: &str) -> DecodeKey {
+        DecodeKey {
+            apk_sha256: apk_sha256.to_string(),
+        }
+    }
+
+    /// a decoded apk of `size` bytes
+    fn decoded(folder: &TestFolder, name: &str, size: usize) -> std::path::PathBuf {
+        folder.write(&format!("{}/AndroidManifest.xml", name), &vec![b'm'; size]);
+        folder.0.join(name)
+    }
+
+    fn cached(cache_folder: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(cache_folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn store_then_restore() {
+        let folder = TestFolder::new("decode-cache-store".as_ref());
+        let cache_folder = folder.0.join("cache");
+        folder.write("src/res/values/strings.xml", b"<resources/>");
+        let src = decoded(&folder, "src", 10);
+
+        try_store_decode(&cache_folder, &key("a"), "1", "a.apk", &src, 1024).unwrap();
+
+        let des = folder.0.join("des");
+        assert!(restore_decode_from(&cache_folder, &key("a"), &des));
+        assert_eq!(
+            std::fs::read(des.join("res/values/strings.xml")).unwrap(),
+            b"<resources/>"
+        );
+        assert_eq!(
+            std::fs::read(des.join("AndroidManifest.xml")).unwrap(),
+            [b'm'; 10]
+        );
+        assert!(!restore_decode_from(
+            &cache_folder,
+            &key("b"),
+            &folder.0.join("other")
+        ));
+
+        // above the limit on its own, never stored
+        assert!(try_store_decode(&cache_folder, &key("b"), "2", "b.apk", &src, 8).is_err());
+        assert_eq!(cached(&cache_folder), [key("a").folder_name()]);
+    }
+
+    #[test]
+    fn prune_drops_the_least_recently_used() {
+        let folder = TestFolder::new("decode-cache-prune".as_ref());
+        let cache_folder = folder.0.join("cache");
+        let src = decoded(&folder, "src", 100);
+
+        for (index, name) in ["old", "new", "newer"].into_iter().enumerate() {
+            try_store_decode(&cache_folder, &key(name), "1", name, &src, 1024).unwrap();
+            let entry_folder = cache_folder.join(key(name).folder_name());
+            let mut entry = read_entry(&entry_folder).unwrap();
+            entry.last_used = index as u64;
+            write_entry(&entry_folder, &entry).unwrap();
+        }
+
+        prune_decode_cache(&cache_folder, 250).unwrap();
+
+        let mut expected = vec![key("new").folder_name(), key("newer").folder_name()];
+        expected.sort();
+        assert_eq!(cached(&cache_folder), expected);
+    }
+
+    #[test]
+    fn other_tool_versions_are_dropped() {
+        let folder = TestFolder::new("decode-cache-version".as_ref());
+        let cache_folder = folder.0.join("cache");
+        let src = decoded(&folder, "src", 10);
+        try_store_decode(&cache_folder, &key("a"), "1", "a.apk", &src, 1024).unwrap();
+
+        // the same apk decoded by an older apktool, and a broken entry
+        let stale_folder = cache_folder.join("b-apktool000000000000.0");
+        copy_dir(&cache_folder.join(key("a").folder_name()), &stale_folder).unwrap();
+        let mut entry = read_entry(&stale_folder).unwrap();
+        entry.apk_sha256 = "b".to_string();
+        entry.tool_version = "apktool000000000000.0".to_string();
+        write_entry(&stale_folder, &entry).unwrap();
+        std::fs::create_dir_all(cache_folder.join("c-broken")).unwrap();
+
+        assert!(!restore_decode_from(
+            &cache_folder,
+            &key("b"),
+            &folder.0.join("des")
+        ));
+        prune_decode_cache(&cache_folder, 1024).unwrap();
+        assert_eq!(cached(&cache_folder), [key("a").folder_name()]);
+    }
+}
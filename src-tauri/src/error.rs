@@ -0,0 +1,156 @@
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use serde_json::json;
+
+use crate::java_util::MIN_JAVA_MAJOR;
+
+/// What went wrong, as the window and the command line report it. The
+/// pipeline works with `anyhow`, a `RepackError` anywhere in the chain
+/// names the failure, anything else is `Internal`.
+#[derive(Debug, Clone)]
+pub enum RepackError {
+    JavaNotFound { detail: String },
+    JavaUnsupported { java_home: String, version: String },
+    DecodeFailed { apk: String, detail: String },
+    BuildFailed { detail: String },
+    SignFailed { detail: String },
+    ChannelFailed { apk: String, detail: String },
+    InvalidPackageName { name: String },
+    IconNotFound { path: String },
+    InvalidIcon { path: String, detail: String },
+    InvalidRecipe { path: String, detail: String },
+    InvalidSettings { path: String, detail: String },
+    JobNotFound { job_id: String },
+    Cancelled { job_id: String },
+    Internal { detail: String },
+}
+
+impl RepackError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RepackError::JavaNotFound { .. } => "JavaNotFound",
+            RepackError::JavaUnsupported { .. } => "JavaUnsupported",
+            RepackError::DecodeFailed { .. } => "DecodeFailed",
+            RepackError::BuildFailed { .. } => "BuildFailed",
+            RepackError::SignFailed { .. } => "SignFailed",
+            RepackError::ChannelFailed { .. } => "ChannelFailed",
+            RepackError::InvalidPackageName { .. } => "InvalidPackageName",
+            RepackError::IconNotFound { .. } => "IconNotFound",
+            RepackError::InvalidIcon { .. } => "InvalidIcon",
+            RepackError::InvalidRecipe { .. } => "InvalidRecipe",
+            RepackError::InvalidSettings { .. } => "InvalidSettings",
+            RepackError::JobNotFound { .. } => "JobNotFound",
+            RepackError::Cancelled { .. } => "Cancelled",
+            RepackError::Internal { .. } => "Internal",
+        }
+    }
+
+    /// The fields of the variant, for the frontend.
+    fn context(&self) -> serde_json::Value {
+        match self {
+            RepackError::JavaNotFound { detail }
+            | RepackError::BuildFailed { detail }
+            | RepackError::SignFailed { detail }
+            | RepackError::Internal { detail } => json!({ "detail": detail }),
+            RepackError::DecodeFailed { apk, detail }
+            | RepackError::ChannelFailed { apk, detail } => {
+                json!({ "apk": apk, "detail": detail })
+            }
+            RepackError::JavaUnsupported { java_home, version } => {
+                json!({ "java_home": java_home, "version": version })
+            }
+            RepackError::InvalidPackageName { name } => json!({ "name": name }),
+            RepackError::IconNotFound { path } => json!({ "path": path }),
+            RepackError::InvalidIcon { path, detail }
+            | RepackError::InvalidRecipe { path, detail }
+            | RepackError::InvalidSettings { path, detail } => {
+                json!({ "path": path, "detail": detail })
+            }
+            RepackError::JobNotFound { job_id } | RepackError::Cancelled { job_id } => {
+                json!({ "job_id": job_id })
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for RepackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepackError::JavaNotFound { detail } => write!(f, "java not found: {}", detail),
+            RepackError::JavaUnsupported { java_home, version } => write!(
+                f,
+                "java {} in {} is too old, apktool needs java {} or later",
+                version, java_home, MIN_JAVA_MAJOR
+            ),
+            RepackError::DecodeFailed { apk, detail } => {
+                write!(f, "decode {} failed: {}", apk, detail)
+            }
+            RepackError::BuildFailed { detail } => write!(f, "build failed: {}", detail),
+            RepackError::SignFailed { detail } => write!(f, "sign failed: {}", detail),
+            RepackError::ChannelFailed { apk, detail } => {
+                write!(f, "channel of {} failed: {}", apk, detail)
+            }
+            RepackError::InvalidPackageName { name } => write!(f, "bad package name: {}", name),
+            RepackError::IconNotFound { path } => write!(f, "icon not found: {}", path),
+            RepackError::InvalidIcon { path, detail } => {
+                write!(f, "bad icon {}: {}", path, detail)
+            }
+            RepackError::InvalidRecipe { path, detail } => {
+                write!(f, "bad recipe {}: {}", path, detail)
+            }
+            RepackError::InvalidSettings { path, detail } => {
+                write!(f, "bad settings {}: {}", path, detail)
+            }
+            RepackError::JobNotFound { job_id } => write!(f, "job not found: {}", job_id),
+            RepackError::Cancelled { job_id } => write!(f, "job {} cancelled", job_id),
+            RepackError::Internal { detail } => write!(f, "{}", detail),
+        }
+    }
+}
+
+impl std::error::Error for RepackError {}
+
+/// `{ "code": "DecodeFailed", "message": "...", "context": { ... } }`
+impl Serialize for RepackError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RepackError", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("context", &self.context())?;
+        state.end()
+    }
+}
+
+impl From<anyhow::Error> for RepackError {
+    fn from(error: anyhow::Error) -> Self {
+        match error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<RepackError>())
+        {
+            Some(repack_error) => repack_error.clone(),
+            None => RepackError::Internal {
+                detail: format!("{:#}", error),
+            },
+        }
+    }
+}
+
+pub trait RepackResultExt<T> {
+    /// Names a failure that has no `RepackError` yet, `make` gets the
+    /// message of the cause.
+    fn or_repack_error(self, make: impl FnOnce(String) -> RepackError) -> anyhow::Result<T>;
+}
+
+impl<T> RepackResultExt<T> for anyhow::Result<T> {
+    fn or_repack_error(self, make: impl FnOnce(String) -> RepackError) -> anyhow::Result<T> {
+        self.map_err(|error| {
+            if error
+                .chain()
+                .any(|cause| cause.downcast_ref::<RepackError>().is_some())
+            {
+                error
+            } else {
+                make(format!("{:#}", error)).into()
+            }
+        })
+    }
+}
@@ -0,0 +1,248 @@
+use std::path::Path;
+
+use tauri::Manager;
+use tauri_plugin_log::LogTarget;
+
+use crate::apk_util::get_apk_info;
+use crate::apk_util::unpack_apk;
+use crate::apk_util::ApkInfo;
+use crate::channel_util::get_apk_channel;
+use crate::channel_util::read_channel_file;
+use crate::channel_util::ChannelInfo;
+use crate::constants::get_cache_folder;
+use crate::error::RepackError;
+use crate::icon_source::check_icon_source;
+use crate::icon_source::IconSourceReport;
+use crate::java_util::find_java_runtimes;
+use crate::java_util::JavaRuntime;
+use crate::job::create_job;
+use crate::job::finish_job;
+use crate::job::run_job;
+use crate::job::set_progress_sink;
+use crate::prepare::extract_tools;
+use crate::recipe::load_recipe;
+use crate::recipe::save_recipe;
+use crate::recipe::Recipe;
+use crate::repack::do_change_content_and_repack;
+use crate::repack::RepackParams;
+use crate::settings::Settings;
+use crate::sign_config::SignConfig;
+
+#[tauri::command]
+fn get_cache_dir() -> String {
+    get_cache_folder().to_string_lossy().to_string()
+}
+
+/// Every java found, supported ones first, runs each of them once.
+#[tauri::command]
+async fn list_java_runtimes() -> Vec<JavaRuntime> {
+    find_java_runtimes()
+}
+
+#[tauri::command]
+fn get_settings() -> Settings {
+    crate::settings::get_settings()
+}
+
+/// Saves the settings when every field is valid, returns what was saved.
+#[tauri::command]
+async fn update_settings(settings: Settings) -> Result<Settings, RepackError> {
+    Ok(crate::settings::update_settings(settings)?)
+}
+
+/// Deletes the decoded apks kept between runs, returns the bytes freed.
+#[tauri::command]
+fn clear_decode_cache() -> Result<u64, RepackError> {
+    Ok(crate::decode_cache::clear_decode_cache()?)
+}
+
+#[tauri::command]
+fn start_job() -> Result<String, RepackError> {
+    Ok(create_job()?)
+}
+
+/// Deletes the workspace, the icon previews of an info job go with it.
+#[tauri::command]
+fn close_job(job_id: String) -> Result<(), RepackError> {
+    Ok(finish_job(&job_id)?)
+}
+
+/// Kills the running tool of the job and deletes its workspace, the command
+/// that runs the job fails with `Cancelled`.
+#[tauri::command]
+fn cancel_job(job_id: String) -> Result<(), RepackError> {
+    Ok(crate::job::cancel_job(&job_id)?)
+}
+
+/// The workspace stays for the icon previews until `close_job`, a failed
+/// job is closed right away.
+#[tauri::command]
+async fn unpack_and_get_apk_info(job_id: String, apk_path: String) -> Result<ApkInfo, RepackError> {
+    let apk_info = async {
+        let _run = run_job(&job_id)?;
+        let apk_folder = unpack_apk(&job_id, &apk_path).await?;
+        get_apk_info(&apk_folder).await
+    }
+    .await;
+
+    if apk_info.is_err() {
+        let _ = finish_job(&job_id);
+    }
+    Ok(apk_info?)
+}
+
+#[tauri::command]
+fn get_apk_channel_info(apk_path: String) -> Result<ChannelInfo, RepackError> {
+    Ok(get_apk_channel(Path::new(&apk_path))?)
+}
+
+#[tauri::command]
+fn check_new_icon(icon_path: String) -> Result<IconSourceReport, RepackError> {
+    Ok(check_icon_source(&icon_path)?)
+}
+
+#[tauri::command]
+fn load_channel_file(channel_file_path: String) -> Result<Vec<String>, RepackError> {
+    Ok(read_channel_file(Path::new(&channel_file_path))?)
+}
+
+#[tauri::command]
+async fn change_content_and_repack_apk(
+    job_id: String,
+    apk_file_path: String,
+    apk_package_name: String,
+    apk_display_name: String,
+    apk_icon_file_path: String,
+) -> Result<Vec<String>, RepackError> {
+    repack_apk(
+        job_id,
+        RepackParams {
+            apk_file_path,
+            apk_package_name,
+            apk_display_name,
+            apk_icon_file_path,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+#[tauri::command]
+async fn change_content_and_repack_apk_with_sign_config(
+    job_id: String,
+    apk_file_path: String,
+    apk_package_name: String,
+    apk_display_name: String,
+    apk_icon_file_path: String,
+    sign_config: SignConfig,
+) -> Result<Vec<String>, RepackError> {
+    repack_apk(
+        job_id,
+        RepackParams {
+            apk_file_path,
+            apk_package_name,
+            apk_display_name,
+            apk_icon_file_path,
+            sign_config: Some(sign_config),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Runs in the workspace of the job and closes it when done, returns the
+/// output apk paths.
+#[tauri::command]
+async fn repack_apk(job_id: String, params: RepackParams) -> Result<Vec<String>, RepackError> {
+    let result = do_change_content_and_repack(&params, &job_id).await;
+    if let Err(e) = finish_job(&job_id) {
+        log::warn!("{:?}", e);
+    }
+
+    let outputs = result.map_err(|e| {
+        log::error!("{:?}", e);
+        RepackError::from(e)
+    })?;
+    for output in &outputs {
+        log::info!("output: {}", output.to_string_lossy());
+    }
+
+    Ok(outputs
+        .iter()
+        .map(|output| output.to_string_lossy().to_string())
+        .collect())
+}
+
+#[tauri::command]
+async fn repack_apk_with_recipe(
+    job_id: String,
+    apk_file_path: String,
+    recipe_path: String,
+) -> Result<Vec<String>, RepackError> {
+    let params =
+        load_recipe(Path::new(&recipe_path)).and_then(|recipe| recipe.to_params(&apk_file_path));
+
+    match params {
+        Ok(params) => repack_apk(job_id, params).await,
+        Err(e) => {
+            let _ = finish_job(&job_id);
+            Err(e.into())
+        }
+    }
+}
+
+#[tauri::command]
+fn save_repack_recipe(params: RepackParams, recipe_path: String) -> Result<(), RepackError> {
+    Ok(save_recipe(
+        Path::new(&recipe_path),
+        &Recipe::from_params(&params),
+    )?)
+}
+
+pub fn run() {
+    if let Err(e) = extract_tools() {
+        log::error!("{:?}", e);
+        return;
+    }
+
+    tauri::Builder::default()
+        .setup(|app| {
+            let app_handle = app.handle();
+            set_progress_sink(Box::new(move |progress| {
+                if let Err(e) = app_handle.emit_all("job-progress", progress) {
+                    log::warn!("emit job-progress: {}", e);
+                }
+            }));
+            Ok(())
+        })
+        .plugin(
+            tauri_plugin_log::Builder::default()
+                .targets([
+                    LogTarget::Folder(get_cache_folder().to_owned()),
+                    LogTarget::Stdout,
+                    LogTarget::Webview,
+                ])
+                .build(),
+        )
+        .invoke_handler(tauri::generate_handler![
+            get_settings,
+            update_settings,
+            get_cache_dir,
+            clear_decode_cache,
+            list_java_runtimes,
+            start_job,
+            close_job,
+            cancel_job,
+            unpack_and_get_apk_info,
+            change_content_and_repack_apk,
+            change_content_and_repack_apk_with_sign_config,
+            repack_apk,
+            repack_apk_with_recipe,
+            save_repack_recipe,
+            get_apk_channel_info,
+            check_new_icon,
+            load_channel_file,
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
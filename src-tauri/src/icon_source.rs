@@ -0,0 +1,256 @@
+use anyhow::Result;
+use image::{imageops, imageops::FilterType, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::RepackError;
+
+/// largest bucket drawn from the source, the xxxhdpi adaptive layer
+const MIN_SOURCE_SIZE: u32 = 432;
+/// sides within 1% of each other count as square
+const SQUARE_TOLERANCE: f32 = 0.01;
+
+/// A new icon image, svg sources are rendered at each size instead of scaled.
+pub enum IconSource {
+    Bitmap(RgbaImage),
+    Svg(Box<usvg::Tree>),
+}
+
+fn is_svg(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| extension == "svg" || extension == "svgz")
+}
+
+impl IconSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let bad_icon = |e: &dyn std::fmt::Display| {
+            anyhow::Error::from(RepackError::InvalidIcon {
+                path: path.to_string(),
+                detail: e.to_string(),
+            })
+        };
+
+        if !Path::new(path).is_file() {
+            return Err(RepackError::IconNotFound {
+                path: path.to_string(),
+            }
+            .into());
+        }
+
+        if is_svg(path) {
+            let data = std::fs::read(path).map_err(|e| bad_icon(&e))?;
+            let mut options = usvg::Options::default();
+            options.fontdb_mut().load_system_fonts();
+            let tree = usvg::Tree::from_data(&data, &options).map_err(|e| bad_icon(&e))?;
+            return Ok(IconSource::Svg(Box::new(tree)));
+        }
+
+        let image = image::open(path).map_err(|e| bad_icon(&e))?.to_rgba8();
+        if image.width() == 0 || image.height() == 0 {
+            return Err(bad_icon(&"empty image"));
+        }
+        Ok(IconSource::Bitmap(image))
+    }
+
+    pub fn width(&self) -> f32 {
+        match self {
+            IconSource::Bitmap(image) => image.width() as f32,
+            IconSource::Svg(tree) => tree.size().width(),
+        }
+    }
+
+    pub fn height(&self) -> f32 {
+        match self {
+            IconSource::Bitmap(image) => image.height() as f32,
+            IconSource::Svg(tree) => tree.size().height(),
+        }
+    }
+
+    /// Draws the whole source into `w` x `h`, bitmaps are resampled with
+    /// Lanczos3.
+    pub fn render(&self, w: u32, h: u32) -> RgbaImage {
+        let (w, h) = (w.max(1), h.max(1));
+        match self {
+            IconSource::Bitmap(image) => imageops::resize(image, w, h, FilterType::Lanczos3),
+            IconSource::Svg(tree) => {
+                let Some(mut pixmap) = tiny_skia::Pixmap::new(w, h) else {
+                    return RgbaImage::new(w, h);
+                };
+                let transform = tiny_skia::Transform::from_scale(
+                    w as f32 / tree.size().width(),
+                    h as f32 / tree.size().height(),
+                );
+                resvg::render(tree, transform, &mut pixmap.as_mut());
+
+                // tiny-skia keeps premultiplied alpha
+                let mut image = RgbaImage::new(w, h);
+                for (pixel, color) in image.pixels_mut().zip(pixmap.pixels()) {
+                    let color = color.demultiply();
+                    pixel.0 = [color.red(), color.green(), color.blue(), color.alpha()];
+                }
+                image
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IconSourceReport {
+    pub width: u32,
+    pub height: u32,
+    pub vector: bool,
+    /// problems worth a look, the icon is still usable
+    pub issues: Vec<String>,
+}
+
+/// Loads a new icon and reports what would make it look bad on a launcher,
+/// fails when it can not be read at all.
+pub fn check_icon_source(path: &str) -> Result<IconSourceReport> {
+    log::info!("check_icon_source: {}", path);
+
+    let source = IconSource::open(path)?;
+    let vector = matches!(source, IconSource::Svg(_));
+    let (width, height) = (source.width(), source.height());
+    let mut issues = vec![];
+
+    if !vector && width.min(height) < MIN_SOURCE_SIZE as f32 {
+        issues.push(format!(
+            "{}x{} is smaller than {}x{}, high densities are upscaled",
+            width, height, MIN_SOURCE_SIZE, MIN_SOURCE_SIZE
+        ));
+    }
+
+    if (width - height).abs() > width.max(height) * SQUARE_TOLERANCE {
+        issues.push(format!(
+            "{}x{} is not square, it is stretched over existing icons and centered in new ones",
+            width.round(),
+            height.round()
+        ));
+    }
+
+    // a small render is enough to find transparent pixels
+    let opaque = |image: &RgbaImage| image.pixels().all(|pixel| pixel.0[3] == u8::MAX);
+    let is_opaque = match &source {
+        IconSource::Bitmap(image) => opaque(image),
+        IconSource::Svg(_) => opaque(&source.render(64, 64)),
+    };
+    if is_opaque {
+        issues.push("no transparency, an adaptive foreground hides the background".to_string());
+    }
+
+    for issue in &issues {
+        log::warn!("icon {}: {}", path, issue);
+    }
+
+    Ok(IconSourceReport {
+        width: width.round() as u32,
+        height: height.round() as u32,
+        vector,
+        issues,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    const CIRCLE_SVG: &[u8] =
+        br##"<svg xmlns="http://www.w3.org/2000/svg" width="24" height="24" viewBox="0 0 24 24">
+    <circle cx="12" cy="12" r="8" fill="#FF0000"/>
+</svg>"##;
+
+    fn png(folder: &TestFolder, name: &str, width: u32, height: u32, alpha: u8) -> String {
+        let path = folder.0.join(name);
+        RgbaImage::from_pixel(width, height, image::Rgba([0, 128, 255, alpha]))
+            .save(&path)
+            .unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn issues(path: &str) -> Vec<String> {
+        check_icon_source(path).unwrap().issues
+    }
+
+    #[test]
+    fn large_square_transparent_bitmap_has_no_issues() {
+        let folder = TestFolder::new("icon-source-good".as_ref());
+        let path = png(&folder, "icon.png", 432, 432, 0);
+
+        let report = check_icon_source(&path).unwrap();
+        assert_eq!(
+            (report.width, report.height, report.vector),
+            (432, 432, false)
+        );
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn bitmap_issues_are_reported() {
+        let folder = TestFolder::new("icon-source-issues".as_ref());
+
+        let small = issues(&png(&folder, "small.png", 100, 100, 0));
+        assert_eq!(small.len(), 1);
+        assert!(small[0].starts_with("100x100 is smaller than 432x432"));
+
+        let wide = issues(&png(&folder, "wide.png", 600, 480, 0));
+        assert_eq!(wide.len(), 1);
+        assert!(wide[0].starts_with("600x480 is not square"));
+
+        // 1% off still counts as square
+        assert!(issues(&png(&folder, "almost.png", 500, 496, 0)).is_empty());
+
+        let opaque = issues(&png(&folder, "opaque.png", 432, 432, u8::MAX));
+        assert_eq!(opaque.len(), 1);
+        assert!(opaque[0].starts_with("no transparency"));
+    }
+
+    #[test]
+    fn svg_is_rendered_at_any_size() {
+        let folder = TestFolder::new("icon-source-svg".as_ref());
+        let path = folder.write("icon.svg", CIRCLE_SVG);
+
+        // vectors are never too small
+        let report = check_icon_source(&path.to_string_lossy()).unwrap();
+        assert_eq!((report.width, report.height, report.vector), (24, 24, true));
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+
+        let source = IconSource::open(&path.to_string_lossy()).unwrap();
+        let image = source.render(240, 240);
+        assert_eq!(image.dimensions(), (240, 240));
+        assert_eq!(image.get_pixel(120, 120).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(5, 5).0[3], 0);
+    }
+
+    #[test]
+    fn opaque_svg_is_reported() {
+        let folder = TestFolder::new("icon-source-opaque-svg".as_ref());
+        let path = folder.write(
+            "icon.svg",
+            br##"<svg xmlns="http://www.w3.org/2000/svg" width="48" height="32">
+    <rect width="48" height="32" fill="#00FF00"/>
+</svg>"##,
+        );
+
+        let issues = issues(&path.to_string_lossy());
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert!(issues[0].starts_with("48x32 is not square"));
+        assert!(issues[1].starts_with("no transparency"));
+    }
+
+    #[test]
+    fn unreadable_icons_are_errors() {
+        let folder = TestFolder::new("icon-source-bad".as_ref());
+        let code = |path: &Path| {
+            let e = check_icon_source(&path.to_string_lossy()).unwrap_err();
+            RepackError::from(e).code()
+        };
+
+        assert_eq!(code(&folder.0.join("missing.png")), "IconNotFound");
+        assert_eq!(code(&folder.write("bad.png", b"not a png")), "InvalidIcon");
+        assert_eq!(code(&folder.write("bad.svg", b"<svg")), "InvalidIcon");
+    }
+}
@@ -0,0 +1,792 @@
+use anyhow::Result;
+use image::{imageops, imageops::FilterType, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    icon_source::IconSource,
+    strings_util::write_color_resource,
+    xml_util::{xml_find_value, XmlDocument, XmlElement, XmlNode, ANDROID_NAMESPACE},
+};
+
+/// density qualifier and its scale over mdpi
+pub const DENSITIES: &[(&str, f32)] = &[
+    ("mdpi", 1.0),
+    ("hdpi", 1.5),
+    ("xhdpi", 2.0),
+    ("xxhdpi", 3.0),
+    ("xxxhdpi", 4.0),
+];
+
+/// adaptive icon layers are 108dp, launchers show at most the inner 72dp
+/// and their masks cut into that, only a 66dp circle is always shown
+const ADAPTIVE_LAYER_DP: f32 = 108.0;
+const ADAPTIVE_VIEWPORT_DP: f32 = 72.0;
+const ADAPTIVE_SAFE_ZONE_DP: f32 = 66.0;
+/// diameter of the circle no launcher mask crops, relative to the layer
+pub const ADAPTIVE_SAFE_ZONE: f32 = ADAPTIVE_SAFE_ZONE_DP / ADAPTIVE_LAYER_DP;
+/// legacy launcher icons are 48dp
+const LEGACY_ICON_DP: f32 = 48.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum IconBackground {
+    /// `#RGB`, `#RRGGBB` or `#AARRGGBB`
+    Color(String),
+    /// path of an image or svg, scaled to cover the layer
+    Image(String),
+}
+
+impl Default for IconBackground {
+    fn default() -> Self {
+        IconBackground::Color("#FFFFFF".to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveIconOptions {
+    pub background: IconBackground,
+    /// also write a monochrome layer for Android 13 themed icons
+    pub monochrome: bool,
+}
+
+/// Parses `#RGB`, `#RRGGBB` or `#AARRGGBB`.
+pub fn parse_color(color: &str) -> Result<Rgba<u8>> {
+    let bad_color = || anyhow::anyhow!("bad color {}", color);
+
+    let hex = color.trim().strip_prefix('#').ok_or_else(bad_color)?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(bad_color());
+    }
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16);
+
+    let rgba = match hex.len() {
+        3 => {
+            let value = u16::from_str_radix(hex, 16)?;
+            let expand = |shift: u16| ((value >> shift) & 0xf) as u8 * 0x11;
+            [expand(8), expand(4), expand(0), 0xff]
+        }
+        6 => [channel(0)?, channel(2)?, channel(4)?, 0xff],
+        8 => [channel(2)?, channel(4)?, channel(6)?, channel(0)?],
+        _ => return Err(bad_color()),
+    };
+
+    Ok(Rgba(rgba))
+}
+
+/// `(type, name)` of `@mipmap/ic_launcher` like references.
+pub fn parse_resource_reference(reference: &str) -> Option<(String, String)> {
+    let (res_type, name) = reference.strip_prefix('@')?.split_once('/')?;
+    Some((res_type.to_string(), name.to_string()))
+}
+
+fn get_icon_resources(apk_folder: &Path, attr_key: &str) -> Result<Vec<(String, String)>> {
+    let manifest_file_path = apk_folder.join("AndroidManifest.xml");
+    let manifest_file_content = std::fs::read_to_string(manifest_file_path)?;
+
+    let mut resources = vec![];
+    for reference in xml_find_value(&manifest_file_content, "manifest/application", attr_key)? {
+        match parse_resource_reference(&reference) {
+            Some(resource) if !resources.contains(&resource) => resources.push(resource),
+            Some(_) => {}
+            None => log::warn!("{} is not a resource: {}", attr_key, reference),
+        }
+    }
+
+    Ok(resources)
+}
+
+/// `(type, name)` of the application icon, e.g. `("mipmap", "ic_launcher")`.
+pub fn get_apk_icon_resources(apk_folder: &Path) -> Result<Vec<(String, String)>> {
+    let resources = get_icon_resources(apk_folder, "android:icon")?;
+    if resources.is_empty() {
+        return Err(anyhow::anyhow!("error find icon"));
+    }
+
+    Ok(resources)
+}
+
+/// `(type, name)` of `android:roundIcon`, empty when the app has none or it
+/// is the icon itself.
+pub fn get_apk_round_icon_resources(apk_folder: &Path) -> Result<Vec<(String, String)>> {
+    let icon_resources = get_apk_icon_resources(apk_folder)?;
+    Ok(get_icon_resources(apk_folder, "android:roundIcon")?
+        .into_iter()
+        .filter(|resource| !icon_resources.contains(resource))
+        .collect())
+}
+
+/// Files of `@res_type/name` in the `res_type` and `res_type-*` folders, in
+/// any format.
+fn find_resource_files(apk_folder: &Path, res_type: &str, name: &str) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(apk_folder.join("res"))? {
+        let dir = entry?.path();
+        let folder_name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !dir.is_dir()
+            || folder_name != res_type && !folder_name.starts_with(&format!("{}-", res_type))
+        {
+            continue;
+        }
+
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path
+                    .file_stem()
+                    .is_some_and(|stem| stem.to_string_lossy() == name)
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Removes `name.*` from every `res_type` and `res_type-*` folder, so the
+/// new files do not clash with other formats or qualifiers.
+pub fn remove_resource_files(apk_folder: &Path, res_type: &str, name: &str) -> Result<()> {
+    for path in find_resource_files(apk_folder, res_type, name)? {
+        log::info!("remove {}", path.to_string_lossy());
+        std::fs::remove_file(&path)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconFileKind {
+    /// png, webp or jpg
+    Bitmap,
+    Vector,
+    AdaptiveIcon,
+    /// any other drawable xml, kept as is
+    Xml,
+}
+
+/// One file behind the application icon.
+#[derive(Debug, Clone)]
+pub struct IconFile {
+    pub path: PathBuf,
+    pub res_type: String,
+    pub name: String,
+    pub kind: IconFileKind,
+    /// adaptive icon layer drawing it, `None` for the icon itself
+    pub layer: Option<String>,
+}
+
+/// `(reference, layer)` of the drawables an xml drawable is made of.
+fn drawable_references(
+    document: &XmlDocument,
+    root: &str,
+    layer: Option<&str>,
+) -> Result<Vec<(String, Option<String>)>> {
+    let mut references = vec![];
+    match root {
+        "adaptive-icon" => {
+            for layer in ["foreground", "background", "monochrome"] {
+                let selector = format!("adaptive-icon/{}", layer);
+                for reference in document.find_attr(&selector, "android:drawable")? {
+                    references.push((reference, Some(layer.to_string())));
+                }
+            }
+        }
+        "bitmap" => {
+            for reference in document.find_attr("bitmap", "android:src")? {
+                references.push((reference, layer.map(str::to_string)));
+            }
+        }
+        "inset" => {
+            for reference in document.find_attr("inset", "android:drawable")? {
+                references.push((reference, layer.map(str::to_string)));
+            }
+        }
+        _ => {}
+    }
+
+    Ok(references)
+}
+
+fn resolve_icon_files(
+    apk_folder: &Path,
+    res_type: &str,
+    name: &str,
+    layer: Option<&str>,
+    files: &mut Vec<IconFile>,
+) -> Result<()> {
+    if files
+        .iter()
+        .any(|file| file.res_type == res_type && file.name == name)
+    {
+        return Ok(());
+    }
+
+    let paths = find_resource_files(apk_folder, res_type, name)?;
+    if paths.is_empty() {
+        log::warn!("no file of @{}/{}", res_type, name);
+    }
+
+    let mut references = vec![];
+    for path in paths {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let kind = match extension.as_str() {
+            "png" | "webp" | "jpg" | "jpeg" => IconFileKind::Bitmap,
+            "xml" => {
+                let document = XmlDocument::parse(&std::fs::read_to_string(&path)?)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", path.to_string_lossy(), e))?;
+                let root = match document.find("*")?.first() {
+                    Some(element) => element.name.clone(),
+                    None => String::new(),
+                };
+                references.append(&mut drawable_references(&document, &root, layer)?);
+                match root.as_str() {
+                    "adaptive-icon" => IconFileKind::AdaptiveIcon,
+                    "vector" => IconFileKind::Vector,
+                    _ => IconFileKind::Xml,
+                }
+            }
+            _ => {
+                log::warn!("unknown icon file {}", path.to_string_lossy());
+                continue;
+            }
+        };
+
+        log::info!("icon file {:?}: {}", kind, path.to_string_lossy());
+        files.push(IconFile {
+            path,
+            res_type: res_type.to_string(),
+            name: name.to_string(),
+            kind,
+            layer: layer.map(str::to_string),
+        });
+    }
+
+    for (reference, layer) in references {
+        match parse_resource_reference(&reference) {
+            // colors live in values, not in files
+            Some((res_type, _)) if res_type == "color" => {}
+            Some((res_type, name)) => {
+                resolve_icon_files(apk_folder, &res_type, &name, layer.as_deref(), files)?
+            }
+            None => log::warn!("drawable is not a resource: {}", reference),
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_resources_files(
+    apk_folder: &Path,
+    resources: &[(String, String)],
+    files: &mut Vec<IconFile>,
+) -> Result<()> {
+    for (res_type, name) in resources {
+        resolve_icon_files(apk_folder, res_type, name, None, files)?;
+    }
+
+    Ok(())
+}
+
+/// Every file the application icon is drawn from, following adaptive icon
+/// layers and xml drawables to the bitmaps and vectors behind them.
+pub fn get_apk_icon_files(apk_folder: &Path) -> Result<Vec<IconFile>> {
+    let mut files = vec![];
+    resolve_resources_files(apk_folder, &get_apk_icon_resources(apk_folder)?, &mut files)?;
+
+    Ok(files)
+}
+
+/// Files of the round icon that the icon does not already use, adaptive
+/// layers are usually shared.
+pub fn get_apk_round_icon_files(apk_folder: &Path) -> Result<Vec<IconFile>> {
+    // resolved after the icon so shared resources are skipped
+    let mut files = get_apk_icon_files(apk_folder)?;
+    let icon_files_count = files.len();
+    resolve_resources_files(
+        apk_folder,
+        &get_apk_round_icon_resources(apk_folder)?,
+        &mut files,
+    )?;
+
+    Ok(files.split_off(icon_files_count))
+}
+
+/// Files that show the artwork of the icon and round icon: the icons
+/// themselves and adaptive foregrounds. `true` marks legacy round icons, the
+/// adaptive ones are masked by the launcher.
+pub fn get_apk_visible_icon_files(apk_folder: &Path) -> Result<Vec<(IconFile, bool)>> {
+    Ok(get_apk_icon_files(apk_folder)?
+        .into_iter()
+        .map(|file| (file, false))
+        .chain(
+            get_apk_round_icon_files(apk_folder)?
+                .into_iter()
+                .map(|file| {
+                    let round = file.layer.is_none();
+                    (file, round)
+                }),
+        )
+        .filter(|(file, _)| matches!(file.layer.as_deref(), None | Some("foreground")))
+        .collect())
+}
+
+/// The largest bitmap, of the icon itself or else of its foreground.
+fn icon_preview(files: &[IconFile]) -> Option<PathBuf> {
+    for layer in [None, Some("foreground")] {
+        let mut preview: Option<(u32, &Path)> = None;
+        for file in files {
+            if file.kind != IconFileKind::Bitmap || file.layer.as_deref() != layer {
+                continue;
+            }
+            match image::image_dimensions(&file.path) {
+                Ok((w, _)) if preview.is_none_or(|(max_w, _)| w > max_w) => {
+                    preview = Some((w, &file.path))
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("bad icon {}: {}", file.path.to_string_lossy(), e),
+            }
+        }
+        if let Some((_, path)) = preview {
+            return Some(path.to_path_buf());
+        }
+    }
+
+    None
+}
+
+pub fn get_apk_icon_preview(apk_folder: &Path) -> Result<Option<PathBuf>> {
+    Ok(icon_preview(&get_apk_icon_files(apk_folder)?))
+}
+
+pub fn get_apk_round_icon_preview(apk_folder: &Path) -> Result<Option<PathBuf>> {
+    Ok(icon_preview(&get_apk_round_icon_files(apk_folder)?))
+}
+
+/// Saves in the format of the extension, jpg has no alpha channel.
+pub fn save_image(image: &DynamicImage, path: &Path) -> Result<()> {
+    match ImageFormat::from_path(path)? {
+        ImageFormat::Jpeg => image.to_rgb8().save(path)?,
+        _ => image.save(path)?,
+    }
+
+    Ok(())
+}
+
+/// Redraws a bitmap icon file at its own size and in its own format, round
+/// icons are masked to a circle. Foreground layers keep the artwork in the
+/// safe zone.
+fn replace_bitmap_icon(file: &IconFile, source: &IconSource, round: bool) -> Result<()> {
+    let (w, h) = image::image_dimensions(&file.path)
+        .map_err(|e| anyhow::anyhow!("bad icon {}: {}", file.path.to_string_lossy(), e))?;
+    let new_image = if round {
+        round_icon(source, w, h)
+    } else if file.layer.as_deref() == Some("foreground") {
+        let layer_size = w.min(h);
+        let foreground = fit_into(
+            source,
+            (layer_size as f32 * ADAPTIVE_SAFE_ZONE).round() as u32,
+            layer_size,
+        );
+        let mut canvas = RgbaImage::new(w, h);
+        imageops::overlay(
+            &mut canvas,
+            &foreground,
+            (w - layer_size) as i64 / 2,
+            (h - layer_size) as i64 / 2,
+        );
+        canvas
+    } else {
+        source.render(w, h)
+    };
+    save_image(&DynamicImage::ImageRgba8(new_image), &file.path)
+}
+
+/// `108dp` like dimensions of a vector drawable.
+fn parse_dp(value: &str) -> Option<f32> {
+    value.trim().strip_suffix("dp")?.parse().ok()
+}
+
+/// Width the vector declares, or the usual size of its layer when missing.
+pub fn vector_size_dp(file: &IconFile) -> Result<f32> {
+    let document = XmlDocument::parse(&std::fs::read_to_string(&file.path)?)?;
+    Ok(document
+        .find_attr("vector", "android:width")?
+        .first()
+        .and_then(|width| parse_dp(width))
+        .unwrap_or(if file.layer.as_deref() == Some("foreground") {
+            ADAPTIVE_LAYER_DP
+        } else {
+            LEGACY_ICON_DP
+        }))
+}
+
+/// Replaces a vector drawable with a png per density, at the size the vector
+/// declares. Foreground layers keep the artwork in the safe zone.
+fn replace_vector_icon(
+    apk_folder: &Path,
+    file: &IconFile,
+    source: &IconSource,
+    round: bool,
+) -> Result<()> {
+    log::info!("replace_vector_icon: {}", file.path.to_string_lossy());
+
+    let is_foreground = file.layer.as_deref() == Some("foreground");
+    let layer_dp = vector_size_dp(file)?;
+    let artwork_dp = if is_foreground {
+        layer_dp * ADAPTIVE_SAFE_ZONE
+    } else {
+        layer_dp
+    };
+
+    remove_resource_files(apk_folder, &file.res_type, &file.name)?;
+
+    for (density, scale) in DENSITIES {
+        let dir = apk_folder
+            .join("res")
+            .join(format!("{}-{}", file.res_type, density));
+        std::fs::create_dir_all(&dir)?;
+        let size = px(layer_dp, *scale);
+        let icon = if round {
+            round_icon(source, size, size)
+        } else {
+            fit_into(source, px(artwork_dp, *scale), size)
+        };
+        icon.save(dir.join(format!("{}.png", file.name)))?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the icon and its foreground: bitmaps keep their size and format,
+/// vectors become a bitmap per density, round icons are cut to a circle.
+/// Backgrounds are kept.
+pub fn exchange_apk_icon(apk_folder: &Path, new_icon_path: &str) -> Result<()> {
+    log::info!("exchange_apk_icon: {}", new_icon_path);
+
+    let source = IconSource::open(new_icon_path)?;
+
+    let files = get_apk_visible_icon_files(apk_folder)?;
+
+    // vectors first, their bitmaps of other densities go with them
+    let mut replaced: Vec<(&str, &str)> = vec![];
+    for (file, round) in &files {
+        let resource = (file.res_type.as_str(), file.name.as_str());
+        if file.kind == IconFileKind::Vector && !replaced.contains(&resource) {
+            replace_vector_icon(apk_folder, file, &source, *round)?;
+            replaced.push(resource);
+        }
+    }
+
+    for (file, round) in &files {
+        let resource = (file.res_type.as_str(), file.name.as_str());
+        if file.kind == IconFileKind::Bitmap && !replaced.contains(&resource) {
+            replace_bitmap_icon(file, &source, *round)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn px(dp: f32, scale: f32) -> u32 {
+    (dp * scale).round() as u32
+}
+
+/// Draws `source` to fit a `size` square keeping its aspect, centered on a
+/// transparent canvas.
+fn fit_into(source: &IconSource, size: u32, canvas_size: u32) -> RgbaImage {
+    let ratio = (size as f32 / source.width()).min(size as f32 / source.height());
+    let w = ((source.width() * ratio).round() as u32).max(1);
+    let h = ((source.height() * ratio).round() as u32).max(1);
+    let scaled = source.render(w, h);
+
+    let mut canvas = RgbaImage::new(canvas_size, canvas_size);
+    let x = (canvas_size as i64 - w as i64) / 2;
+    let y = (canvas_size as i64 - h as i64) / 2;
+    imageops::overlay(&mut canvas, &scaled, x, y);
+    canvas
+}
+
+/// Draws `source` to cover a `size` square and crops the center.
+fn cover(source: &IconSource, size: u32) -> RgbaImage {
+    let ratio = (size as f32 / source.width()).max(size as f32 / source.height());
+    let w = ((source.width() * ratio).round() as u32).max(size);
+    let h = ((source.height() * ratio).round() as u32).max(size);
+    let scaled = source.render(w, h);
+    imageops::crop_imm(&scaled, (w - size) / 2, (h - size) / 2, size, size).to_image()
+}
+
+/// Cuts a circle out of a square image, the edge pixels keep the part of
+/// their alpha the circle covers.
+fn circle_mask(image: &mut RgbaImage) {
+    let radius = image.width().min(image.height()) as f32 / 2.0;
+    let (cx, cy) = (image.width() as f32 / 2.0, image.height() as f32 / 2.0);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - cx;
+        let dy = y as f32 + 0.5 - cy;
+        let coverage = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+        pixel.0[3] = (pixel.0[3] as f32 * coverage).round() as u8;
+    }
+}
+
+/// Draws `source` to cover a `w` x `h` round icon and masks it to a circle.
+fn round_icon(source: &IconSource, w: u32, h: u32) -> RgbaImage {
+    let mut round = cover(source, w.min(h));
+    circle_mask(&mut round);
+
+    let mut canvas = RgbaImage::new(w, h);
+    imageops::overlay(
+        &mut canvas,
+        &round,
+        (w as i64 - round.width() as i64) / 2,
+        (h as i64 - round.height() as i64) / 2,
+    );
+    canvas
+}
+
+/// White where the foreground is drawn, the launcher tints it by theme.
+fn monochrome(foreground: &RgbaImage) -> RgbaImage {
+    let mut layer = foreground.clone();
+    for pixel in layer.pixels_mut() {
+        pixel.0 = [0xff, 0xff, 0xff, pixel.0[3]];
+    }
+    layer
+}
+
+enum BackgroundLayer {
+    Color(String, Rgba<u8>),
+    Image(IconSource),
+}
+
+fn adaptive_icon_xml(
+    background: &str,
+    foreground: &str,
+    monochrome: Option<&str>,
+) -> Result<String> {
+    let mut adaptive_icon =
+        XmlElement::new("adaptive-icon").with_attr("xmlns:android", ANDROID_NAMESPACE);
+    adaptive_icon.children.push(XmlNode::Element(
+        XmlElement::new("background").with_attr("android:drawable", background),
+    ));
+    adaptive_icon.children.push(XmlNode::Element(
+        XmlElement::new("foreground").with_attr("android:drawable", foreground),
+    ));
+    if let Some(monochrome) = monochrome {
+        adaptive_icon.children.push(XmlNode::Element(
+            XmlElement::new("monochrome").with_attr("android:drawable", monochrome),
+        ));
+    }
+
+    let mut document =
+        XmlDocument::parse("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"no\"?>")?;
+    document.nodes.push(XmlNode::Element(adaptive_icon));
+    document.to_xml_string()
+}
+
+fn write_adaptive_icon(
+    apk_folder: &Path,
+    res_type: &str,
+    name: &str,
+    source: &IconSource,
+    background: &BackgroundLayer,
+    with_monochrome: bool,
+    round_resources: &[(String, String)],
+) -> Result<()> {
+    log::info!(
+        "write_adaptive_icon: @{}/{} {:?}",
+        res_type,
+        name,
+        round_resources
+    );
+
+    let foreground_name = format!("{}_foreground", name);
+    let background_name = format!("{}_background", name);
+    let monochrome_name = format!("{}_monochrome", name);
+    // only what is written again, other icons may share the old layers
+    remove_resource_files(apk_folder, res_type, name)?;
+    remove_resource_files(apk_folder, res_type, &foreground_name)?;
+    if let BackgroundLayer::Image(_) = background {
+        remove_resource_files(apk_folder, res_type, &background_name)?;
+    }
+    if with_monochrome {
+        remove_resource_files(apk_folder, res_type, &monochrome_name)?;
+    }
+    for (round_type, round_name) in round_resources {
+        remove_resource_files(apk_folder, round_type, round_name)?;
+    }
+
+    let res = apk_folder.join("res");
+    for (density, scale) in DENSITIES {
+        let dir = res.join(format!("{}-{}", res_type, density));
+        std::fs::create_dir_all(&dir)?;
+
+        let layer_size = px(ADAPTIVE_LAYER_DP, *scale);
+        // the artwork fits the safe zone, no launcher mask crops it
+        let foreground = fit_into(
+            source,
+            px(ADAPTIVE_LAYER_DP * ADAPTIVE_SAFE_ZONE, *scale),
+            layer_size,
+        );
+        foreground.save(dir.join(format!("{}.png", foreground_name)))?;
+
+        let mut legacy = match background {
+            BackgroundLayer::Color(_, color) => {
+                RgbaImage::from_pixel(layer_size, layer_size, *color)
+            }
+            BackgroundLayer::Image(image) => {
+                let layer = cover(image, layer_size);
+                layer.save(dir.join(format!("{}.png", background_name)))?;
+                layer
+            }
+        };
+
+        if with_monochrome {
+            monochrome(&foreground).save(dir.join(format!("{}.png", monochrome_name)))?;
+        }
+
+        // pre-oreo launchers show the visible viewport of both layers
+        imageops::overlay(&mut legacy, &foreground, 0, 0);
+        let viewport = px(ADAPTIVE_VIEWPORT_DP, *scale);
+        let offset = (layer_size - viewport) / 2;
+        let legacy = imageops::crop_imm(&legacy, offset, offset, viewport, viewport).to_image();
+        let legacy_size = px(LEGACY_ICON_DP, *scale);
+        let legacy = imageops::resize(&legacy, legacy_size, legacy_size, FilterType::Lanczos3);
+        legacy.save(dir.join(format!("{}.png", name)))?;
+
+        if !round_resources.is_empty() {
+            let mut round = legacy;
+            circle_mask(&mut round);
+            for (round_type, round_name) in round_resources {
+                let round_dir = res.join(format!("{}-{}", round_type, density));
+                std::fs::create_dir_all(&round_dir)?;
+                round.save(round_dir.join(format!("{}.png", round_name)))?;
+            }
+        }
+    }
+
+    let background_drawable = match background {
+        BackgroundLayer::Color(color, _) => {
+            write_color_resource(&res.join("values"), &background_name, color)?;
+            format!("@color/{}", background_name)
+        }
+        BackgroundLayer::Image(_) => format!("@{}/{}", res_type, background_name),
+    };
+    let monochrome_drawable = format!("@{}/{}", res_type, monochrome_name);
+    let xml = adaptive_icon_xml(
+        &background_drawable,
+        &format!("@{}/{}", res_type, foreground_name),
+        with_monochrome.then_some(monochrome_drawable.as_str()),
+    )?;
+
+    // the round icon shares the layers, launchers apply their own mask
+    let resources = std::iter::once((res_type, name)).chain(
+        round_resources
+            .iter()
+            .map(|(round_type, round_name)| (round_type.as_str(), round_name.as_str())),
+    );
+    for (res_type, name) in resources {
+        let anydpi_dir = res.join(format!("{}-anydpi-v26", res_type));
+        std::fs::create_dir_all(&anydpi_dir)?;
+        std::fs::write(anydpi_dir.join(format!("{}.xml", name)), &xml)?;
+    }
+
+    Ok(())
+}
+
+/// Builds adaptive icon layers for every density from one source image,
+/// with legacy and round icons for older launchers, and rewrites the adaptive
+/// icon xml.
+pub fn exchange_apk_adaptive_icon(
+    apk_folder: &Path,
+    new_icon_path: &str,
+    options: &AdaptiveIconOptions,
+) -> Result<()> {
+    log::info!(
+        "exchange_apk_adaptive_icon: {} {:?}",
+        new_icon_path,
+        options
+    );
+
+    let source = IconSource::open(new_icon_path)?;
+    let background = match &options.background {
+        IconBackground::Color(color) => {
+            BackgroundLayer::Color(color.trim().to_string(), parse_color(color)?)
+        }
+        IconBackground::Image(path) => BackgroundLayer::Image(IconSource::open(path)?),
+    };
+
+    // round icons go along with the first icon
+    let mut round_resources = get_apk_round_icon_resources(apk_folder)?;
+    for (res_type, name) in get_apk_icon_resources(apk_folder)? {
+        write_adaptive_icon(
+            apk_folder,
+            &res_type,
+            &name,
+            &source,
+            &background,
+            options.monochrome,
+            &std::mem::take(&mut round_resources),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    #[test]
+    fn bitmap_foreground_keeps_the_safe_zone() {
+        let folder = TestFolder::new("bitmap-foreground".as_ref());
+        folder.write(
+            "AndroidManifest.xml",
+            br#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.demo">
+    <application android:icon="@mipmap/ic_launcher"/>
+</manifest>"#,
+        );
+        folder.write(
+            "res/mipmap-anydpi-v26/ic_launcher.xml",
+            br#"<adaptive-icon xmlns:android="http://schemas.android.com/apk/res/android">
+    <background android:drawable="@color/ic_launcher_background"/>
+    <foreground android:drawable="@mipmap/ic_launcher_foreground"/>
+</adaptive-icon>"#,
+        );
+        let foreground_path = folder
+            .0
+            .join("res/mipmap-xxhdpi/ic_launcher_foreground.png");
+        std::fs::create_dir_all(foreground_path.parent().unwrap()).unwrap();
+        RgbaImage::new(324, 324).save(&foreground_path).unwrap();
+        let source_path = folder.0.join("source.png");
+        RgbaImage::from_pixel(10, 10, Rgba([255, 0, 0, 255]))
+            .save(&source_path)
+            .unwrap();
+
+        exchange_apk_icon(&folder.0, &source_path.to_string_lossy()).unwrap();
+
+        // 198px of artwork centered in the 324px layer, 66dp of 108dp
+        let foreground = image::open(&foreground_path).unwrap().to_rgba8();
+        assert_eq!(foreground.dimensions(), (324, 324));
+        let inset = 63;
+        for (x, y) in [
+            (inset - 1, 162),
+            (162, inset - 1),
+            (324 - inset, 162),
+            (0, 0),
+        ] {
+            assert_eq!(foreground.get_pixel(x, y).0[3], 0, "{} {}", x, y);
+        }
+        for (x, y) in [(inset, 162), (162, inset), (323 - inset, 162), (162, 162)] {
+            assert_eq!(foreground.get_pixel(x, y).0[3], 255, "{} {}", x, y);
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{
+    constants::get_tool_timeout, error::RepackError, run_command::command_output,
+    settings::get_settings,
+};
+
+/// apktool 2.x runs on java 8 and later
+pub const MIN_JAVA_MAJOR: u32 = 8;
+
+/// A java found on this machine, as `java -version` describes it.
+#[derive(Debug, Clone, Serialize)]
+pub struct JavaRuntime {
+    pub java_home: String,
+    /// e.g. `17.0.9` or `1.8.0_392`
+    pub version: String,
+    /// `8` for `1.8.0_392`
+    pub major: u32,
+    /// e.g. `Eclipse Adoptium`, empty when java does not say
+    pub vendor: String,
+    /// where it was found: `settings`, `JAVA_HOME`, `PATH` or the folder
+    /// searched
+    pub source: String,
+    /// new enough for the bundled apktool
+    pub supported: bool,
+}
+
+fn java_exe_name(windowed: bool) -> &'static str {
+    match (cfg!(target_os = "windows"), windowed) {
+        // javaw opens no console window for the tools
+        (true, true) => "javaw.exe",
+        (true, false) => "java.exe",
+        _ => "java",
+    }
+}
+
+/// `8` for `1.8.0_392`, `17` for `17.0.9` and `21-ea`.
+fn java_major_version(version: &str) -> Option<u32> {
+    let mut numbers = version
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|number| number.parse::<u32>().ok());
+    match numbers.next()?? {
+        1 => numbers.next()?,
+        major => Some(major),
+    }
+}
+
+/// `(version, vendor)` from the output of
+/// `java -XshowSettings:properties -version`, the vendor falls back to the
+/// runtime name for javas without the properties.
+fn parse_java_version(output: &str) -> Option<(String, String)> {
+    let property = |key: &str| {
+        output.lines().find_map(|line| {
+            let (line_key, value) = line.trim().split_once(" = ")?;
+            (line_key == key).then(|| value.trim().to_string())
+        })
+    };
+
+    // `openjdk version "17.0.9" 2023-10-17`, `java version "1.8.0_392"`
+    let version_line = output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.starts_with("java.") && line.contains(" version \""))?;
+    let version = version_line
+        .split('"')
+        .nth(1)
+        .filter(|version| !version.is_empty())?
+        .to_string();
+
+    let vendor = property("java.vendor").unwrap_or_else(|| {
+        // `OpenJDK Runtime Environment Temurin-17.0.9+9 (build 17.0.9+9)`
+        output
+            .lines()
+            .map(str::trim)
+            .find(|line| line.contains("Runtime Environment"))
+            .map(|line| line.split(" (build").next().unwrap_or(line).to_string())
+            .unwrap_or_default()
+    });
+
+    Some((version, vendor))
+}
+
+/// `(version, major, vendor)` of a java exe, `None` when it does not run
+type Probe = Option<(String, u32, String)>;
+
+fn run_java_version(java_exe: &Path) -> Probe {
+    // a broken java may hang, it gets the time of any other tool
+    let (stdout, stderr) = command_output(
+        Command::new(java_exe).args(["-XshowSettings:properties", "-version"]),
+        get_tool_timeout(),
+    )
+    .map_err(|e| log::warn!("run {}: {}", java_exe.to_string_lossy(), e))
+    .ok()?;
+
+    // java prints both to stderr
+    let output = stderr + "\n" + &stdout;
+    let (version, vendor) = parse_java_version(&output)?;
+    let major = java_major_version(&version)?;
+    Some((version, major, vendor))
+}
+
+/// Runs the java of `java_home`, `None` when there is none or it does not
+/// run. Results are kept, every repack looks for java again.
+fn probe_java_home(java_home: &Path, source: &str) -> Option<JavaRuntime> {
+    static PROBES: OnceLock<Mutex<HashMap<PathBuf, Probe>>> = OnceLock::new();
+    let probes = PROBES.get_or_init(Default::default);
+
+    let java_exe = java_home
+        .join("bin")
+        .join(java_exe_name(false))
+        .canonicalize()
+        .ok()?;
+
+    let probe = probes.lock().ok()?.get(&java_exe).cloned();
+    let probe = match probe {
+        Some(probe) => probe,
+        None => {
+            let probe = run_java_version(&java_exe);
+            if let Ok(mut probes) = probes.lock() {
+                probes.insert(java_exe, probe.clone());
+            }
+            probe
+        }
+    };
+
+    let (version, major, vendor) = probe?;
+    Some(JavaRuntime {
+        java_home: java_home.to_string_lossy().to_string(),
+        version,
+        major,
+        vendor,
+        source: source.to_string(),
+        supported: major >= MIN_JAVA_MAJOR,
+    })
+}
+
+/// Folders that hold one java home each, per platform. On Windows only the
+/// vendor folders of `Program Files` are searched, the registry keys of
+/// JavaSoft and the vendors are not read: a java installed elsewhere is found
+/// through the settings, `JAVA_HOME` or `PATH`.
+fn java_home_parents() -> Vec<(PathBuf, &'static str)> {
+    let mut parents = vec![];
+
+    if cfg!(target_os = "windows") {
+        for program_files in ["ProgramFiles", "ProgramW6432", "ProgramFiles(x86)"] {
+            let Ok(program_files) = std::env::var(program_files) else {
+                continue;
+            };
+            // where the installers of each vendor put their javas
+            for vendor_folder in [
+                "Java",
+                "Eclipse Adoptium",
+                "Eclipse Foundation",
+                "AdoptOpenJDK",
+                "Zulu",
+                "Amazon Corretto",
+                "Microsoft",
+                "BellSoft",
+                "Semeru",
+            ] {
+                parents.push((
+                    Path::new(&program_files).join(vendor_folder),
+                    "Program Files",
+                ));
+            }
+        }
+    } else if cfg!(target_os = "macos") {
+        parents.push((
+            PathBuf::from("/Library/Java/JavaVirtualMachines"),
+            "JavaVirtualMachines",
+        ));
+        if let Some(home) = std::env::var_os("HOME") {
+            parents.push((
+                Path::new(&home).join("Library/Java/JavaVirtualMachines"),
+                "JavaVirtualMachines",
+            ));
+        }
+    } else {
+        parents.push((PathBuf::from("/usr/lib/jvm"), "/usr/lib/jvm"));
+        parents.push((PathBuf::from("/usr/java"), "/usr/java"));
+    }
+
+    parents
+}
+
+/// Java homes worth a look, in order of preference, duplicates included.
+fn java_home_candidates() -> Vec<(PathBuf, &'static str)> {
+    let mut candidates = vec![];
+
+    let java_home = get_settings().java_home;
+    if !java_home.is_empty() {
+        candidates.push((PathBuf::from(java_home), "settings"));
+    }
+
+    if let Some(java_home) = std::env::var_os("JAVA_HOME").filter(|home| !home.is_empty()) {
+        candidates.push((PathBuf::from(java_home), "JAVA_HOME"));
+    }
+
+    // `/usr/bin/java` links into the real home
+    if let Some(path) = std::env::var_os("PATH") {
+        for folder in std::env::split_paths(&path) {
+            let Ok(java_exe) = folder.join(java_exe_name(false)).canonicalize() else {
+                continue;
+            };
+            if let Some(java_home) = java_exe.parent().and_then(Path::parent) {
+                candidates.push((java_home.to_path_buf(), "PATH"));
+            }
+        }
+    }
+
+    if cfg!(target_os = "macos") {
+        if let Ok(output) = Command::new("/usr/libexec/java_home").output() {
+            let java_home = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if output.status.success() && !java_home.is_empty() {
+                candidates.push((PathBuf::from(java_home), "java_home"));
+            }
+        }
+    }
+
+    for (parent, source) in java_home_parents() {
+        let Ok(entries) = std::fs::read_dir(&parent) else {
+            continue;
+        };
+        let mut homes = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<PathBuf>>();
+        homes.sort();
+        for home in homes {
+            // macOS bundles keep the home inside
+            let bundle_home = home.join("Contents").join("Home");
+            candidates.push((
+                if bundle_home.is_dir() {
+                    bundle_home
+                } else {
+                    home
+                },
+                source,
+            ));
+        }
+    }
+
+    candidates
+}
+
+/// Every java found on this machine, once each, supported ones first and
+/// newer before older after the settings, `JAVA_HOME` and `PATH`.
+pub fn find_java_runtimes() -> Vec<JavaRuntime> {
+    log::info!("find_java_runtimes");
+
+    let candidates = java_home_candidates();
+    let probed = std::thread::scope(|scope| {
+        let probes = candidates
+            .iter()
+            .map(|(java_home, source)| scope.spawn(move || probe_java_home(java_home, source)))
+            .collect::<Vec<_>>();
+        probes
+            .into_iter()
+            .map(|probe| probe.join().ok().flatten())
+            .collect::<Vec<Option<JavaRuntime>>>()
+    });
+
+    let mut runtimes: Vec<JavaRuntime> = vec![];
+    let mut seen = vec![];
+    for runtime in probed.into_iter().flatten() {
+        let java_exe = Path::new(&runtime.java_home)
+            .join("bin")
+            .join(java_exe_name(false))
+            .canonicalize()
+            .ok();
+        if seen.contains(&java_exe) {
+            continue;
+        }
+        seen.push(java_exe);
+        runtimes.push(runtime);
+    }
+
+    // the order of PATH is kept
+    let rank = |runtime: &JavaRuntime| match runtime.source.as_str() {
+        "settings" => (0, 0),
+        "JAVA_HOME" => (1, 0),
+        "PATH" => (2, 0),
+        _ => (3, u32::MAX - runtime.major),
+    };
+    runtimes.sort_by_key(|runtime| (!runtime.supported, rank(runtime)));
+
+    runtimes
+}
+
+/// Checks a java home the user picked, fails when it has no java or one
+/// too old for apktool.
+pub fn check_java_home(java_home: &str) -> Result<JavaRuntime> {
+    log::info!("check_java_home: {}", java_home);
+
+    let runtime = probe_java_home(Path::new(java_home), "selected").ok_or_else(|| {
+        RepackError::JavaNotFound {
+            detail: format!("no java runs in {}", java_home),
+        }
+    })?;
+
+    if !runtime.supported {
+        return Err(RepackError::JavaUnsupported {
+            java_home: runtime.java_home,
+            version: runtime.version,
+        }
+        .into());
+    }
+
+    Ok(runtime)
+}
+
+/// The java that runs apktool: the one of the settings or `JAVA_HOME` when
+/// it is new enough, else the best one found.
+pub fn get_java_exe() -> Result<String> {
+    let runtimes = find_java_runtimes();
+    let runtime = runtimes.iter().find(|runtime| runtime.supported);
+
+    let Some(runtime) = runtime else {
+        return Err(match runtimes.first() {
+            Some(runtime) => RepackError::JavaUnsupported {
+                java_home: runtime.java_home.clone(),
+                version: runtime.version.clone(),
+            },
+            None => RepackError::JavaNotFound {
+                detail: "no java in JAVA_HOME, PATH or the usual folders".to_string(),
+            },
+        }
+        .into());
+    };
+    if !get_settings().java_home.is_empty() {
+        if runtime.source != "settings" {
+            log::warn!(
+                "java of the settings is not usable, using {}",
+                runtime.java_home
+            );
+        }
+    } else if std::env::var_os("JAVA_HOME").is_some_and(|home| !home.is_empty())
+        && runtime.source != "JAVA_HOME"
+    {
+        log::warn!("JAVA_HOME is not usable, using {}", runtime.java_home);
+    }
+    log::info!(
+        "java {} {} at {}",
+        runtime.version,
+        runtime.vendor,
+        runtime.java_home
+    );
+
+    let java_exe = Path::new(&runtime.java_home)
+        .join("bin")
+        .join(java_exe_name(true));
+    java_exe.to_str().map(str::to_string).ok_or_else(|| {
+        RepackError::JavaNotFound {
+            detail: format!("{} is not a UTF-8 path", java_exe.to_string_lossy()),
+        }
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn major_versions() {
+        for (version, major) in [
+            ("1.8.0_392", Some(8)),
+            ("17.0.9", Some(17)),
+            ("21-ea", Some(21)),
+            ("11", Some(11)),
+            ("", None),
+            ("ea", None),
+        ] {
+            assert_eq!(java_major_version(version), major, "{}", version);
+        }
+    }
+
+    #[test]
+    fn version_and_vendor_from_properties() {
+        let output = r#"Property settings:
+    java.specification.version = 17
+    java.vendor = Eclipse Adoptium
+    java.version = 17.0.9
+
+openjdk version "17.0.9" 2023-10-17
+OpenJDK Runtime Environment Temurin-17.0.9+9 (build 17.0.9+9)
+OpenJDK 64-Bit Server VM Temurin-17.0.9+9 (build 17.0.9+9, mixed mode, sharing)
+"#;
+        assert_eq!(
+            parse_java_version(output),
+            Some(("17.0.9".to_string(), "Eclipse Adoptium".to_string()))
+        );
+    }
+
+    #[test]
+    fn vendor_falls_back_to_the_runtime_name() {
+        let output = r#"java version "1.8.0_392"
+Java(TM) SE Runtime Environment (build 1.8.0_392-b08)
+Java HotSpot(TM) 64-Bit Server VM (build 25.392-b08, mixed mode)
+"#;
+        assert_eq!(
+            parse_java_version(output),
+            Some((
+                "1.8.0_392".to_string(),
+                "Java(TM) SE Runtime Environment".to_string()
+            ))
+        );
+
+        let output = "openjdk version \"21-ea\" 2023-09-19\n";
+        assert_eq!(
+            parse_java_version(output),
+            Some(("21-ea".to_string(), String::new()))
+        );
+        assert_eq!(parse_java_version("Error: could not create the JVM"), None);
+    }
+}
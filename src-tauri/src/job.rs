@@ -0,0 +1,345 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    process::Child,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::Instant,
+};
+
+use crate::{constants::get_cache_folder, error::RepackError};
+
+const JOBS_FOLDER: &str = "jobs";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    Decode,
+    Edit,
+    Build,
+    Sign,
+    Channel,
+    Move,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Started,
+    /// a progress line of the tool, in `message`
+    Running,
+    /// any other line the tool printed, in `message`
+    Output,
+    Finished,
+}
+
+/// Sent to the progress sink for every stage change, the window gets it as
+/// the `job-progress` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobProgress {
+    pub job_id: String,
+    pub stage: JobStage,
+    pub status: StageStatus,
+    pub message: String,
+    /// time spent in the stage, set when it finished
+    pub elapsed_ms: Option<u64>,
+}
+
+type ProgressSink = Box<dyn Fn(&JobProgress) + Send + Sync>;
+
+static PROGRESS_SINK: OnceLock<ProgressSink> = OnceLock::new();
+
+struct JobState {
+    workspace: PathBuf,
+    cancelled: bool,
+    /// a pipeline works in the workspace, it cleans up after a cancel
+    running: bool,
+    /// the tool the job waits for, killed by `cancel_job`
+    child: Option<Arc<Mutex<Child>>>,
+}
+
+fn jobs() -> Result<MutexGuard<'static, HashMap<String, JobState>>> {
+    static JOBS: OnceLock<Mutex<HashMap<String, JobState>>> = OnceLock::new();
+    JOBS.get_or_init(Default::default)
+        .lock()
+        .map_err(|_| anyhow::anyhow!("job registry poisoned"))
+}
+
+/// Creates an empty workspace and returns the id of its job, jobs never
+/// share files so several can run at once.
+pub fn create_job() -> Result<String> {
+    static NEXT_JOB: AtomicU32 = AtomicU32::new(1);
+    let job_id = format!("job-{}", NEXT_JOB.fetch_add(1, Ordering::Relaxed));
+
+    let workspace = get_cache_folder().join(JOBS_FOLDER).join(&job_id);
+    if workspace.exists() {
+        std::fs::remove_dir_all(&workspace)?;
+    }
+    std::fs::create_dir_all(&workspace)?;
+    log::info!("create_job: {} => {}", job_id, workspace.to_string_lossy());
+
+    jobs()?.insert(
+        job_id.clone(),
+        JobState {
+            workspace,
+            cancelled: false,
+            running: false,
+            child: None,
+        },
+    );
+
+    Ok(job_id)
+}
+
+pub fn job_workspace(job_id: &str) -> Result<PathBuf> {
+    check_cancelled(job_id)?;
+    jobs()?
+        .get(job_id)
+        .map(|job| job.workspace.clone())
+        .ok_or_else(|| {
+            RepackError::JobNotFound {
+                job_id: job_id.to_string(),
+            }
+            .into()
+        })
+}
+
+/// Fails once the job is cancelled, the pipeline checks between stages.
+pub fn check_cancelled(job_id: &str) -> Result<()> {
+    match jobs()?.get(job_id) {
+        Some(job) if job.cancelled => Err(RepackError::Cancelled {
+            job_id: job_id.to_string(),
+        }
+        .into()),
+        Some(_) => Ok(()),
+        None => Err(RepackError::JobNotFound {
+            job_id: job_id.to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Forgets the job and deletes its workspace, unknown ids are ignored.
+pub fn finish_job(job_id: &str) -> Result<()> {
+    log::info!("finish_job: {}", job_id);
+
+    let job = jobs()?.remove(job_id);
+    if let Some(job) = job {
+        if job.workspace.exists() {
+            std::fs::remove_dir_all(job.workspace)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Kills the tool the job runs, an idle job is finished right away, a
+/// running one when its pipeline gives up.
+#[cfg(any(feature = "gui", test))]
+pub fn cancel_job(job_id: &str) -> Result<()> {
+    log::info!("cancel_job: {}", job_id);
+
+    let (running, child) = match jobs()?.get_mut(job_id) {
+        Some(job) => {
+            job.cancelled = true;
+            (job.running, job.child.take())
+        }
+        None => return Ok(()),
+    };
+
+    if let Some(child) = child {
+        let mut child = child
+            .lock()
+            .map_err(|_| anyhow::anyhow!("job child poisoned"))?;
+        if let Err(e) = child.kill() {
+            log::warn!("kill job {}: {}", job_id, e);
+        }
+    }
+
+    if !running {
+        finish_job(job_id)?;
+    }
+
+    Ok(())
+}
+
+/// Marks the job as running until the guard drops, so `cancel_job` leaves
+/// the workspace to it.
+pub struct JobRun {
+    job_id: String,
+}
+
+pub fn run_job(job_id: &str) -> Result<JobRun> {
+    check_cancelled(job_id)?;
+    if let Some(job) = jobs()?.get_mut(job_id) {
+        job.running = true;
+    }
+
+    Ok(JobRun {
+        job_id: job_id.to_string(),
+    })
+}
+
+impl Drop for JobRun {
+    fn drop(&mut self) {
+        if let Ok(mut jobs) = jobs() {
+            if let Some(job) = jobs.get_mut(&self.job_id) {
+                job.running = false;
+            }
+        }
+    }
+}
+
+/// Registers the tool process of the job, `None` once it exited.
+pub fn set_job_child(job_id: &str, child: Option<Arc<Mutex<Child>>>) -> Result<()> {
+    if let Some(job) = jobs()?.get_mut(job_id) {
+        job.child = child;
+    }
+
+    Ok(())
+}
+
+/// Where progress goes besides the log, set once at start.
+pub fn set_progress_sink(sink: ProgressSink) {
+    if PROGRESS_SINK.set(sink).is_err() {
+        log::warn!("progress sink already set");
+    }
+}
+
+pub fn report_progress(progress: JobProgress) {
+    // tool lines are logged as they are read
+    if matches!(
+        progress.status,
+        StageStatus::Started | StageStatus::Finished
+    ) {
+        log::info!(
+            "{} {:?} {:?} {} {}",
+            progress.job_id,
+            progress.stage,
+            progress.status,
+            progress.message,
+            progress
+                .elapsed_ms
+                .map(|elapsed_ms| format!("{}ms", elapsed_ms))
+                .unwrap_or_default()
+        );
+    }
+
+    if let Some(sink) = PROGRESS_SINK.get() {
+        sink(&progress);
+    }
+}
+
+/// Times one stage of a job, reports when it starts and finishes.
+pub struct StageTimer {
+    job_id: String,
+    stage: JobStage,
+    started: Instant,
+}
+
+/// Starts a stage, fails when the job was cancelled meanwhile.
+pub fn start_stage(job_id: &str, stage: JobStage, message: &str) -> Result<StageTimer> {
+    check_cancelled(job_id)?;
+
+    report_progress(JobProgress {
+        job_id: job_id.to_string(),
+        stage,
+        status: StageStatus::Started,
+        message: message.to_string(),
+        elapsed_ms: None,
+    });
+
+    Ok(StageTimer {
+        job_id: job_id.to_string(),
+        stage,
+        started: Instant::now(),
+    })
+}
+
+impl StageTimer {
+    pub fn finish(self) {
+        report_progress(JobProgress {
+            job_id: self.job_id,
+            stage: self.stage,
+            status: StageStatus::Finished,
+            message: String::new(),
+            elapsed_ms: Some(self.started.elapsed().as_millis() as u64),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{record_progress, reported_progress};
+
+    #[test]
+    fn stages_report_start_and_finish() {
+        record_progress();
+        let job_id = create_job().unwrap();
+
+        let stage = start_stage(&job_id, JobStage::Build, "building").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        stage.finish();
+
+        let reported = reported_progress(&job_id);
+        assert_eq!(reported.len(), 2);
+        assert_eq!(
+            (
+                reported[0].stage,
+                reported[0].status,
+                reported[0].message.as_str()
+            ),
+            (JobStage::Build, StageStatus::Started, "building")
+        );
+        assert_eq!(reported[0].elapsed_ms, None);
+        assert_eq!(
+            (reported[1].stage, reported[1].status),
+            (JobStage::Build, StageStatus::Finished)
+        );
+        assert!(reported[1].elapsed_ms.unwrap() >= 20);
+        finish_job(&job_id).unwrap();
+    }
+
+    #[test]
+    fn cancelled_job_starts_no_stage() {
+        let job_id = create_job().unwrap();
+        let run = run_job(&job_id).unwrap();
+        let workspace = job_workspace(&job_id).unwrap();
+
+        cancel_job(&job_id).unwrap();
+
+        // the running pipeline owns the workspace until it gives up
+        assert!(workspace.exists());
+        let e = start_stage(&job_id, JobStage::Edit, "").err().unwrap();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(RepackError::Cancelled { .. })
+        ));
+        assert!(run_job(&job_id).is_err());
+        drop(run);
+        finish_job(&job_id).unwrap();
+        assert!(!workspace.exists());
+    }
+
+    #[test]
+    fn idle_job_is_finished_by_cancel() {
+        let job_id = create_job().unwrap();
+        let workspace = job_workspace(&job_id).unwrap();
+
+        cancel_job(&job_id).unwrap();
+
+        assert!(!workspace.exists());
+        let e = check_cancelled(&job_id).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(RepackError::JobNotFound { .. })
+        ));
+        // unknown ids are ignored
+        cancel_job(&job_id).unwrap();
+    }
+}
@@ -1,146 +1,58 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
-#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-
-use anyhow::Result;
-use apk_util::get_apk_info;
-use constants::get_cache_folder;
-use prepare::extract_tools;
-use std::path::Path;
-use std::vec;
-
-use tauri_plugin_log::LogTarget;
+#![cfg_attr(
+    all(not(debug_assertions), feature = "gui"),
+    windows_subsystem = "windows"
+)]
 
+mod apk_signer;
+mod apk_signing_block;
 mod apk_util;
+mod badge_util;
+mod channel_util;
+mod cli;
 mod constants;
+mod decode_cache;
+mod error;
+#[cfg(feature = "gui")]
+mod gui;
+mod icon_source;
+mod icon_util;
+mod java_util;
+mod job;
+mod meta_data_util;
 mod prepare;
+mod recipe;
+mod repack;
 mod run_command;
+mod settings;
+mod sign_config;
+mod strings_util;
+#[cfg(test)]
+mod test_util;
 mod xml_util;
+mod yml_util;
+mod zip_util;
 
-use crate::apk_util::exchange_apk_display_name;
-use crate::apk_util::exchange_apk_icon;
-use crate::apk_util::exchange_apk_package_name;
-use crate::apk_util::get_apk_vasdolly_channel;
-use crate::apk_util::pack_apk;
-use crate::apk_util::set_apk_vasdolly_channel;
-use crate::apk_util::sign_apk;
-use crate::apk_util::unpack_apk;
-
-#[tauri::command]
-fn get_env(name: &str) -> String {
-    std::env::var(String::from(name)).unwrap_or(String::from(""))
-}
-
-#[tauri::command]
-fn set_env(name: &str, value: &str) {
-    log::info!("set_env: {} => {}", name, value);
-    std::env::set_var(String::from(name), String::from(value));
-}
-
-#[tauri::command]
-fn get_cache_dir() -> String {
-    get_cache_folder().to_str().unwrap().to_string()
-}
-
-#[tauri::command]
-async fn unpack_and_get_apk_info(apk_path: String) -> String {
-    let apk_folder = unpack_apk(&apk_path).await;
-    if apk_folder.is_err() {
-        return format!("error unpack apk: {}", apk_folder.err().unwrap());
-    }
-
-    let apk_folder = apk_folder.unwrap();
-    let apk_info = get_apk_info(&apk_folder).await;
-    if apk_info.is_err() {
-        return format!("error get apk info: {}", apk_info.err().unwrap());
-    }
-
-    let apk_info = apk_info.unwrap();
-
-    serde_json::to_string(&apk_info).unwrap()
-}
-
-async fn do_change_content_and_repack(
-    apk_file_path: String,
-    apk_package_name: String,
-    apk_display_name: String,
-    apk_icon_file_path: String,
-) -> Result<()> {
-    let apk_folder = unpack_apk(&apk_file_path).await?;
-    exchange_apk_package_name(&apk_folder, &apk_package_name)?;
-    exchange_apk_display_name(&apk_folder, &apk_display_name)?;
-    exchange_apk_icon(&apk_folder, &apk_icon_file_path)?;
-
-    let repacked_apk = pack_apk(&apk_folder).await?;
-    sign_apk(&repacked_apk).await?;
-
-    let channel = get_apk_vasdolly_channel(&apk_file_path).await?;
-    log::info!("channel: {}", channel);
-
-    if channel.is_empty() || channel == "null" {
-        log::info!("channel not found");
-    } else {
-        set_apk_vasdolly_channel(&repacked_apk, &channel).await?;
-    }
-
-    let des = apk_file_path + ".repacked.apk";
-    let des = Path::new(&des);
-    if des.exists() {
-        std::fs::remove_file(des)?;
-    }
-
-    std::fs::rename(&repacked_apk, des)?;
-
-    Ok(())
-}
-
-#[tauri::command]
-async fn change_content_and_repack_apk(
-    apk_file_path: String,
-    apk_package_name: String,
-    apk_display_name: String,
-    apk_icon_file_path: String,
-) -> String {
-    let result = do_change_content_and_repack(
-        apk_file_path,
-        apk_package_name,
-        apk_display_name,
-        apk_icon_file_path,
-    )
-    .await;
-
-    match result {
-        Err(e) => {
-            log::error!("{:?}", e);
-            "error".to_string()
+fn main() {
+    // `env::args` panics on an argument that is not utf-8
+    let args = match std::env::args_os()
+        .skip(1)
+        .map(|arg| arg.into_string())
+        .collect::<Result<Vec<String>, _>>()
+    {
+        Ok(args) => args,
+        Err(arg) => {
+            eprintln!("argument is not valid UTF-8: {}", arg.to_string_lossy());
+            std::process::exit(cli::EXIT_USAGE);
         }
-        Ok(_) => "success".to_string(),
-    }
-}
+    };
 
-fn main() {
-    let result = extract_tools();
-    if result.is_err() {
-        log::error!("{:?}", result.err().unwrap());
+    // the window only opens without a command, ci never needs a display
+    #[cfg(feature = "gui")]
+    if !cli::is_cli_command(&args) {
+        gui::run();
         return;
     }
 
-    tauri::Builder::default()
-        .plugin(
-            tauri_plugin_log::Builder::default()
-                .targets([
-                    LogTarget::Folder(get_cache_folder().to_owned()),
-                    LogTarget::Stdout,
-                    LogTarget::Webview,
-                ])
-                .build(),
-        )
-        .invoke_handler(tauri::generate_handler![
-            get_env,
-            set_env,
-            get_cache_dir,
-            unpack_and_get_apk_info,
-            change_content_and_repack_apk,
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+    std::process::exit(cli::run(&args));
 }
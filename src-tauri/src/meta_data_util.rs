@@ -0,0 +1,300 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::xml_util::{selector_literal, XmlDocument, XmlElement};
+
+/// components that may hold `<meta-data>` besides the application
+const META_DATA_OWNERS: &[&str] = &[
+    "activity",
+    "activity-alias",
+    "service",
+    "receiver",
+    "provider",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetaData {
+    /// `android:name` of the owning component, empty for the application
+    pub component: String,
+    pub name: String,
+    pub value: Option<String>,
+    pub resource: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MetaDataEdit {
+    /// changes the value of an existing entry
+    Set {
+        #[serde(default)]
+        component: String,
+        name: String,
+        value: String,
+    },
+    /// adds an entry that must not exist yet
+    Add {
+        #[serde(default)]
+        component: String,
+        name: String,
+        value: String,
+    },
+    Remove {
+        #[serde(default)]
+        component: String,
+        name: String,
+    },
+}
+
+impl MetaDataEdit {
+    fn target(&self) -> (&str, &str) {
+        match self {
+            MetaDataEdit::Set {
+                component, name, ..
+            }
+            | MetaDataEdit::Add {
+                component, name, ..
+            }
+            | MetaDataEdit::Remove { component, name } => (component, name),
+        }
+    }
+}
+
+/// `.Main` and `Main` are relative to the manifest package
+fn resolve_class_name(package_name: &str, name: &str) -> String {
+    if name.starts_with('.') {
+        format!("{}{}", package_name, name)
+    } else if !name.contains('.') {
+        format!("{}.{}", package_name, name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// `(component, selector)` of the application and every component in it.
+fn list_owners(document: &XmlDocument) -> Result<Vec<(String, String)>> {
+    let mut owners = vec![(String::new(), "manifest/application".to_string())];
+    for owner in META_DATA_OWNERS {
+        let selector = format!("manifest/application/{}", owner);
+        for element in document.find(&selector)? {
+            let Some(component) = element.ns_attr("android:name") else {
+                continue;
+            };
+            owners.push((
+                component.to_string(),
+                format!(
+                    "{}[@android:name={}]",
+                    selector,
+                    selector_literal(component)?
+                ),
+            ));
+        }
+    }
+
+    Ok(owners)
+}
+
+/// Selector of the owner of `component`, relative names match absolute ones.
+fn find_owner(document: &XmlDocument, component: &str) -> Result<String> {
+    let package_name = document
+        .find_attr("manifest", "package")?
+        .pop()
+        .unwrap_or_default();
+
+    list_owners(document)?
+        .into_iter()
+        .find(|(owner, _)| {
+            owner == component
+                || !owner.is_empty()
+                    && !component.is_empty()
+                    && resolve_class_name(&package_name, owner)
+                        == resolve_class_name(&package_name, component)
+        })
+        .map(|(_, selector)| selector)
+        .ok_or_else(|| anyhow::anyhow!("component not found: {}", component))
+}
+
+pub fn list_meta_data(xml_content: &str) -> Result<Vec<MetaData>> {
+    let document = XmlDocument::parse(xml_content)?;
+
+    let mut list = vec![];
+    for (component, selector) in list_owners(&document)? {
+        for element in document.find(&format!("{}/meta-data", selector))? {
+            list.push(MetaData {
+                component: component.clone(),
+                name: element
+                    .ns_attr("android:name")
+                    .unwrap_or_default()
+                    .to_string(),
+                value: element.ns_attr("android:value").map(str::to_string),
+                resource: element.ns_attr("android:resource").map(str::to_string),
+            });
+        }
+    }
+
+    Ok(list)
+}
+
+/// Applies one edit, fails when the entry to set or remove does not exist,
+/// or the entry to add does.
+pub fn edit_meta_data(xml_content: &str, edit: &MetaDataEdit) -> Result<String> {
+    let (component, name) = edit.target();
+
+    let mut document = XmlDocument::parse(xml_content)?;
+    let owner = find_owner(&document, component)?;
+    let selector = format!(
+        "{}/meta-data[@android:name={}]",
+        owner,
+        selector_literal(name)?
+    );
+    let exists = !document.find(&selector)?.is_empty();
+
+    match edit {
+        MetaDataEdit::Add { .. } if exists => {
+            return Err(anyhow::anyhow!("meta-data {} already exists", name))
+        }
+        MetaDataEdit::Set { .. } | MetaDataEdit::Remove { .. } if !exists => {
+            return Err(anyhow::anyhow!("meta-data {} not found", name))
+        }
+        _ => {}
+    }
+
+    match edit {
+        MetaDataEdit::Set { value, .. } => {
+            // a value replaces a resource reference
+            document.remove_attr(&selector, "android:resource")?;
+            document.set_attr(&selector, "android:value", value)?;
+        }
+        MetaDataEdit::Add { value, .. } => {
+            let meta_data = XmlElement::new("meta-data")
+                .with_attr("android:name", name)
+                .with_attr("android:value", value);
+            document.insert_child(&owner, &meta_data)?;
+        }
+        MetaDataEdit::Remove { .. } => {
+            document.remove_node(&selector)?;
+        }
+    }
+
+    document.to_xml_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="no"?>
+<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.demo">
+    <application android:label="@string/app_name">
+        <meta-data android:name="CHANNEL" android:value="google"/>
+        <meta-data android:name="KEY" android:resource="@string/key"/>
+        <activity android:name=".MainActivity">
+            <meta-data android:name="SCREEN" android:value="main"/>
+        </activity>
+    </application>
+</manifest>
+"#;
+
+    fn entries(xml_content: &str) -> Vec<(String, String, Option<String>, Option<String>)> {
+        list_meta_data(xml_content)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.component, entry.name, entry.value, entry.resource))
+            .collect()
+    }
+
+    fn set(component: &str, name: &str, value: &str) -> MetaDataEdit {
+        MetaDataEdit::Set {
+            component: component.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn add(component: &str, name: &str, value: &str) -> MetaDataEdit {
+        MetaDataEdit::Add {
+            component: component.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn remove(component: &str, name: &str) -> MetaDataEdit {
+        MetaDataEdit::Remove {
+            component: component.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn lists_application_and_component_entries() {
+        assert_eq!(
+            entries(MANIFEST),
+            [
+                ("".to_string(), "CHANNEL".to_string(), some("google"), None),
+                ("".to_string(), "KEY".to_string(), None, some("@string/key")),
+                (
+                    ".MainActivity".to_string(),
+                    "SCREEN".to_string(),
+                    some("main"),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_replaces_value_and_resource() {
+        let xml_content = edit_meta_data(MANIFEST, &set("", "KEY", "abc")).unwrap();
+        // a full class name finds the relative one
+        let xml_content = edit_meta_data(
+            &xml_content,
+            &set("com.example.demo.MainActivity", "SCREEN", "home"),
+        )
+        .unwrap();
+
+        let list = entries(&xml_content);
+        assert_eq!(
+            list[1],
+            ("".to_string(), "KEY".to_string(), some("abc"), None)
+        );
+        assert_eq!(list[2].2, some("home"));
+    }
+
+    #[test]
+    fn add_and_remove_entries() {
+        let xml_content = edit_meta_data(MANIFEST, &add(".MainActivity", "MODE", "dark")).unwrap();
+        let xml_content = edit_meta_data(&xml_content, &remove("", "CHANNEL")).unwrap();
+
+        let names = entries(&xml_content)
+            .into_iter()
+            .map(|(component, name, ..)| format!("{}/{}", component, name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["/KEY", ".MainActivity/SCREEN", ".MainActivity/MODE"]
+        );
+    }
+
+    #[test]
+    fn edits_of_wrong_entries_fail() {
+        let error = |edit: MetaDataEdit| edit_meta_data(MANIFEST, &edit).unwrap_err().to_string();
+
+        assert_eq!(
+            error(set("", "MISSING", "1")),
+            "meta-data MISSING not found"
+        );
+        assert_eq!(error(remove("", "SCREEN")), "meta-data SCREEN not found");
+        assert_eq!(
+            error(add("", "CHANNEL", "1")),
+            "meta-data CHANNEL already exists"
+        );
+        assert_eq!(
+            error(add(".SplashActivity", "MODE", "1")),
+            "component not found: .SplashActivity"
+        );
+    }
+}
@@ -0,0 +1,715 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    apk_util::is_valid_package_name,
+    badge_util::IconBadge,
+    channel_util::{read_channel_file, ChannelScheme},
+    error::{RepackError, RepackResultExt},
+    icon_source::{check_icon_source, IconSource},
+    icon_util::{parse_color, AdaptiveIconOptions, IconBackground},
+    meta_data_util::MetaDataEdit,
+    repack::{check_output_name_template, RepackParams},
+    sign_config::{SignConfig, KEY_PASSWORD_ENV, STORE_PASSWORD_ENV},
+};
+
+/// A saved set of repack steps, written as toml or json:
+///
+/// ```toml
+/// [[steps]]
+/// op = "package"
+/// name = "com.example.qa"
+///
+/// [[steps]]
+/// op = "badge"
+/// text = "QA"
+/// ```
+///
+/// Relative paths are relative to the recipe file. The steps run in the
+/// order of the pipeline, `label` and `meta_data` may repeat and apply in
+/// file order, every other step may appear once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Recipe {
+    pub steps: Vec<RecipeStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RecipeStep {
+    Package {
+        name: String,
+        #[serde(default)]
+        rename_references: bool,
+    },
+    Label {
+        /// default locale, empty keeps the original
+        #[serde(default)]
+        name: String,
+        /// label per values folder, e.g. `values-ja`
+        #[serde(default)]
+        locales: BTreeMap<String, String>,
+    },
+    Icon {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        adaptive: Option<AdaptiveIconOptions>,
+    },
+    Badge(IconBadge),
+    MetaData {
+        edits: Vec<MetaDataEdit>,
+    },
+    Version {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<u32>,
+        #[serde(default)]
+        bump_code: bool,
+        #[serde(default)]
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_sdk: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target_sdk: Option<u32>,
+    },
+    Channels {
+        #[serde(default)]
+        names: Vec<String>,
+        /// channels one per line, added after `names`
+        #[serde(default)]
+        file: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scheme: Option<ChannelScheme>,
+        #[serde(default)]
+        extras: BTreeMap<String, String>,
+    },
+    /// passwords are never saved, empty ones are read from
+    /// `REPACK_STORE_PASSWORD` and `REPACK_KEY_PASSWORD`
+    Sign {
+        keystore: String,
+        key_alias: String,
+        #[serde(default, skip_serializing)]
+        store_password: String,
+        #[serde(default, skip_serializing)]
+        key_password: String,
+    },
+    Output {
+        /// empty writes the outputs next to the input apk
+        #[serde(default)]
+        folder: String,
+        /// e.g. `{package}-{channel}.apk`
+        #[serde(default)]
+        name_template: String,
+    },
+}
+
+impl RecipeStep {
+    fn op(&self) -> &'static str {
+        match self {
+            RecipeStep::Package { .. } => "package",
+            RecipeStep::Label { .. } => "label",
+            RecipeStep::Icon { .. } => "icon",
+            RecipeStep::Badge(_) => "badge",
+            RecipeStep::MetaData { .. } => "meta_data",
+            RecipeStep::Version { .. } => "version",
+            RecipeStep::Channels { .. } => "channels",
+            RecipeStep::Sign { .. } => "sign",
+            RecipeStep::Output { .. } => "output",
+        }
+    }
+
+    /// Every problem of the step, paths are already resolved.
+    fn check(&self) -> Vec<String> {
+        let mut issues = vec![];
+        let missing =
+            |path: &str| (!Path::new(path).is_file()).then(|| format!("file not found: {}", path));
+
+        match self {
+            RecipeStep::Package { name, .. } => {
+                if !is_valid_package_name(name) {
+                    return vec![format!("bad package name: {:?}", name)];
+                }
+            }
+            RecipeStep::Label { name, locales } => {
+                if name.is_empty() && locales.is_empty() {
+                    return vec!["needs a name or locales".to_string()];
+                }
+                for folder in locales.keys() {
+                    if folder != "values" && !folder.starts_with("values-") {
+                        issues.push(format!("bad values folder: {}", folder));
+                    }
+                }
+            }
+            RecipeStep::Icon { path, adaptive } => {
+                issues.extend(missing(path));
+                match adaptive.as_ref().map(|options| &options.background) {
+                    Some(IconBackground::Color(color)) => {
+                        if let Err(e) = parse_color(color) {
+                            issues.push(e.to_string());
+                        }
+                    }
+                    Some(IconBackground::Image(path)) => issues.extend(missing(path)),
+                    None => {}
+                }
+                // the file is only decoded once it exists
+                if issues.is_empty() {
+                    if let Err(e) = check_icon_source(path) {
+                        issues.push(e.to_string());
+                    }
+                    if let Some(AdaptiveIconOptions {
+                        background: IconBackground::Image(path),
+                        ..
+                    }) = adaptive
+                    {
+                        if let Err(e) = IconSource::open(path) {
+                            issues.push(e.to_string());
+                        }
+                    }
+                }
+            }
+            RecipeStep::Badge(badge) => {
+                if badge.text.trim().is_empty() {
+                    issues.push("text is empty".to_string());
+                }
+                for color in [&badge.color, &badge.text_color] {
+                    if let Err(e) = parse_color(color) {
+                        issues.push(e.to_string());
+                    }
+                }
+            }
+            RecipeStep::MetaData { edits } => {
+                if edits.is_empty() {
+                    issues.push("no edits".to_string());
+                }
+            }
+            RecipeStep::Version {
+                code,
+                bump_code,
+                name,
+                min_sdk,
+                target_sdk,
+            } => {
+                if code.is_none()
+                    && !bump_code
+                    && name.is_empty()
+                    && min_sdk.is_none()
+                    && target_sdk.is_none()
+                {
+                    issues.push("changes nothing".to_string());
+                }
+                if code.is_some() && *bump_code {
+                    issues.push("code and bump_code exclude each other".to_string());
+                }
+                if let (Some(min_sdk), Some(target_sdk)) = (min_sdk, target_sdk) {
+                    if min_sdk > target_sdk {
+                        issues.push(format!(
+                            "min_sdk {} is above target_sdk {}",
+                            min_sdk, target_sdk
+                        ));
+                    }
+                }
+            }
+            RecipeStep::Channels {
+                names,
+                file,
+                scheme,
+                extras,
+            } => {
+                if names.is_empty() && file.is_empty() {
+                    issues.push("needs names or a file".to_string());
+                }
+                if !file.is_empty() {
+                    issues.extend(missing(file));
+                }
+                if !extras.is_empty() && scheme.is_some_and(|scheme| scheme != ChannelScheme::Walle)
+                {
+                    issues.push("extras need the walle scheme".to_string());
+                }
+            }
+            RecipeStep::Sign {
+                keystore,
+                key_alias,
+                ..
+            } => {
+                issues.extend(missing(keystore));
+                if key_alias.is_empty() {
+                    issues.push("key_alias is empty".to_string());
+                }
+            }
+            RecipeStep::Output { .. } => {}
+        }
+
+        issues
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+}
+
+impl Recipe {
+    /// Makes relative paths relative to `base`.
+    fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |path: &mut String| {
+            if !path.is_empty() && Path::new(path.as_str()).is_relative() {
+                *path = base.join(path.as_str()).to_string_lossy().to_string();
+            }
+        };
+
+        for step in &mut self.steps {
+            match step {
+                RecipeStep::Icon { path, adaptive } => {
+                    resolve(path);
+                    if let Some(AdaptiveIconOptions {
+                        background: IconBackground::Image(path),
+                        ..
+                    }) = adaptive
+                    {
+                        resolve(path);
+                    }
+                }
+                RecipeStep::Channels { file, .. } => resolve(file),
+                RecipeStep::Sign { keystore, .. } => resolve(keystore),
+                RecipeStep::Output { folder, .. } => resolve(folder),
+                _ => {}
+            }
+        }
+    }
+
+    /// Checks every step and reports all problems at once.
+    pub fn validate(&self) -> Result<()> {
+        log::info!("validate recipe: {} steps", self.steps.len());
+
+        let mut problems = vec![];
+        for (index, step) in self.steps.iter().enumerate() {
+            let op = step.op();
+            let repeatable = matches!(step, RecipeStep::Label { .. } | RecipeStep::MetaData { .. });
+            if !repeatable && self.steps[..index].iter().any(|other| other.op() == op) {
+                problems.push(format!(
+                    "step {} ({}): appears more than once",
+                    index + 1,
+                    op
+                ));
+            }
+            for issue in step.check() {
+                problems.push(format!("step {} ({}): {}", index + 1, op, issue));
+            }
+        }
+
+        // a template without {channel} gives every channel the same file
+        let channel_count = self
+            .steps
+            .iter()
+            .map(|step| match step {
+                RecipeStep::Channels { names, file, .. } => {
+                    names.len() + usize::from(!file.is_empty())
+                }
+                _ => 0,
+            })
+            .sum::<usize>();
+        for (index, step) in self.steps.iter().enumerate() {
+            if let RecipeStep::Output { name_template, .. } = step {
+                if let Err(e) = check_output_name_template(name_template) {
+                    problems.push(format!("step {} (output): {}", index + 1, e));
+                }
+                if channel_count > 1
+                    && !name_template.trim().is_empty()
+                    && !name_template.contains("{channel}")
+                {
+                    problems.push(format!(
+                        "step {} (output): name_template needs {{channel}} for several channels",
+                        index + 1
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} problems\n  {}",
+                problems.len(),
+                problems.join("\n  ")
+            ))
+        }
+    }
+
+    /// The repack of `apk_file_path` this recipe describes.
+    pub fn to_params(&self, apk_file_path: &str) -> Result<RepackParams> {
+        let mut params = RepackParams {
+            apk_file_path: apk_file_path.to_string(),
+            ..Default::default()
+        };
+
+        for step in &self.steps {
+            match step.clone() {
+                RecipeStep::Package {
+                    name,
+                    rename_references,
+                } => {
+                    params.apk_package_name = name;
+                    params.rename_package_references = rename_references;
+                }
+                RecipeStep::Label { name, locales } => {
+                    if !name.is_empty() {
+                        params.apk_display_name = name;
+                    }
+                    params.apk_display_names.extend(locales);
+                }
+                RecipeStep::Icon { path, adaptive } => {
+                    params.apk_icon_file_path = path;
+                    params.adaptive_icon = adaptive;
+                }
+                RecipeStep::Badge(badge) => params.icon_badge = Some(badge),
+                RecipeStep::MetaData { mut edits } => params.meta_data_edits.append(&mut edits),
+                RecipeStep::Version {
+                    code,
+                    bump_code,
+                    name,
+                    min_sdk,
+                    target_sdk,
+                } => {
+                    params.apk_version_code = code;
+                    params.auto_increase_version_code = bump_code;
+                    params.apk_version_name = name;
+                    params.apk_min_sdk_version = min_sdk;
+                    params.apk_target_sdk_version = target_sdk;
+                }
+                RecipeStep::Channels {
+                    mut names,
+                    file,
+                    scheme,
+                    extras,
+                } => {
+                    if !file.is_empty() {
+                        names.append(&mut read_channel_file(Path::new(&file))?);
+                    }
+                    params.channels = names;
+                    params.channel_scheme = scheme;
+                    params.channel_extras = extras;
+                }
+                RecipeStep::Sign {
+                    keystore,
+                    key_alias,
+                    store_password,
+                    key_password,
+                } => {
+                    let store_password = if store_password.is_empty() {
+                        std::env::var(STORE_PASSWORD_ENV).unwrap_or_default()
+                    } else {
+                        store_password
+                    };
+                    // keystores made by keytool often share one password
+                    let key_password = if key_password.is_empty() {
+                        std::env::var(KEY_PASSWORD_ENV).unwrap_or_else(|_| store_password.clone())
+                    } else {
+                        key_password
+                    };
+                    params.sign_config = Some(SignConfig {
+                        keystore_path: keystore,
+                        store_password,
+                        key_alias,
+                        key_password,
+                    });
+                }
+                RecipeStep::Output {
+                    folder,
+                    name_template,
+                } => {
+                    params.output_folder = folder;
+                    params.output_name_template = name_template;
+                }
+            }
+        }
+
+        Ok(params)
+    }
+
+    /// The steps that reproduce `params`, the input apk and passwords are
+    /// left out.
+    pub fn from_params(params: &RepackParams) -> Recipe {
+        let mut steps = vec![];
+
+        if !params.apk_package_name.is_empty() {
+            steps.push(RecipeStep::Package {
+                name: params.apk_package_name.clone(),
+                rename_references: params.rename_package_references,
+            });
+        }
+        if !params.apk_display_name.is_empty() || !params.apk_display_names.is_empty() {
+            steps.push(RecipeStep::Label {
+                name: params.apk_display_name.clone(),
+                locales: params.apk_display_names.clone(),
+            });
+        }
+        if !params.apk_icon_file_path.is_empty() {
+            steps.push(RecipeStep::Icon {
+                path: params.apk_icon_file_path.clone(),
+                adaptive: params.adaptive_icon.clone(),
+            });
+        }
+        if let Some(badge) = &params.icon_badge {
+            steps.push(RecipeStep::Badge(badge.clone()));
+        }
+        if !params.meta_data_edits.is_empty() {
+            steps.push(RecipeStep::MetaData {
+                edits: params.meta_data_edits.clone(),
+            });
+        }
+        if params.apk_version_code.is_some()
+            || params.auto_increase_version_code
+            || !params.apk_version_name.is_empty()
+            || params.apk_min_sdk_version.is_some()
+            || params.apk_target_sdk_version.is_some()
+        {
+            steps.push(RecipeStep::Version {
+                code: params.apk_version_code,
+                bump_code: params.apk_version_code.is_none() && params.auto_increase_version_code,
+                name: params.apk_version_name.clone(),
+                min_sdk: params.apk_min_sdk_version,
+                target_sdk: params.apk_target_sdk_version,
+            });
+        }
+        if !params.channels.is_empty() {
+            steps.push(RecipeStep::Channels {
+                names: params.channels.clone(),
+                file: String::new(),
+                scheme: params.channel_scheme,
+                extras: params.channel_extras.clone(),
+            });
+        }
+        if let Some(sign_config) = &params.sign_config {
+            steps.push(RecipeStep::Sign {
+                keystore: sign_config.keystore_path.clone(),
+                key_alias: sign_config.key_alias.clone(),
+                store_password: String::new(),
+                key_password: String::new(),
+            });
+        }
+        if !params.output_folder.is_empty() || !params.output_name_template.is_empty() {
+            steps.push(RecipeStep::Output {
+                folder: params.output_folder.clone(),
+                name_template: params.output_name_template.clone(),
+            });
+        }
+
+        Recipe { steps }
+    }
+}
+
+/// Reads a `.toml` or `.json` recipe and validates it.
+pub fn load_recipe(recipe_path: &Path) -> Result<Recipe> {
+    log::info!("load_recipe: {}", recipe_path.to_string_lossy());
+
+    let load = || -> Result<Recipe> {
+        let content = std::fs::read_to_string(recipe_path)?;
+        let mut recipe: Recipe = if is_toml(recipe_path) {
+            toml::from_str(&content)?
+        } else {
+            serde_json::from_str(&content)?
+        };
+
+        recipe.resolve_paths(recipe_path.parent().unwrap_or(Path::new("")));
+        recipe.validate()?;
+
+        Ok(recipe)
+    };
+
+    load().or_repack_error(|detail| RepackError::InvalidRecipe {
+        path: recipe_path.to_string_lossy().to_string(),
+        detail,
+    })
+}
+
+/// Writes a recipe as toml or json by the file extension.
+pub fn save_recipe(recipe_path: &Path, recipe: &Recipe) -> Result<()> {
+    log::info!("save_recipe: {}", recipe_path.to_string_lossy());
+
+    let content = if is_toml(recipe_path) {
+        toml::to_string_pretty(recipe)?
+    } else {
+        serde_json::to_string_pretty(recipe)?
+    };
+    std::fs::write(recipe_path, content)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    fn problems(recipe: &str) -> String {
+        let recipe: Recipe = toml::from_str(recipe).unwrap();
+        recipe.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let detail = problems(
+            r#"
+            [[steps]]
+            op = "package"
+            name = "com.example"
+
+            [[steps]]
+            op = "package"
+            name = "1bad"
+            "#,
+        );
+        assert!(detail.starts_with("2 problems"), "{}", detail);
+        assert!(detail.contains("step 2 (package): appears more than once"));
+        assert!(detail.contains("step 2 (package): bad package name: \"1bad\""));
+
+        let detail = problems(
+            r#"
+            [[steps]]
+            op = "channels"
+            names = ["google"]
+            scheme = "vasdolly_v2"
+            extras = { region = "eu" }
+            "#,
+        );
+        assert!(detail.contains("step 1 (channels): extras need the walle scheme"));
+
+        let detail = problems(
+            r#"
+            [[steps]]
+            op = "channels"
+            names = ["google", "huawei"]
+
+            [[steps]]
+            op = "output"
+            name_template = "{package}.apk"
+            "#,
+        );
+        assert!(detail.contains("step 2 (output): name_template needs {channel}"));
+    }
+
+    #[test]
+    fn repeatable_steps_are_valid() {
+        let recipe: Recipe = toml::from_str(
+            r#"
+            [[steps]]
+            op = "label"
+            name = "Demo"
+
+            [[steps]]
+            op = "label"
+            locales = { values-ja = "デモ" }
+
+            [[steps]]
+            op = "channels"
+            names = ["google"]
+            scheme = "walle"
+            extras = { region = "eu" }
+
+            [[steps]]
+            op = "output"
+            name_template = "{package}.apk"
+            "#,
+        )
+        .unwrap();
+        recipe.validate().unwrap();
+    }
+
+    #[test]
+    fn params_round_trip_without_passwords() {
+        let params = RepackParams {
+            apk_package_name: "com.example.qa".to_string(),
+            apk_display_name: "Demo QA".to_string(),
+            apk_version_name: "1.2.3".to_string(),
+            auto_increase_version_code: true,
+            channels: vec!["google".to_string(), "huawei".to_string()],
+            sign_config: Some(SignConfig {
+                keystore_path: "/keys/release.jks".to_string(),
+                store_password: "store-secret".to_string(),
+                key_alias: "release".to_string(),
+                key_password: "key-secret".to_string(),
+            }),
+            output_name_template: "{package}-{channel}.apk".to_string(),
+            ..Default::default()
+        };
+
+        let recipe = Recipe::from_params(&params);
+        for content in [
+            toml::to_string_pretty(&recipe).unwrap(),
+            serde_json::to_string_pretty(&recipe).unwrap(),
+        ] {
+            assert!(!content.contains("secret"), "{}", content);
+            assert!(!content.contains("password"), "{}", content);
+        }
+
+        let restored = recipe.to_params("demo.apk").unwrap();
+        assert_eq!(restored.apk_file_path, "demo.apk");
+        assert_eq!(restored.apk_package_name, params.apk_package_name);
+        assert_eq!(restored.apk_display_name, params.apk_display_name);
+        assert_eq!(restored.apk_version_name, params.apk_version_name);
+        assert!(restored.auto_increase_version_code);
+        assert_eq!(restored.channels, params.channels);
+        assert_eq!(restored.output_name_template, params.output_name_template);
+        let sign_config = restored.sign_config.unwrap();
+        assert_eq!(sign_config.keystore_path, "/keys/release.jks");
+        assert_eq!(sign_config.key_alias, "release");
+    }
+
+    #[test]
+    fn recipes_load_from_toml_and_json() {
+        let folder = TestFolder::new("recipe-load".as_ref());
+        folder.write("keys/release.jks", b"");
+        folder.write("channels.txt", b"google\nhuawei\n");
+
+        let toml_path = folder.write(
+            "recipe.toml",
+            br#"
+            [[steps]]
+            op = "channels"
+            file = "channels.txt"
+
+            [[steps]]
+            op = "sign"
+            keystore = "keys/release.jks"
+            key_alias = "release"
+            "#,
+        );
+        let json_path = folder.write(
+            "recipe.json",
+            br#"{"steps": [
+                {"op": "channels", "file": "channels.txt"},
+                {"op": "sign", "keystore": "keys/release.jks", "key_alias": "release"}
+            ]}"#,
+        );
+
+        for recipe_path in [toml_path, json_path] {
+            let params = load_recipe(&recipe_path)
+                .unwrap()
+                .to_params("demo.apk")
+                .unwrap();
+            assert_eq!(params.channels, ["google", "huawei"]);
+            assert_eq!(
+                Path::new(&params.sign_config.unwrap().keystore_path),
+                folder.0.join("keys/release.jks")
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_recipe_names_the_file() {
+        let folder = TestFolder::new("recipe-invalid".as_ref());
+        let recipe_path = folder.write(
+            "recipe.toml",
+            b"[[steps]]\nop = \"sign\"\nkeystore = \"missing.jks\"\nkey_alias = \"\"\n",
+        );
+
+        let e = load_recipe(&recipe_path).unwrap_err();
+        let Some(RepackError::InvalidRecipe { path, detail }) = e.downcast_ref() else {
+            panic!("{:?}", e);
+        };
+        assert_eq!(Path::new(path), recipe_path);
+        assert!(detail.contains("file not found"), "{}", detail);
+        assert!(detail.contains("key_alias is empty"), "{}", detail);
+    }
+}
@@ -0,0 +1,466 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    apk_util::{
+        exchange_apk_display_name, exchange_apk_display_names, exchange_apk_meta_data,
+        exchange_apk_package_name, exchange_apk_sdk_versions, exchange_apk_version_code,
+        exchange_apk_version_name, get_apk_display_name, get_apk_package_name,
+        increase_apk_version_code, pack_apk, rename_apk_package, sign_apk, unpack_apk,
+    },
+    badge_util::{add_apk_icon_badge, IconBadge},
+    channel_util::{get_apk_channel, set_apk_channel, ChannelInfo, ChannelScheme},
+    constants::DEFAULT_OUTPUT_NAME_TEMPLATE,
+    error::{RepackError, RepackResultExt},
+    icon_source::{check_icon_source, IconSource},
+    icon_util::{
+        exchange_apk_adaptive_icon, exchange_apk_icon, AdaptiveIconOptions, IconBackground,
+    },
+    job::{run_job, start_stage, JobStage},
+    meta_data_util::MetaDataEdit,
+    settings::get_settings,
+    sign_config::{verify_sign_config, SignConfig},
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepackParams {
+    pub apk_file_path: String,
+    /// empty keeps the original value
+    pub apk_package_name: String,
+    /// also rewrite authorities, permissions and other attributes derived
+    /// from the old package, so both apks install side by side
+    pub rename_package_references: bool,
+    pub apk_display_name: String,
+    /// label per values folder, e.g. `values-ja`, applied after `apk_display_name`
+    pub apk_display_names: BTreeMap<String, String>,
+    pub apk_icon_file_path: String,
+    /// builds adaptive icon layers from the icon instead of replacing the
+    /// existing bitmaps
+    pub adaptive_icon: Option<AdaptiveIconOptions>,
+    /// stamped on the new icon, or on the original one when no icon is given
+    pub icon_badge: Option<IconBadge>,
+    /// `None` keeps the original value
+    pub apk_version_code: Option<u32>,
+    /// bumps the version code by one, ignored when `apk_version_code` is set
+    pub auto_increase_version_code: bool,
+    pub apk_version_name: String,
+    pub apk_min_sdk_version: Option<u32>,
+    pub apk_target_sdk_version: Option<u32>,
+    /// applied in order after the other manifest edits
+    pub meta_data_edits: Vec<MetaDataEdit>,
+    /// `None` signs as the settings say
+    pub sign_config: Option<SignConfig>,
+    /// one output per channel, empty keeps the channel of the input apk
+    pub channels: Vec<String>,
+    /// empty uses the settings, then writes next to the input apk
+    pub output_folder: String,
+    /// e.g. `{package}-{channel}.apk`, empty uses the settings, then the
+    /// default template
+    pub output_name_template: String,
+    /// `None` keeps the scheme of the input apk, VasDolly v2 when it has none
+    pub channel_scheme: Option<ChannelScheme>,
+    /// merged over the Walle extras of the input apk, Walle only
+    pub channel_extras: BTreeMap<String, String>,
+}
+
+/// A template is a file name, it must not leave the output folder.
+pub fn check_output_name_template(template: &str) -> Result<()> {
+    if template.contains(['/', '\\']) || template.contains("..") {
+        return Err(anyhow::anyhow!(
+            "output name template must be a file name without / \\ or ..: {}",
+            template
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fills `{package}`, `{name}` and `{channel}` of the template into a file name.
+pub fn render_output_name(
+    template: &str,
+    package: &str,
+    name: &str,
+    channel: &str,
+) -> Result<String> {
+    let template = if template.trim().is_empty() {
+        DEFAULT_OUTPUT_NAME_TEMPLATE
+    } else {
+        template.trim()
+    };
+    check_output_name_template(template)?;
+
+    // values must not escape the output folder
+    let sanitize = |value: &str| {
+        value
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect::<String>()
+    };
+
+    let mut output_name = template
+        .replace("{package}", &sanitize(package))
+        .replace("{name}", &sanitize(name))
+        .replace("{channel}", &sanitize(channel));
+
+    if !output_name.to_lowercase().ends_with(".apk") {
+        output_name.push_str(".apk");
+    }
+
+    Ok(output_name)
+}
+
+/// An existing output may be the input apk itself, by another path.
+fn check_not_input_apk(des: &Path, apk_file_path: &str) -> Result<()> {
+    if des.canonicalize().ok() == Some(Path::new(apk_file_path).canonicalize()?) {
+        return Err(anyhow::anyhow!(
+            "output {} would overwrite the input apk",
+            des.to_string_lossy()
+        ));
+    }
+
+    Ok(())
+}
+
+/// The output paths, one per channel. Without channels there is one output
+/// that keeps the channel of the input, named by the template with an empty
+/// `{channel}`, or `{apk}.repacked.apk` when no template is set.
+fn output_targets<'a>(
+    apk_file_path: &str,
+    output_folder: &Path,
+    output_name_template: &str,
+    package_name: &str,
+    display_name: &str,
+    original_channel: Option<&'a str>,
+    channels: &[&'a str],
+) -> Result<Vec<(Option<&'a str>, PathBuf)>> {
+    if channels.is_empty() {
+        let output_name = if output_name_template.trim().is_empty() {
+            let apk_file_name = Path::new(apk_file_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            format!("{}.repacked.apk", apk_file_name)
+        } else {
+            render_output_name(output_name_template, package_name, display_name, "")?
+        };
+        let des = output_folder.join(output_name);
+        check_not_input_apk(&des, apk_file_path)?;
+        return Ok(vec![(original_channel, des)]);
+    }
+
+    let mut targets: Vec<(Option<&str>, PathBuf)> = vec![];
+    for channel in channels {
+        let output_name =
+            render_output_name(output_name_template, package_name, display_name, channel)?;
+        let des = output_folder.join(output_name);
+        log::info!("channel {} => {}", channel, des.to_string_lossy());
+        check_not_input_apk(&des, apk_file_path)?;
+
+        if targets.iter().any(|(_, target)| *target == des) {
+            return Err(anyhow::anyhow!(
+                "output name template gives the same file for several channels: {}",
+                des.to_string_lossy()
+            ));
+        }
+        targets.push((Some(channel), des));
+    }
+
+    Ok(targets)
+}
+
+/// Unpacks, edits, packs and signs once in the workspace of the job, then
+/// writes one apk per channel. VasDolly v1 outputs are signed one by one
+/// after the channel is written. Every stage reports its progress.
+/// Returns the output apk paths.
+pub async fn do_change_content_and_repack(
+    params: &RepackParams,
+    job_id: &str,
+) -> Result<Vec<PathBuf>> {
+    let _run = run_job(job_id)?;
+
+    // what the request leaves out comes from the settings
+    let settings = get_settings();
+    let sign_config = params
+        .sign_config
+        .clone()
+        .or(settings.sign_config)
+        .unwrap_or_default();
+    verify_sign_config(&sign_config)?;
+    // icons are read before the long unpack, issues are only logged
+    if !params.apk_icon_file_path.is_empty() {
+        check_icon_source(&params.apk_icon_file_path)?;
+    }
+    if let Some(AdaptiveIconOptions {
+        background: IconBackground::Image(background_path),
+        ..
+    }) = &params.adaptive_icon
+    {
+        IconSource::open(background_path)?;
+    }
+    check_output_name_template(&params.output_name_template)?;
+    check_output_name_template(&settings.output_name_template)?;
+
+    let apk_file_path = &params.apk_file_path;
+    let stage = start_stage(job_id, JobStage::Decode, apk_file_path)?;
+    let apk_folder = unpack_apk(job_id, apk_file_path).await?;
+    stage.finish();
+
+    let stage = start_stage(job_id, JobStage::Edit, "")?;
+    if !params.apk_package_name.is_empty() {
+        if params.rename_package_references {
+            rename_apk_package(&apk_folder, &params.apk_package_name)?;
+        } else {
+            exchange_apk_package_name(&apk_folder, &params.apk_package_name)?;
+        }
+    }
+    if !params.apk_display_name.is_empty() {
+        exchange_apk_display_name(&apk_folder, &params.apk_display_name)?;
+    }
+    if !params.apk_display_names.is_empty() {
+        exchange_apk_display_names(&apk_folder, &params.apk_display_names)?;
+    }
+    if !params.apk_icon_file_path.is_empty() {
+        match &params.adaptive_icon {
+            Some(options) => {
+                exchange_apk_adaptive_icon(&apk_folder, &params.apk_icon_file_path, options)?
+            }
+            None => exchange_apk_icon(&apk_folder, &params.apk_icon_file_path)?,
+        }
+    }
+    if let Some(badge) = &params.icon_badge {
+        add_apk_icon_badge(&apk_folder, badge)?;
+    }
+    if let Some(version_code) = params.apk_version_code {
+        exchange_apk_version_code(&apk_folder, version_code)?;
+    } else if params.auto_increase_version_code {
+        let version_code = increase_apk_version_code(&apk_folder)?;
+        log::info!("version code increased to {}", version_code);
+    }
+    if !params.apk_version_name.is_empty() {
+        exchange_apk_version_name(&apk_folder, &params.apk_version_name)?;
+    }
+    if params.apk_min_sdk_version.is_some() || params.apk_target_sdk_version.is_some() {
+        exchange_apk_sdk_versions(
+            &apk_folder,
+            params.apk_min_sdk_version,
+            params.apk_target_sdk_version,
+        )?;
+    }
+    if !params.meta_data_edits.is_empty() {
+        exchange_apk_meta_data(&apk_folder, &params.meta_data_edits)?;
+    }
+    let package_name = get_apk_package_name(&apk_folder)?;
+    // the label after the edits, only used to name the outputs
+    let display_name = get_apk_display_name(&apk_folder).unwrap_or_else(|e| {
+        log::warn!("no display name for the output name: {}", e);
+        String::new()
+    });
+    stage.finish();
+
+    let original_channel = get_apk_channel(Path::new(apk_file_path)).or_repack_error(|detail| {
+        RepackError::ChannelFailed {
+            apk: apk_file_path.to_string(),
+            detail,
+        }
+    })?;
+    log::info!("channel: {:?}", original_channel);
+
+    let scheme = match (params.channel_scheme, original_channel.scheme) {
+        (Some(scheme), _) => scheme,
+        (None, ChannelScheme::None) => ChannelScheme::VasDollyV2,
+        (None, scheme) => scheme,
+    };
+    let mut extras = original_channel.extras.clone();
+    extras.extend(
+        params
+            .channel_extras
+            .iter()
+            .map(|(key, value)| (key.clone(), serde_json::Value::String(value.clone()))),
+    );
+    if scheme != ChannelScheme::Walle && !extras.is_empty() {
+        log::warn!("{:?} cannot hold extras, dropped: {:?}", scheme, extras);
+        extras.clear();
+    }
+
+    let mut channels = vec![];
+    for channel in &params.channels {
+        let channel = channel.trim();
+        if !channel.is_empty() && !channels.contains(&channel) {
+            channels.push(channel);
+        }
+    }
+
+    let output_folder = if params.output_folder.is_empty() {
+        &settings.output_folder
+    } else {
+        &params.output_folder
+    };
+    let output_folder = if output_folder.is_empty() {
+        Path::new(apk_file_path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    } else {
+        std::fs::create_dir_all(output_folder)?;
+        PathBuf::from(output_folder)
+    };
+    let output_name_template = if params.output_name_template.trim().is_empty() {
+        &settings.output_name_template
+    } else {
+        &params.output_name_template
+    };
+
+    let targets = output_targets(
+        apk_file_path,
+        &output_folder,
+        output_name_template,
+        &package_name,
+        &display_name,
+        original_channel.channel.as_deref(),
+        &channels,
+    )?;
+
+    let stage = start_stage(job_id, JobStage::Build, "")?;
+    let repacked_apk = pack_apk(job_id, &apk_folder).await?;
+    stage.finish();
+
+    // the v2 signature covers the zip comment, so v1 channels go in before
+    // signing and every output is signed on its own
+    let sign_each = scheme == ChannelScheme::VasDollyV1;
+    if !sign_each {
+        let stage = start_stage(job_id, JobStage::Sign, "")?;
+        sign_apk(&repacked_apk, &sign_config).await?;
+        stage.finish();
+    }
+
+    let mut outputs = vec![];
+    for (channel, des) in targets {
+        let des_name = des.to_string_lossy().to_string();
+
+        let stage = start_stage(job_id, JobStage::Move, &des_name)?;
+        if des.exists() {
+            std::fs::remove_file(&des)?;
+        }
+        std::fs::copy(&repacked_apk, &des)?;
+        stage.finish();
+
+        let stage = start_stage(job_id, JobStage::Channel, channel.unwrap_or_default())?;
+        // walle extras are kept without a channel
+        if channel.is_some() || (scheme == ChannelScheme::Walle && !extras.is_empty()) {
+            set_apk_channel(
+                &des,
+                &ChannelInfo {
+                    scheme,
+                    channel: channel.map(str::to_string),
+                    extras: extras.clone(),
+                },
+            )
+            .or_repack_error(|detail| RepackError::ChannelFailed {
+                apk: des_name.clone(),
+                detail,
+            })?;
+        } else {
+            log::info!("channel not found");
+        }
+        stage.finish();
+
+        if sign_each {
+            let stage = start_stage(job_id, JobStage::Sign, &des_name)?;
+            sign_apk(&des, &sign_config).await?;
+            stage.finish();
+        }
+        outputs.push(des);
+    }
+
+    std::fs::remove_file(&repacked_apk)?;
+
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    #[test]
+    fn output_name_stays_in_the_output_folder() {
+        assert_eq!(
+            render_output_name("", "com.example", "Demo", "huawei").unwrap(),
+            "com.example-huawei.apk"
+        );
+        assert_eq!(
+            render_output_name("{name}_{channel}", "com.example", "a/b", "..\\x").unwrap(),
+            "a_b_.._x.apk"
+        );
+
+        for template in [
+            "../{channel}.apk",
+            "out/{channel}",
+            "{channel}\\x.apk",
+            "..{channel}",
+        ] {
+            assert!(
+                check_output_name_template(template).is_err(),
+                "{}",
+                template
+            );
+            assert!(render_output_name(template, "com.example", "Demo", "huawei").is_err());
+        }
+    }
+
+    #[test]
+    fn output_may_not_overwrite_the_input() {
+        let folder = TestFolder::new("overwrite-input".as_ref());
+        let input = folder.write("com.example-huawei.apk", b"apk");
+        let output_name = render_output_name("", "com.example", "Demo", "huawei").unwrap();
+
+        let input = input.to_string_lossy();
+        assert!(check_not_input_apk(&folder.0.join(".").join(&output_name), &input).is_err());
+        assert!(check_not_input_apk(&folder.0.join("other.apk"), &input).is_ok());
+    }
+
+    #[test]
+    fn outputs_are_named_by_the_template() {
+        let folder = TestFolder::new("output-targets".as_ref());
+        let input = folder.write("in.apk", b"apk");
+        let input = input.to_string_lossy();
+        let targets = |template: &str, channels: &[&'static str]| {
+            output_targets(
+                &input,
+                &folder.0,
+                template,
+                "com.example",
+                "Demo",
+                None,
+                channels,
+            )
+            .map(|targets| {
+                targets
+                    .into_iter()
+                    .map(|(_, des)| des.file_name().unwrap().to_string_lossy().to_string())
+                    .collect::<Vec<String>>()
+            })
+        };
+
+        assert_eq!(targets("", &[]).unwrap(), ["in.apk.repacked.apk"]);
+        assert_eq!(
+            targets("{name}-{package}{channel}", &[]).unwrap(),
+            ["Demo-com.example.apk"]
+        );
+        assert_eq!(
+            targets("{name}-{channel}", &["huawei", "xiaomi"]).unwrap(),
+            ["Demo-huawei.apk", "Demo-xiaomi.apk"]
+        );
+        assert!(targets("{name}", &["huawei", "xiaomi"]).is_err());
+        assert!(targets("in", &[]).is_err());
+    }
+}
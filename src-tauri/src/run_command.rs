@@ -1,19 +1,450 @@
 use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read},
+    process::{Child, Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-pub async fn run_command(program: &str, args: &[&str]) -> Result<String> {
+use crate::{
+    constants::get_tool_timeout,
+    job::{check_cancelled, report_progress, set_job_child, JobProgress, JobStage, StageStatus},
+};
+
+/// apktool prints its progress as `I: Decoding AndroidManifest.xml...`
+const TOOL_PROGRESS_PREFIX: &str = "I: ";
+/// stderr lines kept for the error of a failed command
+const STDERR_TAIL_LINES: usize = 20;
+/// how often the exit of the tool and the timeout are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// how long the last lines are read after the tool exited
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+fn forward_lines(
+    stream: impl Read + Send + 'static,
+    sender: mpsc::Sender<(bool, String)>,
+    is_stderr: bool,
+) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if sender.send((is_stderr, line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn lock_child(child: &Mutex<Child>) -> Result<std::sync::MutexGuard<'_, Child>> {
+    child
+        .lock()
+        .map_err(|_| anyhow::anyhow!("job child poisoned"))
+}
+
+/// Registers the tool on the job and, however the wait ends, kills it if it
+/// still runs and forgets it again.
+struct JobChild<'a> {
+    job_id: &'a str,
+    child: Arc<Mutex<Child>>,
+}
+
+impl<'a> JobChild<'a> {
+    fn register(job_id: &'a str, child: Child) -> Result<Self> {
+        let job_child = JobChild {
+            job_id,
+            child: Arc::new(Mutex::new(child)),
+        };
+        set_job_child(job_id, Some(job_child.child.clone()))?;
+        Ok(job_child)
+    }
+}
+
+impl Drop for JobChild<'_> {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(child.try_wait(), Ok(None)) {
+            log::warn!("killing the tool of job {}", self.job_id);
+            if child.kill().is_ok() {
+                let _ = child.wait();
+            }
+        }
+        drop(child);
+
+        if let Err(e) = set_job_child(self.job_id, None) {
+            log::warn!("forget the tool of job {}: {}", self.job_id, e);
+        }
+    }
+}
+
+/// Waits for the tool and hands every line to the log and the progress sink.
+fn wait_command(
+    job_id: &str,
+    stage: JobStage,
+    full_command: &str,
+    mut child: Child,
+    timeout: Duration,
+) -> Result<String> {
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, sender.clone(), false);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, sender, true);
+    }
+
+    let job_child = JobChild::register(job_id, child)?;
+    let child = &job_child.child;
+    // a cancel that came before the child was registered
+    check_cancelled(job_id)?;
+
+    let mut out_lines = vec![];
+    let mut err_tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut handle_line = |(is_stderr, line): (bool, String)| {
+        if is_stderr {
+            log::warn!("> {}", line);
+        } else {
+            log::info!("> {}", line);
+        }
+
+        let (status, message) = match line.strip_prefix(TOOL_PROGRESS_PREFIX) {
+            Some(message) => (StageStatus::Running, message.trim()),
+            None => (StageStatus::Output, line.as_str()),
+        };
+        report_progress(JobProgress {
+            job_id: job_id.to_string(),
+            stage,
+            status,
+            message: message.to_string(),
+            elapsed_ms: None,
+        });
+
+        if is_stderr {
+            if err_tail.len() == STDERR_TAIL_LINES {
+                err_tail.pop_front();
+            }
+            err_tail.push_back(line);
+        } else {
+            out_lines.push(line);
+        }
+    };
+
+    // tools started by the tool, like aapt2, may keep the streams open after
+    // a kill, so the exit of the tool itself ends the wait. The child is only
+    // locked to poll it, `cancel_job` kills it in between.
+    let started = Instant::now();
+    let mut timed_out = false;
+    let mut streams_open = true;
+    let status = loop {
+        if streams_open {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => handle_line(line),
+                Err(mpsc::RecvTimeoutError::Disconnected) => streams_open = false,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            }
+        } else {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        let status = lock_child(child)?.try_wait()?;
+        if let Some(status) = status {
+            // the pipes may still hold lines, or stay open in a tool it started
+            let deadline = Instant::now() + EXIT_GRACE;
+            while let Ok(line) =
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                handle_line(line);
+            }
+            break status;
+        }
+
+        if !timed_out && started.elapsed() > timeout {
+            log::error!("timed out, killing: {}", full_command);
+            timed_out = true;
+            let _ = lock_child(child)?.kill();
+        }
+    };
+
+    drop(job_child);
+    check_cancelled(job_id)?;
+
+    let err_tail = Vec::from(err_tail).join("\n");
+    if timed_out {
+        return Err(anyhow::anyhow!(
+            "`{}` timed out after {}s\n{}",
+            full_command,
+            timeout.as_secs(),
+            err_tail
+        ));
+    }
+    if !status.success() {
+        let code = status
+            .code()
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| "none, killed by a signal".to_string());
+        return Err(anyhow::anyhow!(
+            "`{}` exited with code {}\n{}",
+            full_command,
+            code,
+            err_tail
+        ));
+    }
+
+    Ok(out_lines.join("\n"))
+}
+
+/// Runs a short tool outside of a job, like `java -version`, and returns its
+/// stdout and stderr. It is killed after `timeout` as the tools of a job are.
+pub fn command_output(command: &mut Command, timeout: Duration) -> Result<(String, String)> {
+    let full_command = format!("{:?}", command);
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("`{}` failed to start: {}", full_command, e))?;
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, sender.clone(), false);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, sender, true);
+    }
+
+    let started = Instant::now();
+    while child.try_wait()?.is_none() {
+        if started.elapsed() > timeout {
+            log::error!("timed out, killing: {}", full_command);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow::anyhow!(
+                "`{}` timed out after {}s",
+                full_command,
+                timeout.as_secs()
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    let (mut out_lines, mut err_lines) = (vec![], vec![]);
+    let deadline = Instant::now() + EXIT_GRACE;
+    while let Ok((is_stderr, line)) =
+        receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+    {
+        if is_stderr {
+            err_lines.push(line);
+        } else {
+            out_lines.push(line);
+        }
+    }
+
+    Ok((out_lines.join("\n"), err_lines.join("\n")))
+}
+
+/// Runs a tool for a stage of the job, `cancel_job` kills it and so does the
+/// tool timeout. Lines are logged and reported as they come, returns the
+/// whole stdout.
+pub async fn run_command(
+    job_id: &str,
+    stage: JobStage,
+    program: &str,
+    args: &[&str],
+) -> Result<String> {
     let full_command = format!("{} {}", program, args.join(" "));
     log::info!("running command: {}", full_command);
 
-    let output = std::process::Command::new(program).args(args).output()?;
-    if output.status.code() != Some(0) {
-        if !output.stderr.is_empty() {
-            log::error!("error: {}", String::from_utf8_lossy(&output.stderr));
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("`{}` failed to start: {}", full_command, e))?;
+
+    let job_id = job_id.to_string();
+    let timeout = get_tool_timeout();
+    // waiting blocks, keep it off the threads of the runtime
+    #[cfg(feature = "gui")]
+    {
+        tauri::async_runtime::spawn_blocking(move || {
+            wait_command(&job_id, stage, &full_command, child, timeout)
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("command thread failed: {}", e))?
+    }
+
+    // the command line blocks on the pipeline anyway
+    #[cfg(not(feature = "gui"))]
+    {
+        wait_command(&job_id, stage, &full_command, child, timeout)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        error::RepackError,
+        job::{cancel_job, create_job, finish_job, job_workspace, run_job},
+        test_util::{record_progress, reported_progress},
+    };
+
+    fn sh(job_id: &str, script: &str, timeout: Duration) -> (Result<String>, u32) {
+        let child = Command::new("sh")
+            .args(["-c", script])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let result = wait_command(job_id, JobStage::Decode, "sh", child, timeout);
+        (result, pid)
+    }
+
+    fn is_running(pid: u32) -> bool {
+        Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    #[test]
+    fn lines_are_streamed_and_stdout_returned() {
+        record_progress();
+        let job_id = create_job().unwrap();
+
+        let (result, _) = sh(
+            &job_id,
+            "echo 'I: Decoding AndroidManifest.xml...'; echo done; echo warn >&2",
+            Duration::from_secs(10),
+        );
+
+        assert_eq!(result.unwrap(), "I: Decoding AndroidManifest.xml...\ndone");
+        let reported = reported_progress(&job_id)
+            .into_iter()
+            .map(|progress| (progress.status, progress.message))
+            .collect::<Vec<(StageStatus, String)>>();
+        for line in [
+            (
+                StageStatus::Running,
+                "Decoding AndroidManifest.xml...".to_string(),
+            ),
+            (StageStatus::Output, "done".to_string()),
+            (StageStatus::Output, "warn".to_string()),
+        ] {
+            assert!(reported.contains(&line), "{:?} in {:?}", line, reported);
         }
-        return Err(anyhow::anyhow!("run_command failed"));
+        finish_job(&job_id).unwrap();
+    }
+
+    #[test]
+    fn failure_reports_exit_code_and_stderr_tail() {
+        let job_id = create_job().unwrap();
+
+        let (result, _) = sh(
+            &job_id,
+            "for i in $(seq 1 30); do echo \"error $i\" >&2; done; exit 3",
+            Duration::from_secs(10),
+        );
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("exited with code 3"), "{}", message);
+        assert!(message.contains("error 30"), "{}", message);
+        assert!(message.contains("error 11\n"), "{}", message);
+        assert!(!message.contains("error 10\n"), "{}", message);
+        finish_job(&job_id).unwrap();
+    }
+
+    #[test]
+    fn timeout_kills_the_tool() {
+        let job_id = create_job().unwrap();
+
+        let started = Instant::now();
+        // the sleep keeps the streams open after sh is killed
+        let (result, pid) = sh(&job_id, "sleep 5; echo late", Duration::from_millis(300));
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("timed out"), "{}", message);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!is_running(pid));
+        finish_job(&job_id).unwrap();
+    }
+
+    #[test]
+    fn tool_of_an_unknown_job_is_killed() {
+        let (result, pid) = sh("job-unknown", "sleep 5", Duration::from_secs(60));
+
+        assert!(result.is_err());
+        assert!(!is_running(pid));
+    }
+
+    fn is_cancelled(e: &anyhow::Error) -> bool {
+        matches!(e.downcast_ref(), Some(RepackError::Cancelled { .. }))
     }
 
-    let out_str = String::from_utf8_lossy(&output.stdout);
-    log::info!("output: {}", out_str);
+    #[test]
+    fn cancel_kills_the_running_tool() {
+        let job_id = create_job().unwrap();
+        let workspace = job_workspace(&job_id).unwrap();
 
-    Ok(out_str.to_string())
+        // a pipeline, it closes the job when the tool fails
+        let pipeline_job_id = job_id.clone();
+        let pipeline = std::thread::spawn(move || {
+            let run = run_job(&pipeline_job_id).unwrap();
+            let (result, pid) = sh(&pipeline_job_id, "exec sleep 30", Duration::from_secs(60));
+            drop(run);
+            finish_job(&pipeline_job_id).unwrap();
+            (result, pid)
+        });
+        std::thread::sleep(Duration::from_millis(300));
+        cancel_job(&job_id).unwrap();
+
+        let (result, pid) = pipeline.join().unwrap();
+        assert!(is_cancelled(&result.unwrap_err()));
+        assert!(!is_running(pid));
+        assert!(!workspace.exists());
+    }
+
+    #[test]
+    fn cancel_before_the_tool_started_kills_it() {
+        let job_id = create_job().unwrap();
+        let run = run_job(&job_id).unwrap();
+        cancel_job(&job_id).unwrap();
+
+        let started = Instant::now();
+        let (result, pid) = sh(&job_id, "exec sleep 30", Duration::from_secs(60));
+
+        assert!(is_cancelled(&result.unwrap_err()));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!is_running(pid));
+        drop(run);
+        finish_job(&job_id).unwrap();
+    }
+
+    #[test]
+    fn command_output_returns_both_streams() {
+        let (stdout, stderr) = command_output(
+            Command::new("sh").args(["-c", "echo out; echo err >&2; echo more"]),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        assert_eq!(stdout, "out\nmore");
+        assert_eq!(stderr, "err");
+    }
+
+    #[test]
+    fn command_output_kills_a_hanging_tool() {
+        let started = Instant::now();
+        let e = command_output(
+            Command::new("sh").args(["-c", "sleep 30"]),
+            Duration::from_millis(300),
+        )
+        .unwrap_err();
+
+        assert!(e.to_string().contains("timed out"), "{}", e);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
 }
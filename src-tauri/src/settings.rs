@@ -0,0 +1,275 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, OnceLock},
+};
+
+use crate::{
+    constants::{DEFAULT_DECODE_CACHE_LIMIT_MB, DEFAULT_TOOL_TIMEOUT_SECS},
+    error::{RepackError, RepackResultExt},
+    java_util::check_java_home,
+    repack::check_output_name_template,
+    sign_config::{verify_sign_config, SignConfig},
+};
+
+const SETTINGS_FILE: &str = "settings.json";
+/// names the config folder by its `identifier`
+const TAURI_CONF: &str = include_str!("../tauri.conf.json");
+
+/// Choices kept between runs, in `settings.json` of the app config folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// empty looks for java on its own, see `find_java_runtimes`
+    pub java_home: String,
+    /// `-Xms` of apktool
+    pub jvm_initial_memory_mb: u32,
+    /// `-Xmx` of apktool
+    pub jvm_max_memory_mb: u32,
+    /// used when a repack names no output folder, empty writes the outputs
+    /// next to the input apk
+    pub output_folder: String,
+    /// used when a repack names no template, empty uses the default one
+    pub output_name_template: String,
+    /// signs when a repack brings no sign config, `None` signs with the
+    /// bundled keystore. The file is only readable by the user, it holds
+    /// the passwords.
+    pub sign_config: Option<SignConfig>,
+    /// megabytes of decoded apks kept, 0 turns the decode cache off
+    pub decode_cache_mb: u64,
+    /// seconds apktool may run before it is killed
+    pub tool_timeout_secs: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            java_home: String::new(),
+            jvm_initial_memory_mb: 512,
+            jvm_max_memory_mb: 1024,
+            output_folder: String::new(),
+            output_name_template: String::new(),
+            sign_config: None,
+            decode_cache_mb: DEFAULT_DECODE_CACHE_LIMIT_MB,
+            tool_timeout_secs: DEFAULT_TOOL_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// The folder tauri gives the app, the command line of the same build uses
+/// it as well.
+#[cfg(feature = "gui")]
+fn app_config_folder() -> Option<PathBuf> {
+    static CONFIG: OnceLock<Option<tauri::Config>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        serde_json::from_str(TAURI_CONF)
+            .map_err(|e| log::error!("bad tauri.conf.json: {}", e))
+            .ok()
+    });
+    tauri::api::path::app_config_dir(config.as_ref()?)
+}
+
+/// `%APPDATA%`, `~/Library/Application Support` or `$XDG_CONFIG_HOME`, the
+/// folder tauri gives the app.
+#[cfg(not(feature = "gui"))]
+fn app_config_folder() -> Option<PathBuf> {
+    let config = serde_json::from_str::<serde_json::Value>(TAURI_CONF).ok()?;
+    let identifier = config.pointer("/tauri/bundle/identifier")?.as_str()?;
+
+    let home = || std::env::var_os("HOME").filter(|home| !home.is_empty());
+    let base = if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        Path::new(&home()?).join("Library/Application Support")
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME").filter(|config| !config.is_empty()) {
+            Some(config) => PathBuf::from(config),
+            None => Path::new(&home()?).join(".config"),
+        }
+    };
+
+    Some(base.join(identifier))
+}
+
+pub fn get_settings_path() -> Result<PathBuf> {
+    app_config_folder()
+        .map(|folder| folder.join(SETTINGS_FILE))
+        .ok_or_else(|| anyhow::anyhow!("no config folder, HOME is not set"))
+}
+
+fn load_settings() -> Settings {
+    let settings_path = match get_settings_path() {
+        Ok(settings_path) => settings_path,
+        Err(e) => {
+            log::warn!("{}", e);
+            return Settings::default();
+        }
+    };
+    if !settings_path.exists() {
+        return Settings::default();
+    }
+
+    let settings = std::fs::read_to_string(&settings_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok(serde_json::from_str::<Settings>(&content)?));
+    match settings {
+        Ok(settings) => {
+            log::info!("settings loaded from {}", settings_path.to_string_lossy());
+            settings
+        }
+        // kept on disk, the next update replaces it
+        Err(e) => {
+            log::warn!("bad settings {}: {}", settings_path.to_string_lossy(), e);
+            Settings::default()
+        }
+    }
+}
+
+fn current_settings() -> MutexGuard<'static, Settings> {
+    static SETTINGS: OnceLock<Mutex<Settings>> = OnceLock::new();
+    SETTINGS
+        .get_or_init(|| Mutex::new(load_settings()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// The settings in use, read from disk once.
+pub fn get_settings() -> Settings {
+    current_settings().clone()
+}
+
+/// Changes the settings of this run only, for the command line flags.
+pub fn override_settings(edit: impl FnOnce(&mut Settings)) {
+    edit(&mut current_settings());
+}
+
+impl Settings {
+    /// Checks every field and reports all problems at once.
+    pub fn validate(&self) -> Result<()> {
+        log::info!("validate settings");
+
+        let mut problems = vec![];
+        if !self.java_home.is_empty() {
+            if let Err(e) = check_java_home(&self.java_home) {
+                problems.push(format!("java_home: {}", RepackError::from(e)));
+            }
+        }
+        if self.jvm_initial_memory_mb < 64 {
+            problems.push("jvm_initial_memory_mb: at least 64".to_string());
+        }
+        if self.jvm_max_memory_mb < self.jvm_initial_memory_mb {
+            problems.push("jvm_max_memory_mb: below jvm_initial_memory_mb".to_string());
+        }
+        if !self.output_folder.is_empty() {
+            let output_folder = Path::new(&self.output_folder);
+            if !output_folder.is_absolute() {
+                problems.push("output_folder: must be an absolute path".to_string());
+            } else if output_folder.is_file() {
+                problems.push("output_folder: is a file".to_string());
+            }
+        }
+        if let Err(e) = check_output_name_template(&self.output_name_template) {
+            problems.push(format!("output_name_template: {}", e));
+        }
+        if let Some(sign_config) = &self.sign_config {
+            if let Err(e) = verify_sign_config(sign_config) {
+                problems.push(format!("sign_config: {}", RepackError::from(e)));
+            }
+        }
+        // megabytes are turned into bytes
+        if self.decode_cache_mb > 1024 * 1024 {
+            problems.push("decode_cache_mb: at most 1048576".to_string());
+        }
+        if self.tool_timeout_secs == 0 {
+            problems.push("tool_timeout_secs: at least 1".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} problems\n  {}",
+                problems.len(),
+                problems.join("\n  ")
+            ))
+        }
+    }
+}
+
+fn write_settings(settings_path: &Path, settings: &Settings) -> Result<()> {
+    if let Some(folder) = settings_path.parent() {
+        std::fs::create_dir_all(folder)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(settings_path)?;
+    // the mode only applies to a new file, one written before may be wider
+    #[cfg(unix)]
+    std::fs::set_permissions(
+        settings_path,
+        std::os::unix::fs::PermissionsExt::from_mode(0o600),
+    )?;
+    std::io::Write::write_all(
+        &mut file,
+        serde_json::to_string_pretty(settings)?.as_bytes(),
+    )?;
+
+    Ok(())
+}
+
+/// Validates, saves and applies the settings, nothing changes when one
+/// field is wrong.
+pub fn update_settings(settings: Settings) -> Result<Settings> {
+    log::info!("update_settings");
+
+    let settings_path = get_settings_path()?;
+    settings
+        .validate()
+        .or_repack_error(|detail| RepackError::InvalidSettings {
+            path: settings_path.to_string_lossy().to_string(),
+            detail,
+        })?;
+
+    write_settings(&settings_path, &settings)?;
+    *current_settings() = settings.clone();
+
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    #[test]
+    fn config_folder_is_named_by_the_identifier() {
+        let config = serde_json::from_str::<serde_json::Value>(TAURI_CONF).unwrap();
+        let identifier = config["tauri"]["bundle"]["identifier"].as_str().unwrap();
+        if let Some(folder) = app_config_folder() {
+            assert!(folder.ends_with(identifier), "{:?}", folder);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn settings_are_only_readable_by_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let folder = TestFolder::new("settings".as_ref());
+        let settings_path = folder.write(SETTINGS_FILE, b"{}");
+        std::fs::set_permissions(&settings_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_settings(&settings_path, &Settings::default()).unwrap();
+
+        let mode = std::fs::metadata(&settings_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
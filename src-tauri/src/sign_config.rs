@@ -0,0 +1,58 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{
+    apk_signer::load_signing_key,
+    constants::{get_key_store_path, KEYSTORE_ALIAS_NAME, KEYSTORE_PWD},
+    error::{RepackError, RepackResultExt},
+};
+
+/// passwords are read from these when not given, so they stay out of ci logs
+/// and recipe files
+pub const STORE_PASSWORD_ENV: &str = "REPACK_STORE_PASSWORD";
+pub const KEY_PASSWORD_ENV: &str = "REPACK_KEY_PASSWORD";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignConfig {
+    pub keystore_path: String,
+    pub store_password: String,
+    pub key_alias: String,
+    pub key_password: String,
+}
+
+impl Default for SignConfig {
+    /// The keystore bundled with the app, extracted by `extract_tools`.
+    fn default() -> Self {
+        SignConfig {
+            keystore_path: get_key_store_path().to_string_lossy().to_string(),
+            store_password: KEYSTORE_PWD.to_string(),
+            key_alias: KEYSTORE_ALIAS_NAME.to_string(),
+            key_password: KEYSTORE_PWD.to_string(),
+        }
+    }
+}
+
+/// Loads the signing key so a wrong password or alias fails fast,
+/// before the slow unpack / pack stages run.
+pub fn verify_sign_config(sign_config: &SignConfig) -> Result<()> {
+    log::info!("verify_sign_config");
+
+    if !Path::new(&sign_config.keystore_path).is_file() {
+        return Err(RepackError::SignFailed {
+            detail: format!("keystore not found: {}", sign_config.keystore_path),
+        }
+        .into());
+    }
+
+    if sign_config.key_alias.is_empty() {
+        return Err(RepackError::SignFailed {
+            detail: "key alias is empty".to_string(),
+        }
+        .into());
+    }
+
+    load_signing_key(sign_config).or_repack_error(|detail| RepackError::SignFailed { detail })?;
+
+    Ok(())
+}
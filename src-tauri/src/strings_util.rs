@@ -0,0 +1,212 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::xml_util::{selector_literal, XmlDocument, XmlElement};
+
+const STRINGS_XML: &str = "strings.xml";
+const COLORS_XML: &str = "colors.xml";
+
+/// Decodes the escapes aapt accepts in a `<string>` value: surrounding double
+/// quotes keep the content as is, `\'`, `\"`, `\@`, `\?`, `\\`, `\n`, `\t`
+/// and `\uXXXX` are single characters.
+pub fn android_string_unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('u') => {
+                    let hex = chars.by_ref().take(4).collect::<String>();
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(c) => out.push(c),
+                        None => {
+                            out.push_str("\\u");
+                            out.push_str(&hex);
+                        }
+                    }
+                }
+                Some(c) => out.push(c),
+                None => {}
+            },
+            // unquoted whitespace runs collapse into one space
+            c if c.is_whitespace() && !quoted => {
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                out.push(' ');
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Escapes a plain value so aapt reads it back unchanged.
+pub fn android_string_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 8);
+    for (index, c) in value.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\'' => out.push_str("\\'"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            // a leading @ or ? would make it a reference
+            '@' | '?' if index == 0 => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+
+    // keep leading, trailing and repeated spaces
+    if value.starts_with(' ') || value.ends_with(' ') || value.contains("  ") {
+        out = format!("\"{}\"", out);
+    }
+
+    out
+}
+
+/// The unescaped value of `<string name="...">` in `values_dir/strings.xml`.
+pub fn read_string_resource(values_dir: &Path, name: &str) -> Result<Option<String>> {
+    let strings_file_path = values_dir.join(STRINGS_XML);
+    if !strings_file_path.is_file() {
+        return Ok(None);
+    }
+
+    let document = XmlDocument::parse(&std::fs::read_to_string(strings_file_path)?)?;
+    let selector = format!("resources/string[@name={}]", selector_literal(name)?);
+    match document.find(&selector)?.first() {
+        Some(element) => Ok(Some(android_string_unescape(element.text()?.trim()))),
+        None => Ok(None),
+    }
+}
+
+/// Sets `<tag name="...">text</tag>` in `values_dir/file_name`, the entry and
+/// the file are created when missing. Only that entry changes, the rest of
+/// the file is written back as read.
+fn write_values_resource(
+    values_dir: &Path,
+    file_name: &str,
+    tag: &str,
+    name: &str,
+    text: &str,
+) -> Result<()> {
+    let values_file_path = values_dir.join(file_name);
+    let mut document = if values_file_path.is_file() {
+        XmlDocument::parse(&std::fs::read_to_string(&values_file_path)?)?
+    } else {
+        std::fs::create_dir_all(values_dir)?;
+        XmlDocument::parse(
+            "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"no\"?>\n<resources>\n</resources>\n",
+        )?
+    };
+
+    let selector = format!("resources/{}[@name={}]", tag, selector_literal(name)?);
+    if document.find(&selector)?.is_empty() {
+        let element = XmlElement::new(tag).with_attr("name", name);
+        document.insert_child("resources", &element)?;
+    }
+
+    document.set_text(&selector, text)?;
+
+    std::fs::write(values_file_path, document.to_xml_string()?)?;
+
+    Ok(())
+}
+
+/// Sets `<string name="...">` in `values_dir/strings.xml`.
+pub fn write_string_resource(values_dir: &Path, name: &str, value: &str) -> Result<()> {
+    log::info!(
+        "write_string_resource: {} {} => {}",
+        values_dir.to_string_lossy(),
+        name,
+        value
+    );
+
+    write_values_resource(
+        values_dir,
+        STRINGS_XML,
+        "string",
+        name,
+        &android_string_escape(value),
+    )
+}
+
+/// Sets `<color name="...">` in `values_dir/colors.xml`, e.g. `#FF3DDC84`.
+pub fn write_color_resource(values_dir: &Path, name: &str, color: &str) -> Result<()> {
+    log::info!(
+        "write_color_resource: {} {} => {}",
+        values_dir.to_string_lossy(),
+        name,
+        color
+    );
+
+    write_values_resource(values_dir, COLORS_XML, "color", name, color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFolder;
+
+    const STRINGS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<resources>
+    <string name="app_name">Demo</string>
+    <string name="styled">Hello <b>bold</b> world</string>
+    <string name="spaced">"  two  spaces  "</string>
+    <!-- kept as is -->
+    <string name="multiline">first
+        second</string>
+</resources>
+"#;
+
+    #[test]
+    fn write_changes_only_the_entry() {
+        let folder = TestFolder::new("strings-edit".as_ref());
+        let strings_file_path = folder.write("values/strings.xml", STRINGS.as_bytes());
+        let values_dir = strings_file_path.parent().unwrap();
+
+        write_string_resource(values_dir, "app_name", "It's <new>").unwrap();
+        write_string_resource(values_dir, "added", "  padded").unwrap();
+
+        let expected = STRINGS.replace(">Demo<", ">It\\'s &lt;new&gt;<").replace(
+            "second</string>\n",
+            "second</string>\n    <string name=\"added\">\"  padded\"</string>\n",
+        );
+        assert_eq!(
+            std::fs::read_to_string(&strings_file_path).unwrap(),
+            expected
+        );
+        assert_eq!(
+            read_string_resource(values_dir, "app_name")
+                .unwrap()
+                .unwrap(),
+            "It's <new>"
+        );
+        assert_eq!(
+            read_string_resource(values_dir, "spaced").unwrap().unwrap(),
+            "  two  spaces  "
+        );
+    }
+
+    #[test]
+    fn write_creates_the_file() {
+        let folder = TestFolder::new("colors-new".as_ref());
+        let values_dir = folder.0.join("values");
+
+        write_color_resource(&values_dir, "icon_background", "#FF3DDC84").unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(values_dir.join(COLORS_XML)).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"no\"?>\n<resources>\n    <color name=\"icon_background\">#FF3DDC84</color>\n</resources>\n"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io::Write,
+    path::PathBuf,
+    sync::{Mutex, Once},
+};
+
+use crate::{
+    apk_signing_block::build_apk_signing_block,
+    job::{set_progress_sink, JobProgress},
+    zip_util::{write_central_directory_header, write_eocd, write_local_header, ZipEntry},
+    zip_util::{METHOD_DEFLATED, METHOD_STORED},
+};
+
+/// An empty folder under the temp dir, removed on drop.
+pub struct TestFolder(pub PathBuf);
+
+impl TestFolder {
+    pub fn new(name: &OsStr) -> Self {
+        let mut folder_name = OsString::from(format!("repack-{}-", std::process::id()));
+        folder_name.push(name);
+        let path = std::env::temp_dir().join(folder_name);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestFolder(path)
+    }
+
+    pub fn write(&self, file: &str, content: &[u8]) -> PathBuf {
+        let path = self.0.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TestFolder {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A zip of `(name, content, stored)` entries, with an apk signing block of
+/// `pairs` before the central directory when there are any.
+pub fn build_zip(entries: &[(&str, &[u8], bool)], pairs: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut zip_data = vec![];
+    let mut cd_entries = vec![];
+    for (name, content, stored) in entries {
+        let data = if *stored {
+            content.to_vec()
+        } else {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(content).unwrap();
+            encoder.finish().unwrap()
+        };
+        let entry = ZipEntry {
+            name: name.to_string(),
+            version_made_by: 20,
+            version_needed: 20,
+            flags: 0,
+            method: if *stored {
+                METHOD_STORED
+            } else {
+                METHOD_DEFLATED
+            },
+            time: 0,
+            date: 0,
+            crc32: crc32fast::hash(content),
+            compressed_size: data.len(),
+            uncompressed_size: content.len(),
+            internal_attrs: 0,
+            external_attrs: 0,
+            cd_extra: vec![],
+            comment: vec![],
+            local_header_offset: zip_data.len(),
+            local_extra: vec![],
+            data_offset: 0,
+        };
+        write_local_header(&mut zip_data, &entry, &[]);
+        zip_data.extend_from_slice(&data);
+        cd_entries.push(entry);
+    }
+
+    if !pairs.is_empty() {
+        zip_data.extend(build_apk_signing_block(pairs));
+    }
+
+    let cd_offset = zip_data.len();
+    let mut cd_data = vec![];
+    for entry in &cd_entries {
+        write_central_directory_header(&mut cd_data, entry);
+    }
+    zip_data.extend_from_slice(&cd_data);
+    write_eocd(
+        &mut zip_data,
+        cd_entries.len(),
+        cd_offset,
+        cd_data.len(),
+        &[],
+    );
+
+    zip_data
+}
+
+static PROGRESS: Mutex<Vec<JobProgress>> = Mutex::new(vec![]);
+
+/// Starts recording progress, the sink is set once for all tests.
+pub fn record_progress() {
+    static SINK: Once = Once::new();
+    SINK.call_once(|| {
+        set_progress_sink(Box::new(|progress| {
+            PROGRESS.lock().unwrap().push(progress.clone());
+        }))
+    });
+}
+
+/// Progress reported for `job_id` since `record_progress`.
+pub fn reported_progress(job_id: &str) -> Vec<JobProgress> {
+    PROGRESS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|progress| progress.job_id == job_id)
+        .cloned()
+        .collect()
+}
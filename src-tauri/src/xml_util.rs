@@ -1,115 +1,885 @@
 use anyhow::Result;
 use quick_xml::{
-    events::{attributes::Attribute, BytesStart, Event},
-    name::QName,
+    escape::partial_escape,
+    events::{BytesEnd, BytesStart, BytesText, Event},
     Reader, Writer,
 };
-use std::{borrow::Cow, io::Cursor};
+use std::io::Cursor;
 
-pub fn xml_find_value(
-    xml_content: &str,
-    node_tree: &[&str],
-    attr_key: &str,
-) -> Result<Vec<String>> {
-    let mut name_list: Vec<String> = vec![];
-
-    let mut reader = Reader::from_str(xml_content);
-    reader.trim_text(true);
-
-    let mut node_tree_index = 0;
-    loop {
-        match reader.read_event() {
-            Ok(Event::Start(e)) => {
-                // exchange package name
-                if e.name().as_ref() == node_tree[node_tree_index].as_bytes() {
-                    if node_tree_index == node_tree.len() - 1 {
-                        let value = String::from_utf8(
-                            e.attributes()
-                                .collect::<Result<Vec<Attribute<'_>>, _>>()?
-                                .iter()
-                                .find(|attr| attr.key.as_ref() == attr_key.as_bytes())
-                                .unwrap_or(&Attribute {
-                                    key: QName(b""),
-                                    value: Cow::Borrowed(b""),
-                                })
-                                .value
-                                .to_vec(),
-                        )?;
-                        name_list.push(value);
-                    } else {
-                        node_tree_index += 1;
+// Selectors are slash separated element steps from the document root, e.g.
+// `manifest/application/activity[@android:name='.Main']/intent-filter`.
+// `//` matches at any depth, `*` matches any element, `[@attr]` and
+// `[@attr='value']` filter by attribute. Prefixes are compared by namespace
+// uri, `android:`, `app:` and `tools:` always mean the well-known ones.
+
+pub const ANDROID_NAMESPACE: &str = "http://schemas.android.com/apk/res/android";
+
+const WELL_KNOWN_NAMESPACES: &[(&str, &str)] = &[
+    ("android", ANDROID_NAMESPACE),
+    ("app", "http://schemas.android.com/apk/res-auto"),
+    ("tools", "http://schemas.android.com/tools"),
+];
+
+#[derive(Debug, Clone)]
+pub enum XmlNode {
+    Element(XmlElement),
+    /// text, comments, declarations, kept as read
+    Other(Event<'static>),
+}
+
+/// `xmlns:prefix` bindings, the innermost last
+type Namespaces = Vec<(String, String)>;
+
+/// The tags an element was read from, written back as they were while the
+/// element keeps its name and attributes.
+#[derive(Debug, Clone)]
+struct SourceTags {
+    name: String,
+    attributes: Vec<(String, String)>,
+    start: BytesStart<'static>,
+    end: Option<BytesEnd<'static>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct XmlElement {
+    pub name: String,
+    /// qualified keys as written and unescaped values, in document order
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<XmlNode>,
+    /// bindings of the ancestors, the own ones are in `attributes`
+    inherited_namespaces: Namespaces,
+    /// `<name/>` while it has no children
+    self_closing: bool,
+    source: Option<SourceTags>,
+}
+
+impl Default for XmlElement {
+    fn default() -> Self {
+        XmlElement {
+            name: String::new(),
+            attributes: vec![],
+            children: vec![],
+            inherited_namespaces: vec![],
+            self_closing: true,
+            source: None,
+        }
+    }
+}
+
+fn is_whitespace_text(node: &XmlNode) -> bool {
+    match node {
+        XmlNode::Other(Event::Text(text)) => text.iter().all(u8::is_ascii_whitespace),
+        _ => false,
+    }
+}
+
+impl XmlElement {
+    pub fn new(name: &str) -> Self {
+        XmlElement {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_attr(mut self, key: &str, value: &str) -> Self {
+        self.set_attr(key, value);
+        self
+    }
+
+    /// Looks up a selector key, `android:name` finds the attribute whatever
+    /// prefix is bound to android here.
+    pub fn ns_attr(&self, selector_key: &str) -> Option<&str> {
+        let key = self.resolve_selector_name(selector_key);
+        self.attributes
+            .iter()
+            .find(|(attr_key, _)| self.resolve_name(attr_key) == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_attr(&mut self, key: &str, value: &str) {
+        match self
+            .attributes
+            .iter_mut()
+            .find(|(attr_key, _)| attr_key == key)
+        {
+            Some((_, old_value)) => *old_value = value.to_string(),
+            None => self.attributes.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn remove_attr(&mut self, key: &str) -> bool {
+        let len = self.attributes.len();
+        self.attributes.retain(|(attr_key, _)| attr_key != key);
+        self.attributes.len() != len
+    }
+
+    /// Unescaped text and cdata of the element, child elements are skipped.
+    pub fn text(&self) -> Result<String> {
+        let mut text = String::new();
+        for child in &self.children {
+            match child {
+                XmlNode::Other(Event::Text(e)) => text.push_str(&e.unescape()?),
+                XmlNode::Other(Event::CData(e)) => text.push_str(&String::from_utf8(e.to_vec())?),
+                _ => {}
+            }
+        }
+        Ok(text)
+    }
+
+    /// Replaces all children with `text`, only `&`, `<` and `>` are escaped
+    /// so quotes stay readable.
+    pub fn set_text(&mut self, text: &str) {
+        self.children = vec![XmlNode::Other(Event::Text(
+            BytesText::from_escaped(partial_escape(text)).into_owned(),
+        ))];
+    }
+
+    /// Bindings in scope here, the own declarations last.
+    fn namespaces(&self) -> Namespaces {
+        let mut namespaces = self.inherited_namespaces.clone();
+        for (key, value) in &self.attributes {
+            if let Some(prefix) = key.strip_prefix("xmlns:") {
+                namespaces.push((prefix.to_string(), value.clone()));
+            }
+        }
+        namespaces
+    }
+
+    /// Gives the element and its descendants the bindings of a new parent.
+    fn inherit_namespaces(&mut self, namespaces: Namespaces) {
+        self.inherited_namespaces = namespaces;
+        let namespaces = self.namespaces();
+        for child in &mut self.children {
+            if let XmlNode::Element(child) = child {
+                child.inherit_namespaces(namespaces.clone());
+            }
+        }
+    }
+
+    fn namespace_uri(&self, prefix: &str) -> Option<String> {
+        self.namespaces()
+            .into_iter()
+            .rev()
+            .find(|(p, _)| p == prefix)
+            .map(|(_, uri)| uri)
+    }
+
+    /// `(namespace uri, local name)` of a qualified name written here, an
+    /// unbound prefix stands for itself.
+    fn resolve_name<'a>(&self, qname: &'a str) -> (Option<String>, &'a str) {
+        match qname.split_once(':') {
+            Some((prefix, local)) => (
+                Some(
+                    self.namespace_uri(prefix)
+                        .unwrap_or_else(|| prefix.to_string()),
+                ),
+                local,
+            ),
+            None => (None, qname),
+        }
+    }
+
+    /// `(namespace uri, local name)` of a qualified name in a selector.
+    fn resolve_selector_name<'a>(&self, qname: &'a str) -> (Option<String>, &'a str) {
+        match qname.split_once(':') {
+            Some((prefix, local)) => match WELL_KNOWN_NAMESPACES.iter().find(|(p, _)| *p == prefix)
+            {
+                Some((_, uri)) => (Some(uri.to_string()), local),
+                None => self.resolve_name(qname),
+            },
+            None => (None, qname),
+        }
+    }
+
+    /// Qualified key as written here for a selector key, so `android:name`
+    /// follows whatever prefix is bound to android in this element.
+    fn document_key(&self, selector_key: &str) -> String {
+        let (Some(uri), local) = self.resolve_selector_name(selector_key) else {
+            return selector_key.to_string();
+        };
+
+        match self.namespaces().into_iter().rev().find(|(_, u)| *u == uri) {
+            Some((prefix, _)) => format!("{}:{}", prefix, local),
+            None => selector_key.to_string(),
+        }
+    }
+
+    /// Qualified key with the well-known prefix of its namespace, e.g.
+    /// `a:name` becomes `android:name` when `a` is bound to android here.
+    fn canonical_key(&self, document_key: &str) -> String {
+        let (Some(uri), local) = self.resolve_name(document_key) else {
+            return document_key.to_string();
+        };
+
+        match WELL_KNOWN_NAMESPACES.iter().find(|(_, u)| *u == uri) {
+            Some((prefix, _)) => format!("{}:{}", prefix, local),
+            None => document_key.to_string(),
+        }
+    }
+
+    fn matches_step(&self, step: &Step) -> bool {
+        if let Some(name) = &step.name {
+            if self.resolve_selector_name(name) != self.resolve_name(&self.name) {
+                return false;
+            }
+        }
+
+        step.predicates.iter().all(|predicate| {
+            let key = self.resolve_selector_name(&predicate.attr_key);
+            self.attributes.iter().any(|(attr_key, value)| {
+                self.resolve_name(attr_key) == key
+                    && predicate.value.as_ref().is_none_or(|v| v == value)
+            })
+        })
+    }
+
+    /// Appends a child, indented like its siblings, its selector keys are
+    /// written with the prefixes bound here.
+    fn append_child(&mut self, mut child: XmlElement) {
+        child.attributes = child
+            .attributes
+            .iter()
+            .map(|(key, value)| (self.document_key(key), value.clone()))
+            .collect();
+        child.inherit_namespaces(self.namespaces());
+
+        // `<parent>\n    <a/>\n</parent>`: the new child goes before the
+        // closing indent, after a copy of the indent of the last sibling
+        let last_element = self
+            .children
+            .iter()
+            .rposition(|node| matches!(node, XmlNode::Element(_)));
+        let closing_indent = self.children.last().is_some_and(is_whitespace_text)
+            && last_element.is_none_or(|index| index + 1 < self.children.len());
+        let sibling_indent = match last_element {
+            Some(index) => index
+                .checked_sub(1)
+                .map(|index| &self.children[index])
+                .filter(|node| is_whitespace_text(node))
+                .cloned(),
+            // the first child, one level deeper than the closing tag
+            None if closing_indent => self.children.last().and_then(|node| match node {
+                XmlNode::Other(Event::Text(text)) => Some(XmlNode::Other(Event::Text(
+                    BytesText::from_escaped(format!("{}    ", String::from_utf8_lossy(text))),
+                ))),
+                _ => None,
+            }),
+            None => None,
+        };
+
+        let mut nodes = vec![];
+        nodes.extend(sibling_indent);
+        nodes.push(XmlNode::Element(child));
+        let index = self.children.len() - usize::from(closing_indent);
+        self.children.splice(index..index, nodes);
+    }
+
+    /// Removes a child element and the indent before it.
+    fn remove_child(&mut self, index: usize) {
+        self.children.remove(index);
+        if index > 0 && is_whitespace_text(&self.children[index - 1]) {
+            self.children.remove(index - 1);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Predicate {
+    attr_key: String,
+    /// `None` only checks the attribute is present
+    value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    /// `//` before the step
+    descendant: bool,
+    /// `None` for `*`
+    name: Option<String>,
+    predicates: Vec<Predicate>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')
+}
+
+fn parse_selector(selector: &str) -> Result<Vec<Step>> {
+    let bad_selector = |reason: &str| anyhow::anyhow!("bad selector '{}': {}", selector, reason);
+
+    let chars = selector.trim().chars().collect::<Vec<char>>();
+    let mut steps = vec![];
+    let mut pos = 0;
+    while pos < chars.len() {
+        let mut descendant = false;
+        if chars[pos] == '/' {
+            pos += 1;
+            if chars.get(pos) == Some(&'/') {
+                descendant = true;
+                pos += 1;
+            }
+        } else if !steps.is_empty() {
+            return Err(bad_selector("expected '/'"));
+        }
+
+        let name_start = pos;
+        while pos < chars.len() && (is_name_char(chars[pos]) || chars[pos] == '*') {
+            pos += 1;
+        }
+        let name = chars[name_start..pos].iter().collect::<String>();
+        if name.is_empty() {
+            return Err(bad_selector("missing element name"));
+        }
+
+        let mut predicates = vec![];
+        while chars.get(pos) == Some(&'[') {
+            pos += 1;
+            if chars.get(pos) != Some(&'@') {
+                return Err(bad_selector("predicates must start with '@'"));
+            }
+            pos += 1;
+
+            let key_start = pos;
+            while pos < chars.len() && is_name_char(chars[pos]) {
+                pos += 1;
+            }
+            let attr_key = chars[key_start..pos].iter().collect::<String>();
+            if attr_key.is_empty() {
+                return Err(bad_selector("missing attribute name"));
+            }
+
+            let mut value = None;
+            if chars.get(pos) == Some(&'=') {
+                pos += 1;
+                let quote = match chars.get(pos) {
+                    Some(quote @ ('\'' | '"')) => *quote,
+                    _ => return Err(bad_selector("attribute values must be quoted")),
+                };
+                pos += 1;
+
+                let value_start = pos;
+                while pos < chars.len() && chars[pos] != quote {
+                    pos += 1;
+                }
+                if pos == chars.len() {
+                    return Err(bad_selector("unterminated attribute value"));
+                }
+                value = Some(chars[value_start..pos].iter().collect::<String>());
+                pos += 1;
+            }
+
+            if chars.get(pos) != Some(&']') {
+                return Err(bad_selector("expected ']'"));
+            }
+            pos += 1;
+
+            predicates.push(Predicate { attr_key, value });
+        }
+
+        steps.push(Step {
+            descendant,
+            name: (name != "*").then_some(name),
+            predicates,
+        });
+    }
+
+    if steps.is_empty() {
+        return Err(bad_selector("empty"));
+    }
+
+    Ok(steps)
+}
+
+/// Quotes a value for a selector predicate.
+pub fn selector_literal(value: &str) -> Result<String> {
+    if !value.contains('\'') {
+        Ok(format!("'{}'", value))
+    } else if !value.contains('"') {
+        Ok(format!("\"{}\"", value))
+    } else {
+        Err(anyhow::anyhow!("cannot quote {} in a selector", value))
+    }
+}
+
+/// Index path from the document nodes down to an element.
+type NodePath = Vec<usize>;
+
+#[derive(Debug, Clone, Default)]
+pub struct XmlDocument {
+    pub nodes: Vec<XmlNode>,
+}
+
+impl XmlDocument {
+    /// Keeps every event, whitespace included, so the unedited parts are
+    /// written back byte for byte.
+    pub fn parse(xml_content: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml_content);
+
+        let mut document = XmlDocument::default();
+        let mut open: Vec<XmlElement> = vec![];
+        loop {
+            let node = match reader.read_event() {
+                Ok(Event::Start(e)) => {
+                    let namespaces = open.last().map(XmlElement::namespaces).unwrap_or_default();
+                    let mut element = read_element(e.into_owned(), namespaces)?;
+                    element.self_closing = false;
+                    open.push(element);
+                    continue;
+                }
+                Ok(Event::Empty(e)) => {
+                    let namespaces = open.last().map(XmlElement::namespaces).unwrap_or_default();
+                    XmlNode::Element(read_element(e.into_owned(), namespaces)?)
+                }
+                Ok(Event::End(e)) => {
+                    let mut element = open.pop().ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "unexpected end tag at position {}",
+                            reader.buffer_position()
+                        )
+                    })?;
+                    if element.name.as_bytes() != e.name().as_ref() {
+                        return Err(anyhow::anyhow!(
+                            "end tag does not match {} at position {}",
+                            element.name,
+                            reader.buffer_position()
+                        ));
+                    }
+                    if let Some(source) = &mut element.source {
+                        source.end = Some(e.into_owned());
                     }
+                    XmlNode::Element(element)
                 }
+                Ok(Event::Eof) => break,
+                Ok(e) => XmlNode::Other(e.into_owned()),
+                Err(e) => {
+                    return Err(anyhow::anyhow!(
+                        "Error at position {}: {:?}",
+                        reader.buffer_position(),
+                        e
+                    ))
+                }
+            };
+
+            match open.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => document.nodes.push(node),
             }
-            Ok(Event::End(_e)) => {
-                node_tree_index = node_tree_index.saturating_sub(1);
+        }
+
+        if let Some(element) = open.last() {
+            return Err(anyhow::anyhow!("unclosed element {}", element.name));
+        }
+
+        Ok(document)
+    }
+
+    pub fn to_xml_string(&self) -> Result<String> {
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        for node in &self.nodes {
+            write_node(&mut writer, node)?;
+        }
+        Ok(String::from_utf8(writer.into_inner().into_inner())?)
+    }
+
+    fn select(&self, selector: &str) -> Result<Vec<NodePath>> {
+        let steps = parse_selector(selector)?;
+
+        // the document itself is the context of the first step
+        let mut contexts: Vec<NodePath> = vec![vec![]];
+        for step in &steps {
+            let mut matched: Vec<NodePath> = vec![];
+            for context in &contexts {
+                let children = match self.get(context) {
+                    Some(element) => &element.children,
+                    None => &self.nodes,
+                };
+                select_children(children, context, step, &mut matched);
+            }
+            matched.sort();
+            matched.dedup();
+            contexts = matched;
+        }
+
+        Ok(contexts)
+    }
+
+    fn get(&self, path: &[usize]) -> Option<&XmlElement> {
+        let (first, rest) = path.split_first()?;
+        let mut element = match self.nodes.get(*first)? {
+            XmlNode::Element(element) => element,
+            XmlNode::Other(_) => return None,
+        };
+        for index in rest {
+            element = match element.children.get(*index)? {
+                XmlNode::Element(element) => element,
+                XmlNode::Other(_) => return None,
+            };
+        }
+        Some(element)
+    }
+
+    fn get_mut(&mut self, path: &[usize]) -> Option<&mut XmlElement> {
+        let (first, rest) = path.split_first()?;
+        let mut element = match self.nodes.get_mut(*first)? {
+            XmlNode::Element(element) => element,
+            XmlNode::Other(_) => return None,
+        };
+        for index in rest {
+            element = match element.children.get_mut(*index)? {
+                XmlNode::Element(element) => element,
+                XmlNode::Other(_) => return None,
+            };
+        }
+        Some(element)
+    }
+
+    pub fn find(&self, selector: &str) -> Result<Vec<&XmlElement>> {
+        Ok(self
+            .select(selector)?
+            .iter()
+            .filter_map(|path| self.get(path))
+            .collect())
+    }
+
+    /// Values of `attr_key` on the matched elements that have it.
+    pub fn find_attr(&self, selector: &str, attr_key: &str) -> Result<Vec<String>> {
+        Ok(self
+            .find(selector)?
+            .iter()
+            .filter_map(|element| element.ns_attr(attr_key).map(str::to_string))
+            .collect())
+    }
+
+    /// Runs `edit` on every matched element, fails when nothing matches.
+    fn edit_each(
+        &mut self,
+        selector: &str,
+        mut edit: impl FnMut(&mut XmlElement),
+    ) -> Result<usize> {
+        let paths = self.select(selector)?;
+        if paths.is_empty() {
+            return Err(anyhow::anyhow!("no element matches '{}'", selector));
+        }
+
+        for path in &paths {
+            if let Some(element) = self.get_mut(path) {
+                edit(element);
             }
-            Ok(Event::Eof) => break,
-            // we can either move or borrow the event to write, depending on your use-case
-            Ok(_e) => {}
-            Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "Error at position {}: {:?}",
-                    reader.buffer_position(),
-                    e
-                ))
+        }
+
+        Ok(paths.len())
+    }
+
+    pub fn set_attr(&mut self, selector: &str, attr_key: &str, attr_value: &str) -> Result<usize> {
+        self.edit_each(selector, |element| {
+            let key = element.document_key(attr_key);
+            element.set_attr(&key, attr_value)
+        })
+    }
+
+    pub fn remove_attr(&mut self, selector: &str, attr_key: &str) -> Result<usize> {
+        self.edit_each(selector, |element| {
+            let key = element.document_key(attr_key);
+            element.remove_attr(&key);
+        })
+    }
+
+    pub fn set_text(&mut self, selector: &str, text: &str) -> Result<usize> {
+        self.edit_each(selector, |element| element.set_text(text))
+    }
+
+    /// Appends a copy of `child` to every matched element, its attribute
+    /// keys are selector keys like `android:name`.
+    pub fn insert_child(&mut self, selector: &str, child: &XmlElement) -> Result<usize> {
+        self.edit_each(selector, |element| element.append_child(child.clone()))
+    }
+
+    /// Removes the matched elements and their children.
+    pub fn remove_node(&mut self, selector: &str) -> Result<usize> {
+        let mut paths = self.select(selector)?;
+        if paths.is_empty() {
+            return Err(anyhow::anyhow!("no element matches '{}'", selector));
+        }
+
+        // later siblings and descendants first so the other paths stay valid
+        paths.sort_by(|a, b| b.cmp(a));
+        for path in &paths {
+            let Some((index, parent)) = path.split_last() else {
+                continue;
+            };
+            if parent.is_empty() {
+                self.nodes.remove(*index);
+            } else if let Some(parent) = self.get_mut(parent) {
+                parent.remove_child(*index);
             }
         }
+
+        Ok(paths.len())
     }
 
-    Ok(name_list)
+    /// Rewrites attribute values of every element, `map` gets the element
+    /// name, the canonical attribute key and the value, and returns the new
+    /// value or `None` to keep it.
+    pub fn map_attrs(&mut self, mut map: impl FnMut(&str, &str, &str) -> Option<String>) {
+        fn walk(nodes: &mut [XmlNode], map: &mut impl FnMut(&str, &str, &str) -> Option<String>) {
+            for node in nodes {
+                let XmlNode::Element(element) = node else {
+                    continue;
+                };
+                let keys = element
+                    .attributes
+                    .iter()
+                    .map(|(key, _)| element.canonical_key(key))
+                    .collect::<Vec<String>>();
+                for ((key, value), canonical_key) in element.attributes.iter_mut().zip(keys) {
+                    if let Some(new_value) = map(&element.name, &canonical_key, value) {
+                        log::info!("{} {}: {} => {}", element.name, key, value, new_value);
+                        *value = new_value;
+                    }
+                }
+                walk(&mut element.children, map);
+            }
+        }
+
+        walk(&mut self.nodes, &mut map);
+    }
+}
+
+fn read_element(
+    start: BytesStart<'static>,
+    inherited_namespaces: Namespaces,
+) -> Result<XmlElement> {
+    let mut element = XmlElement::new(&String::from_utf8(start.name().as_ref().to_vec())?);
+    for attr in start.attributes() {
+        let attr = attr?;
+        let key = String::from_utf8(attr.key.as_ref().to_vec())?;
+        let value = attr.unescape_value()?.to_string();
+        element.attributes.push((key, value));
+    }
+    element.inherited_namespaces = inherited_namespaces;
+    element.source = Some(SourceTags {
+        name: element.name.clone(),
+        attributes: element.attributes.clone(),
+        start,
+        end: None,
+    });
+    Ok(element)
+}
+
+fn select_children(
+    children: &[XmlNode],
+    parent: &NodePath,
+    step: &Step,
+    matched: &mut Vec<NodePath>,
+) {
+    for (index, node) in children.iter().enumerate() {
+        // text and comments between elements never match
+        let XmlNode::Element(element) = node else {
+            continue;
+        };
+
+        let mut path = parent.clone();
+        path.push(index);
+        if element.matches_step(step) {
+            matched.push(path.clone());
+        }
+        if step.descendant {
+            select_children(&element.children, &path, step, matched);
+        }
+    }
+}
+
+fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &XmlNode) -> Result<()> {
+    match node {
+        XmlNode::Other(event) => writer.write_event(event.clone())?,
+        XmlNode::Element(element) => {
+            let source = element.source.as_ref().filter(|source| {
+                source.name == element.name && source.attributes == element.attributes
+            });
+            let start = match source {
+                Some(source) => source.start.clone(),
+                None => {
+                    let mut start = BytesStart::new(element.name.as_str());
+                    for (key, value) in &element.attributes {
+                        start.push_attribute((key.as_str(), value.as_str()));
+                    }
+                    start
+                }
+            };
+
+            if element.children.is_empty() && element.self_closing {
+                writer.write_event(Event::Empty(start))?;
+            } else {
+                writer.write_event(Event::Start(start))?;
+                for child in &element.children {
+                    write_node(writer, child)?;
+                }
+                let end = source
+                    .and_then(|source| source.end.clone())
+                    .unwrap_or_else(|| BytesEnd::new(element.name.clone()));
+                writer.write_event(Event::End(end))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn xml_find_value(xml_content: &str, selector: &str, attr_key: &str) -> Result<Vec<String>> {
+    XmlDocument::parse(xml_content)?.find_attr(selector, attr_key)
 }
 
 pub fn xml_exchange_value(
     xml_content: &str,
-    node_tree: &[&str],
+    selector: &str,
     attr_key: &str,
     attr_value: &str,
 ) -> Result<String> {
-    let mut reader = Reader::from_str(xml_content);
-    reader.trim_text(true);
-    let mut writer = Writer::new(Cursor::new(Vec::new()));
-
-    let mut node_tree_index = 0;
-    loop {
-        match reader.read_event() {
-            Ok(Event::Start(e)) => {
-                // exchange package name
-                if e.name().as_ref() == node_tree[node_tree_index].as_bytes() {
-                    if node_tree_index == node_tree.len() - 1 {
-                        let mut elem = BytesStart::new(String::from_utf8_lossy(e.name().0));
-                        elem.extend_attributes(
-                            e.attributes()
-                                .collect::<Result<Vec<Attribute<'_>>, _>>()?
-                                .iter()
-                                .filter(|attr| attr.key.as_ref() != attr_key.as_bytes())
-                                .map(|attr| attr.to_owned()),
-                        );
-                        elem.push_attribute((attr_key, attr_value));
-                        assert!(writer.write_event(Event::Start(elem)).is_ok());
-                    } else {
-                        node_tree_index += 1;
-                    }
-                } else {
-                    assert!(writer.write_event(Event::Start(e)).is_ok());
-                }
-            }
-            Ok(Event::End(e)) => {
-                assert!(writer.write_event(Event::End(e)).is_ok());
-                node_tree_index = node_tree_index.saturating_sub(1);
-            }
-            Ok(Event::Eof) => break,
-            // we can either move or borrow the event to write, depending on your use-case
-            Ok(e) => assert!(writer.write_event(e).is_ok()),
-            Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "Error at position {}: {:?}",
-                    reader.buffer_position(),
-                    e
-                ))
-            }
+    let mut document = XmlDocument::parse(xml_content)?;
+    document.set_attr(selector, attr_key, attr_value)?;
+    document.to_xml_string()
+}
+
+/// See `XmlDocument::map_attrs`.
+pub fn xml_map_values(
+    xml_content: &str,
+    map: impl FnMut(&str, &str, &str) -> Option<String>,
+) -> Result<String> {
+    let mut document = XmlDocument::parse(xml_content)?;
+    document.map_attrs(map);
+    document.to_xml_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="no"?>
+<!-- decoded by apktool -->
+<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.app">
+    <uses-permission android:name="android.permission.INTERNET"/>
+    <application android:label="@string/app_name"
+        android:icon="@mipmap/ic_launcher" >
+        <activity android:name=".Main" android:exported="true">
+            <intent-filter>
+                <action android:name="android.intent.action.MAIN" />
+            </intent-filter>
+        </activity>
+        <service xmlns:a="http://schemas.android.com/apk/res/android" a:name=".Sync"></service>
+        <meta-data android:name="CHANNEL" android:value="a &amp; b"/>
+    </application>
+</manifest>
+"#;
+
+    #[test]
+    fn round_trip_is_byte_for_byte() {
+        let strings = "<resources>\n    <string name=\"hello\">Hello <b>bold</b> world</string>\n    <string name=\"space\">  two  spaces  </string>\n    <string name=\"raw\"><![CDATA[<raw>]]></string>\n</resources>\n";
+
+        for xml_content in [MANIFEST, strings] {
+            let document = XmlDocument::parse(xml_content).unwrap();
+            assert_eq!(document.to_xml_string().unwrap(), xml_content);
+        }
+    }
+
+    #[test]
+    fn malformed_xml_is_an_error() {
+        for xml_content in ["<a><b></a>", "<a>", "</a>", "<a b=\"1></a>"] {
+            assert!(XmlDocument::parse(xml_content).is_err(), "{}", xml_content);
         }
     }
 
-    Ok(String::from_utf8(writer.into_inner().into_inner()).unwrap())
+    #[test]
+    fn selects_by_path_descendant_and_predicate() {
+        let document = XmlDocument::parse(MANIFEST).unwrap();
+
+        let find = |selector: &str| document.find_attr(selector, "android:name").unwrap();
+        assert_eq!(find("manifest/application/activity"), [".Main"]);
+        assert_eq!(find("//action"), ["android.intent.action.MAIN"]);
+        assert_eq!(
+            find("manifest/application/*[@android:exported='true']"),
+            [".Main"]
+        );
+        assert_eq!(
+            find("manifest/application/*[@android:name]"),
+            [".Main", ".Sync", "CHANNEL"]
+        );
+        assert!(find("manifest/activity").is_empty());
+        assert_eq!(
+            document
+                .find_attr("//meta-data[@android:name='CHANNEL']", "android:value")
+                .unwrap(),
+            ["a & b"]
+        );
+        assert!(document.find("manifest[").is_err());
+    }
+
+    #[test]
+    fn namespaces_are_scoped_per_element() {
+        let xml_content = r#"<root xmlns:a="urn:first">
+    <item xmlns:a="http://schemas.android.com/apk/res/android" a:name="inner"/>
+    <item a:name="outer"/>
+</root>"#;
+        let document = XmlDocument::parse(xml_content).unwrap();
+
+        // only the redeclared prefix means android
+        assert_eq!(
+            document.find_attr("//item", "android:name").unwrap(),
+            ["inner"]
+        );
+        assert_eq!(
+            document.find_attr("//item", "a:name").unwrap(),
+            ["inner", "outer"]
+        );
+    }
+
+    #[test]
+    fn attribute_edits_keep_the_other_bytes() {
+        let mut document = XmlDocument::parse(MANIFEST).unwrap();
+        document
+            .set_attr("manifest/application/service", "android:name", ".Async")
+            .unwrap();
+        document
+            .set_attr("manifest/application/activity", "android:label", "Main")
+            .unwrap();
+        document
+            .remove_attr("//meta-data", "android:value")
+            .unwrap();
+        assert!(document
+            .set_attr("manifest/provider", "android:name", "x")
+            .is_err());
+
+        let expected = MANIFEST
+            .replace("a:name=\".Sync\"", "a:name=\".Async\"")
+            .replace(
+                "android:exported=\"true\">",
+                "android:exported=\"true\" android:label=\"Main\">",
+            )
+            .replace(" android:value=\"a &amp; b\"", "");
+        assert_eq!(document.to_xml_string().unwrap(), expected);
+    }
+
+    #[test]
+    fn inserted_and_removed_nodes_follow_the_indent() {
+        let mut document = XmlDocument::parse(MANIFEST).unwrap();
+        document.remove_node("manifest/uses-permission").unwrap();
+        let meta_data = XmlElement::new("meta-data")
+            .with_attr("android:name", "NEW")
+            .with_attr("android:value", "<1>");
+        document
+            .insert_child("manifest/application/service", &meta_data)
+            .unwrap();
+        document
+            .insert_child("manifest/application", &meta_data)
+            .unwrap();
+
+        let expected = MANIFEST
+            .replace("\n    <uses-permission android:name=\"android.permission.INTERNET\"/>", "")
+            .replace(
+                "></service>",
+                "><meta-data a:name=\"NEW\" a:value=\"&lt;1&gt;\"/></service>",
+            )
+            .replace(
+                "a &amp; b\"/>\n",
+                "a &amp; b\"/>\n        <meta-data android:name=\"NEW\" android:value=\"&lt;1&gt;\"/>\n",
+            );
+        assert_eq!(document.to_xml_string().unwrap(), expected);
+    }
 }
@@ -0,0 +1,270 @@
+use anyhow::Result;
+
+// apktool.yml starts with a `!!brut.androlib...` tag and is read back by
+// apktool itself, so values are edited line by line to keep the rest intact
+
+/// `(indent, key, raw value)` of a `key: value` line
+fn parse_key_line(line: &str) -> Option<(usize, &str, &str)> {
+    let indent = line_indent(line);
+    let content = &line[indent..];
+    if content.starts_with('-') || content.starts_with('#') || content.starts_with("!!") {
+        return None;
+    }
+
+    let (key, value) = content.split_once(':')?;
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    Some((indent, key, value.trim()))
+}
+
+fn line_indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Line index of every key of `key_path`, as deep as the path exists.
+fn find_key_lines(yml_content: &str, key_path: &[&str]) -> Vec<usize> {
+    let mut best: Vec<usize> = vec![];
+    let mut found: Vec<usize> = vec![];
+    let mut stack: Vec<(usize, &str)> = vec![];
+
+    for (index, line) in yml_content.lines().enumerate() {
+        let Some((indent, key, _)) = parse_key_line(line) else {
+            continue;
+        };
+
+        while stack
+            .last()
+            .is_some_and(|(last_indent, _)| *last_indent >= indent)
+        {
+            stack.pop();
+        }
+        stack.push((indent, key));
+
+        let matched = stack
+            .iter()
+            .zip(key_path)
+            .take_while(|((_, key), path_key)| key == *path_key)
+            .count();
+        found.truncate(matched);
+        if matched == stack.len() && matched > found.len() {
+            found.push(index);
+            if found.len() > best.len() {
+                best = found.clone();
+            }
+            if found.len() == key_path.len() {
+                break;
+            }
+        }
+    }
+
+    best
+}
+
+fn unquote(value: &str) -> Option<String> {
+    if value.is_empty() || value == "null" || value == "~" {
+        return None;
+    }
+
+    if let Some(value) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return Some(value.replace("''", "'"));
+    }
+
+    if let Some(value) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        return Some(value.replace("\\\"", "\"").replace("\\\\", "\\"));
+    }
+
+    Some(value.to_string())
+}
+
+/// Single quoted yml scalar, always read back as a string.
+pub fn yml_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Value at `key_path`, e.g. `["versionInfo", "versionCode"]`. `None` when the
+/// key is missing or null.
+pub fn yml_find_value(yml_content: &str, key_path: &[&str]) -> Option<String> {
+    let lines = find_key_lines(yml_content, key_path);
+    if lines.len() != key_path.len() {
+        return None;
+    }
+
+    let line = yml_content.lines().nth(*lines.last()?)?;
+    let (_, _, value) = parse_key_line(line)?;
+    unquote(value)
+}
+
+/// Sets the raw yml `value` at `key_path`, missing keys are added.
+pub fn yml_exchange_value(yml_content: &str, key_path: &[&str], value: &str) -> Result<String> {
+    if key_path.is_empty() {
+        return Err(anyhow::anyhow!("empty yml key path"));
+    }
+
+    let mut lines = yml_content
+        .lines()
+        .map(|line| line.to_string())
+        .collect::<Vec<String>>();
+    let found = find_key_lines(yml_content, key_path);
+
+    if found.len() == key_path.len() {
+        let index = found[found.len() - 1];
+        let indent = line_indent(&lines[index]);
+        lines[index] = format!(
+            "{}{}: {}",
+            " ".repeat(indent),
+            key_path[key_path.len() - 1],
+            value
+        );
+    } else {
+        // append the missing keys to the block of the deepest existing parent
+        let (mut insert_at, mut indent) = match found.last() {
+            Some(index) => {
+                let parent_indent = line_indent(&lines[*index]);
+                let (_, _, value) = parse_key_line(&lines[*index]).unwrap_or_default();
+                if value.is_empty() {
+                    let end = lines[index + 1..]
+                        .iter()
+                        .position(|line| {
+                            !line.trim().is_empty() && line_indent(line) <= parent_indent
+                        })
+                        .map_or(lines.len(), |offset| index + 1 + offset);
+                    (end, parent_indent + 2)
+                } else {
+                    // `sdkInfo: {}` or `sdkInfo: null` becomes a block
+                    lines[*index] = format!(
+                        "{}{}:",
+                        " ".repeat(parent_indent),
+                        key_path[found.len() - 1]
+                    );
+                    (index + 1, parent_indent + 2)
+                }
+            }
+            None => (lines.len(), 0),
+        };
+
+        for (depth, key) in key_path.iter().enumerate().skip(found.len()) {
+            let line = if depth == key_path.len() - 1 {
+                format!("{}{}: {}", " ".repeat(indent), key, value)
+            } else {
+                format!("{}{}:", " ".repeat(indent), key)
+            };
+            lines.insert(insert_at, line);
+            insert_at += 1;
+            indent += 2;
+        }
+    }
+
+    let mut result = lines.join("\n");
+    if yml_content.ends_with('\n') {
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APKTOOL_YML: &str = "!!brut.androlib.meta.MetaInfo
+apkFileName: demo.apk
+sdkInfo:
+  minSdkVersion: '21'
+  targetSdkVersion: '33'
+unknownFiles: {}
+versionInfo:
+  versionCode: '12'
+  versionName: 1.2.0
+";
+
+    #[test]
+    fn finds_nested_values() {
+        assert_eq!(
+            yml_find_value(APKTOOL_YML, &["sdkInfo", "minSdkVersion"]).as_deref(),
+            Some("21")
+        );
+        assert_eq!(
+            yml_find_value(APKTOOL_YML, &["versionInfo", "versionName"]).as_deref(),
+            Some("1.2.0")
+        );
+        assert_eq!(
+            yml_find_value(APKTOOL_YML, &["versionInfo", "minSdkVersion"]),
+            None
+        );
+        assert_eq!(
+            yml_find_value(APKTOOL_YML, &["unknownFiles"]).as_deref(),
+            Some("{}")
+        );
+        assert_eq!(
+            find_key_lines(APKTOOL_YML, &["versionInfo", "versionCode"]),
+            [6, 7]
+        );
+        assert_eq!(
+            find_key_lines(APKTOOL_YML, &["versionInfo", "missing"]),
+            [6]
+        );
+    }
+
+    #[test]
+    fn exchanges_only_the_value() {
+        let result = yml_exchange_value(
+            APKTOOL_YML,
+            &["versionInfo", "versionCode"],
+            &yml_quote("13"),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            APKTOOL_YML.replace("versionCode: '12'", "versionCode: '13'")
+        );
+        assert!(yml_exchange_value(APKTOOL_YML, &[], "x").is_err());
+    }
+
+    #[test]
+    fn adds_missing_keys() {
+        let result =
+            yml_exchange_value(APKTOOL_YML, &["sdkInfo", "maxSdkVersion"], "'34'").unwrap();
+        assert_eq!(
+            result,
+            APKTOOL_YML.replace("'33'\n", "'33'\n  maxSdkVersion: '34'\n")
+        );
+
+        let yml_content = "apkFileName: demo.apk\nsdkInfo: null\n";
+        let result =
+            yml_exchange_value(yml_content, &["sdkInfo", "minSdkVersion"], "'21'").unwrap();
+        assert_eq!(
+            result,
+            "apkFileName: demo.apk\nsdkInfo:\n  minSdkVersion: '21'\n"
+        );
+
+        let result = yml_exchange_value(
+            "apkFileName: demo.apk",
+            &["versionInfo", "versionCode"],
+            "'1'",
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            "apkFileName: demo.apk\nversionInfo:\n  versionCode: '1'"
+        );
+    }
+
+    #[test]
+    fn quoted_values_round_trip() {
+        for value in ["it's", "a \"b\"", "null"] {
+            let yml_content = format!("key: {}\n", yml_quote(value));
+            assert_eq!(
+                yml_find_value(&yml_content, &["key"]).as_deref(),
+                Some(value)
+            );
+        }
+        assert_eq!(yml_find_value("key: ''", &["key"]).as_deref(), Some(""));
+        assert_eq!(yml_find_value("key: null", &["key"]), None);
+        assert_eq!(
+            yml_find_value(r#"key: "a \"b\"""#, &["key"]).as_deref(),
+            Some("a \"b\"")
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::Result;
+use std::io::Read;
+
+pub const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+pub const CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x02014b50;
+pub const EOCD_SIGNATURE: u32 = 0x06054b50;
+pub const ZIP64_EOCD_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+
+pub const LOCAL_FILE_HEADER_SIZE: usize = 30;
+pub const CENTRAL_DIRECTORY_HEADER_SIZE: usize = 46;
+pub const EOCD_MIN_SIZE: usize = 22;
+
+pub const METHOD_STORED: u16 = 0;
+pub const METHOD_DEFLATED: u16 = 8;
+
+/// general purpose flag bit 3, sizes and crc follow the data in a descriptor
+pub const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+
+pub fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of zip data at {}", offset))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of zip data at {}", offset))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes = data
+        .get(offset..offset + 8)
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of zip data at {}", offset))?;
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipEocd {
+    /// offset of the end of central directory record itself
+    pub offset: usize,
+    pub cd_offset: usize,
+    pub cd_size: usize,
+    pub entries_count: usize,
+    pub comment: Vec<u8>,
+}
+
+pub fn find_eocd(data: &[u8]) -> Result<ZipEocd> {
+    if data.len() < EOCD_MIN_SIZE {
+        return Err(anyhow::anyhow!("not a zip file, too small"));
+    }
+
+    // the record is followed by a comment of at most 65535 bytes
+    let last = data.len() - EOCD_MIN_SIZE;
+    let first = last.saturating_sub(u16::MAX as usize);
+    for offset in (first..=last).rev() {
+        if read_u32(data, offset)? != EOCD_SIGNATURE {
+            continue;
+        }
+
+        let comment_len = read_u16(data, offset + 20)? as usize;
+        if offset + EOCD_MIN_SIZE + comment_len != data.len() {
+            continue;
+        }
+
+        if offset >= 20 && read_u32(data, offset - 20)? == ZIP64_EOCD_LOCATOR_SIGNATURE {
+            return Err(anyhow::anyhow!("zip64 archives are not supported"));
+        }
+
+        let cd_size = read_u32(data, offset + 12)? as usize;
+        let cd_offset = read_u32(data, offset + 16)? as usize;
+        if cd_offset + cd_size > offset {
+            return Err(anyhow::anyhow!("central directory out of range"));
+        }
+
+        return Ok(ZipEocd {
+            offset,
+            cd_offset,
+            cd_size,
+            entries_count: read_u16(data, offset + 10)? as usize,
+            comment: data[offset + EOCD_MIN_SIZE..].to_vec(),
+        });
+    }
+
+    Err(anyhow::anyhow!("end of central directory not found"))
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    pub version_made_by: u16,
+    pub version_needed: u16,
+    pub flags: u16,
+    pub method: u16,
+    pub time: u16,
+    pub date: u16,
+    pub crc32: u32,
+    pub compressed_size: usize,
+    pub uncompressed_size: usize,
+    pub internal_attrs: u16,
+    pub external_attrs: u32,
+    pub cd_extra: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub local_header_offset: usize,
+    pub local_extra: Vec<u8>,
+    /// offset of the (compressed) data following the local header
+    pub data_offset: usize,
+}
+
+impl ZipEntry {
+    pub fn raw_data<'a>(&self, data: &'a [u8]) -> Result<&'a [u8]> {
+        data.get(self.data_offset..self.data_offset + self.compressed_size)
+            .ok_or_else(|| anyhow::anyhow!("data of {} out of range", self.name))
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+pub fn read_central_directory(data: &[u8], eocd: &ZipEocd) -> Result<Vec<ZipEntry>> {
+    let mut entries = Vec::with_capacity(eocd.entries_count);
+
+    let cd_end = eocd.cd_offset + eocd.cd_size;
+    let mut offset = eocd.cd_offset;
+    while offset < cd_end {
+        if read_u32(data, offset)? != CENTRAL_DIRECTORY_SIGNATURE {
+            return Err(anyhow::anyhow!(
+                "bad central directory header at {}",
+                offset
+            ));
+        }
+
+        let name_len = read_u16(data, offset + 28)? as usize;
+        let extra_len = read_u16(data, offset + 30)? as usize;
+        let comment_len = read_u16(data, offset + 32)? as usize;
+        let local_header_offset = read_u32(data, offset + 42)? as usize;
+
+        let name_start = offset + CENTRAL_DIRECTORY_HEADER_SIZE;
+        let extra_start = name_start + name_len;
+        let comment_start = extra_start + extra_len;
+        let next = comment_start + comment_len;
+        if next > cd_end {
+            return Err(anyhow::anyhow!("central directory header out of range"));
+        }
+
+        // the local header may carry a different extra field, e.g. zipalign padding
+        if read_u32(data, local_header_offset)? != LOCAL_FILE_HEADER_SIGNATURE {
+            return Err(anyhow::anyhow!(
+                "bad local file header at {}",
+                local_header_offset
+            ));
+        }
+        let local_name_len = read_u16(data, local_header_offset + 26)? as usize;
+        let local_extra_len = read_u16(data, local_header_offset + 28)? as usize;
+        let local_extra_start = local_header_offset + LOCAL_FILE_HEADER_SIZE + local_name_len;
+        let data_offset = local_extra_start + local_extra_len;
+
+        let entry = ZipEntry {
+            name: String::from_utf8_lossy(&data[name_start..extra_start]).to_string(),
+            version_made_by: read_u16(data, offset + 4)?,
+            version_needed: read_u16(data, offset + 6)?,
+            flags: read_u16(data, offset + 8)?,
+            method: read_u16(data, offset + 10)?,
+            time: read_u16(data, offset + 12)?,
+            date: read_u16(data, offset + 14)?,
+            crc32: read_u32(data, offset + 16)?,
+            compressed_size: read_u32(data, offset + 20)? as usize,
+            uncompressed_size: read_u32(data, offset + 24)? as usize,
+            internal_attrs: read_u16(data, offset + 36)?,
+            external_attrs: read_u32(data, offset + 38)?,
+            cd_extra: data[extra_start..comment_start].to_vec(),
+            comment: data[comment_start..next].to_vec(),
+            local_header_offset,
+            local_extra: data
+                .get(local_extra_start..data_offset)
+                .ok_or_else(|| anyhow::anyhow!("local extra field out of range"))?
+                .to_vec(),
+            data_offset,
+        };
+        entries.push(entry);
+
+        offset = next;
+    }
+
+    Ok(entries)
+}
+
+/// Returns the uncompressed content of an entry.
+pub fn read_entry_data(data: &[u8], entry: &ZipEntry) -> Result<Vec<u8>> {
+    let raw = entry.raw_data(data)?;
+    match entry.method {
+        METHOD_STORED => Ok(raw.to_vec()),
+        METHOD_DEFLATED => {
+            let mut out = Vec::with_capacity(entry.uncompressed_size);
+            flate2::read::DeflateDecoder::new(raw).read_to_end(&mut out)?;
+            Ok(out)
+        }
+        method => Err(anyhow::anyhow!(
+            "unsupported compression method {} of {}",
+            method,
+            entry.name
+        )),
+    }
+}
+
+pub fn write_local_header(out: &mut Vec<u8>, entry: &ZipEntry, extra: &[u8]) {
+    out.extend_from_slice(&LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+    out.extend_from_slice(&entry.version_needed.to_le_bytes());
+    out.extend_from_slice(&(entry.flags & !FLAG_DATA_DESCRIPTOR).to_le_bytes());
+    out.extend_from_slice(&entry.method.to_le_bytes());
+    out.extend_from_slice(&entry.time.to_le_bytes());
+    out.extend_from_slice(&entry.date.to_le_bytes());
+    out.extend_from_slice(&entry.crc32.to_le_bytes());
+    out.extend_from_slice(&(entry.compressed_size as u32).to_le_bytes());
+    out.extend_from_slice(&(entry.uncompressed_size as u32).to_le_bytes());
+    out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+    out.extend_from_slice(entry.name.as_bytes());
+    out.extend_from_slice(extra);
+}
+
+pub fn write_central_directory_header(out: &mut Vec<u8>, entry: &ZipEntry) {
+    out.extend_from_slice(&CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+    out.extend_from_slice(&entry.version_made_by.to_le_bytes());
+    out.extend_from_slice(&entry.version_needed.to_le_bytes());
+    out.extend_from_slice(&(entry.flags & !FLAG_DATA_DESCRIPTOR).to_le_bytes());
+    out.extend_from_slice(&entry.method.to_le_bytes());
+    out.extend_from_slice(&entry.time.to_le_bytes());
+    out.extend_from_slice(&entry.date.to_le_bytes());
+    out.extend_from_slice(&entry.crc32.to_le_bytes());
+    out.extend_from_slice(&(entry.compressed_size as u32).to_le_bytes());
+    out.extend_from_slice(&(entry.uncompressed_size as u32).to_le_bytes());
+    out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entry.cd_extra.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entry.comment.len() as u16).to_le_bytes());
+    // disk number start
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&entry.internal_attrs.to_le_bytes());
+    out.extend_from_slice(&entry.external_attrs.to_le_bytes());
+    out.extend_from_slice(&(entry.local_header_offset as u32).to_le_bytes());
+    out.extend_from_slice(entry.name.as_bytes());
+    out.extend_from_slice(&entry.cd_extra);
+    out.extend_from_slice(&entry.comment);
+}
+
+pub fn write_eocd(
+    out: &mut Vec<u8>,
+    entries_count: usize,
+    cd_offset: usize,
+    cd_size: usize,
+    comment: &[u8],
+) {
+    out.extend_from_slice(&EOCD_SIGNATURE.to_le_bytes());
+    // disk numbers
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&(entries_count as u16).to_le_bytes());
+    out.extend_from_slice(&(entries_count as u16).to_le_bytes());
+    out.extend_from_slice(&(cd_size as u32).to_le_bytes());
+    out.extend_from_slice(&(cd_offset as u32).to_le_bytes());
+    out.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    out.extend_from_slice(comment);
+}
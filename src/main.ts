@@ -1,5 +1,6 @@
 import { invoke } from "@tauri-apps/api/tauri";
-import { message, open } from '@tauri-apps/api/dialog';
+import { listen } from '@tauri-apps/api/event';
+import { message, open, save } from '@tauri-apps/api/dialog';
 import { exists, BaseDirectory, copyFile } from '@tauri-apps/api/fs';
 import { sep, join } from '@tauri-apps/api/path'
 import { info } from "tauri-plugin-log-api";
@@ -9,22 +10,87 @@ import { convertFileSrc } from '@tauri-apps/api/tauri';
 let lb_apk_local_path: HTMLLabelElement | null;
 let btn_apk_load_info: HTMLButtonElement | null;
 let btn_apk_load_by_path: HTMLButtonElement | null;
+let info_job_id: string | null = null;
+let apk_icon_path: string | null = null;
 
 let lb_package_old: HTMLLabelElement | null;
 let it_package_new: HTMLInputElement | null;
+let cb_rename_package_references: HTMLInputElement | null;
 
 let lb_display_name_old: HTMLLabelElement | null;
 let it_display_name_new: HTMLInputElement | null;
+let tb_display_names: HTMLTableElement | null;
+let ta_display_names_add: HTMLTextAreaElement | null;
+let display_names_old: { [folder: string]: string } = {};
+
+let lb_version_code_old: HTMLLabelElement | null;
+let it_version_code_new: HTMLInputElement | null;
+let cb_auto_increase_version_code: HTMLInputElement | null;
+let lb_version_name_old: HTMLLabelElement | null;
+let it_version_name_new: HTMLInputElement | null;
+let lb_min_sdk_old: HTMLLabelElement | null;
+let it_min_sdk_new: HTMLInputElement | null;
+let lb_target_sdk_old: HTMLLabelElement | null;
+let it_target_sdk_new: HTMLInputElement | null;
 
 let icon_old: HTMLImageElement | null;
+let icon_round_old: HTMLImageElement | null;
 let icon_new: HTMLImageElement | null;
 let btn_load_icon: HTMLButtonElement | null;
 let des_icon_path: string | null = null;
-
-let lb_jdk_path: HTMLLabelElement | null;
+let cb_adaptive_icon: HTMLInputElement | null;
+let it_icon_background_color: HTMLInputElement | null;
+let btn_load_icon_background: HTMLButtonElement | null;
+let lb_icon_background_path: HTMLLabelElement | null;
+let cb_icon_monochrome: HTMLInputElement | null;
+let cb_icon_badge: HTMLInputElement | null;
+let it_icon_badge_text: HTMLInputElement | null;
+let sel_icon_badge_style: HTMLSelectElement | null;
+let it_icon_badge_color: HTMLInputElement | null;
+let it_icon_badge_text_color: HTMLInputElement | null;
+
+let tb_meta_data: HTMLTableElement | null;
+let ta_meta_data_add: HTMLTextAreaElement | null;
+
+type MetaData = { component: string, name: string, value: string | null, resource: string | null };
+let meta_data_old: MetaData[] = [];
+
+let ta_channels: HTMLTextAreaElement | null;
+let btn_load_channel_file: HTMLButtonElement | null;
+let it_output_name_template: HTMLInputElement | null;
+let lb_channel_old: HTMLLabelElement | null;
+let sel_channel_scheme: HTMLSelectElement | null;
+let ta_channel_extras: HTMLTextAreaElement | null;
+
+let sel_java_runtime: HTMLSelectElement | null;
 let btn_load_jdk_path: HTMLButtonElement | null;
+let btn_clear_decode_cache: HTMLButtonElement | null;
+
+let settings: Settings | null = null;
+let it_jvm_initial_memory: HTMLInputElement | null;
+let it_jvm_max_memory: HTMLInputElement | null;
+let lb_settings_output_folder: HTMLLabelElement | null;
+let btn_load_output_folder: HTMLButtonElement | null;
+let btn_clear_output_folder: HTMLButtonElement | null;
+let it_settings_output_name_template: HTMLInputElement | null;
+let it_decode_cache_mb: HTMLInputElement | null;
+let it_tool_timeout: HTMLInputElement | null;
+let lb_keystore_path: HTMLLabelElement | null;
+let btn_load_keystore: HTMLButtonElement | null;
+let btn_clear_keystore: HTMLButtonElement | null;
+let it_key_alias: HTMLInputElement | null;
+let it_store_password: HTMLInputElement | null;
+let it_key_password: HTMLInputElement | null;
+let btn_save_settings: HTMLButtonElement | null;
 
 let btn_start_work: HTMLButtonElement | null;
+let btn_cancel_work: HTMLButtonElement | null;
+let lb_job_progress: HTMLLabelElement | null;
+let ta_job_log: HTMLTextAreaElement | null;
+const JOB_LOG_LINES = 500;
+let work_job_id: string | null = null;
+let btn_save_recipe: HTMLButtonElement | null;
+let btn_run_recipe: HTMLButtonElement | null;
 
 async function cacheDir() {
 
@@ -41,6 +107,33 @@ async function alert(content: string) {
     });
 }
 
+// what the commands reject with
+type RepackError = { code: string, message: string, context: any };
+
+function describe_error(e: unknown): string {
+    let error = e as RepackError;
+    if (!error || typeof error.code !== "string") return "" + e;
+
+    switch (error.code) {
+        case "JavaNotFound":
+            return "Java not found, select Java Home: " + error.context.detail;
+        case "JavaUnsupported":
+            return "Java " + error.context.version + " is too old for apktool, select another Java Home";
+        case "InvalidPackageName":
+            return "Bad package name: " + error.context.name;
+        case "IconNotFound":
+            return "Icon not found: " + error.context.path;
+        case "InvalidIcon":
+            return "Icon can not be read: " + error.context.detail;
+        case "Cancelled":
+            return "Repack cancelled";
+        case "InvalidSettings":
+            return "Settings not saved, " + error.context.detail;
+        default:
+            return error.message;
+    }
+}
+
 async function bind_apk_elements() {
     lb_apk_local_path = document.querySelector("#lb_apk_local_path");
     btn_apk_load_info = document.querySelector("#btn_apk_load_info");
@@ -95,6 +188,16 @@ async function load_apk() {
     btn_apk_load_info!.hidden = false;
 }
 
+// a job id, or null after telling the user why not
+async function start_job(): Promise<string | null> {
+    try {
+        return await invoke<string>("start_job");
+    } catch (e) {
+        await alert(describe_error(e));
+        return null;
+    }
+}
+
 async function load_apk_info() {
     info("load_apk_info");
 
@@ -107,18 +210,35 @@ async function load_apk_info() {
 
     btn_apk_load_info!.hidden = true;
 
-    let result = await invoke<string>("unpack_and_get_apk_info", {
-        apkPath: apk_path,
-    });
+    // the previews of the last apk live in its workspace
+    if (info_job_id) {
+        await invoke("close_job", { jobId: info_job_id }).catch((e) => info(describe_error(e)));
+        info_job_id = null;
+        if (des_icon_path == apk_icon_path) {
+            des_icon_path = null;
+        }
+    }
 
-    info("" + result);
+    info_job_id = await start_job();
+    if (!info_job_id) {
+        btn_apk_load_info!.hidden = false;
+        return;
+    }
 
-    if (result.startsWith("error")) {
-        await alert('Load apk info failed');
+    let apk_info: any = null;
+    try {
+        apk_info = await invoke("unpack_and_get_apk_info", {
+            jobId: info_job_id,
+            apkPath: apk_path,
+        });
+    } catch (e) {
+        // the job is gone with the failure
+        info_job_id = null;
+        await alert('Load apk info failed: ' + describe_error(e));
     }
-    else {
-        let apk_info = JSON.parse(result);
-        info("apk_info: " + apk_info);
+
+    if (apk_info) {
+        info("apk_info: " + JSON.stringify(apk_info));
 
         lb_package_old!.textContent = apk_info['package_name'];
         if (it_package_new!.value == "")
@@ -127,31 +247,131 @@ async function load_apk_info() {
         if (it_display_name_new!.value == "")
             it_display_name_new!.value = apk_info['display_name'];
 
+        lb_version_code_old!.textContent = apk_info['version_code'];
+        lb_version_name_old!.textContent = apk_info['version_name'];
+        lb_min_sdk_old!.textContent = apk_info['min_sdk_version'];
+        lb_target_sdk_old!.textContent = apk_info['target_sdk_version'];
+
+        show_display_names(apk_info['display_names']);
+        show_meta_data(apk_info['meta_data']);
+
         let src_icon_path = apk_info['icon_path'];
+        apk_icon_path = src_icon_path;
         let icon_path = convertFileSrc(src_icon_path);
         info("icon_path: " + icon_path);
         icon_old!.src = icon_path;
+
+        let src_round_icon_path = apk_info['round_icon_path'];
+        icon_round_old!.hidden = !src_round_icon_path;
+        if (src_round_icon_path) {
+            icon_round_old!.src = convertFileSrc(src_round_icon_path);
+        }
         if (!des_icon_path) {
             icon_new!.src = icon_path;
             des_icon_path = src_icon_path;
         }
 
+        await load_apk_channel_info(apk_path);
+
         await alert('Load apk info success');
     }
 
     btn_apk_load_info!.hidden = false;
 }
 
+async function load_apk_channel_info(apk_path: string) {
+    let channel_info: any;
+    try {
+        channel_info = await invoke("get_apk_channel_info", {
+            apkPath: apk_path,
+        });
+    } catch (e) {
+        lb_channel_old!.textContent = describe_error(e);
+        return;
+    }
+
+    info("channel_info: " + JSON.stringify(channel_info));
+
+    if (channel_info['scheme'] == "none") {
+        lb_channel_old!.textContent = "none";
+        return;
+    }
+
+    // extras of the input apk are kept by the repack, only show them here
+    let extras = Object.entries(channel_info['extras'])
+        .map(([key, value]) => key + "=" + (typeof value == "string" ? value : JSON.stringify(value)))
+        .join(", ");
+    lb_channel_old!.textContent = (channel_info['channel'] ?? "no channel") + " (" + channel_info['scheme'] + ")"
+        + (extras ? " " + extras : "");
+}
+
 function bind_package_elements() {
     lb_package_old = document.querySelector("#lb_package_old");
     it_package_new = document.querySelector("#it_package_new");
+    cb_rename_package_references = document.querySelector("#cb_rename_package_references");
     lb_display_name_old = document.querySelector("#lb_display_name_old");
     it_display_name_new = document.querySelector("#it_display_name_new");
+    tb_display_names = document.querySelector("#tb_display_names");
+    ta_display_names_add = document.querySelector("#ta_display_names_add");
+}
+
+function show_display_names(display_names: { [folder: string]: string }) {
+    display_names_old = display_names;
+    tb_display_names!.innerHTML = "";
+
+    Object.entries(display_names).forEach(([folder, name], index) => {
+        let row = tb_display_names!.insertRow();
+        row.insertCell().textContent = folder;
+
+        let input = document.createElement("input");
+        input.id = "it_display_name_" + index;
+        input.value = name;
+        row.insertCell().appendChild(input);
+    });
+}
+
+// only the locales that changed, the display name input covers the rest
+function get_display_names(): { [folder: string]: string } {
+    let display_names: { [folder: string]: string } = {};
+
+    Object.entries(display_names_old).forEach(([folder, name], index) => {
+        let value = document.querySelector<HTMLInputElement>("#it_display_name_" + index)!.value;
+        if (value != name) display_names[folder] = value;
+    });
+
+    for (let line of ta_display_names_add!.value.split("\n")) {
+        let index = line.indexOf("=");
+        if (index <= 0) continue;
+        display_names[line.substring(0, index).trim()] = line.substring(index + 1).trim();
+    }
+
+    return display_names;
+}
+
+function bind_version_elements() {
+    lb_version_code_old = document.querySelector("#lb_version_code_old");
+    it_version_code_new = document.querySelector("#it_version_code_new");
+    cb_auto_increase_version_code = document.querySelector("#cb_auto_increase_version_code");
+    lb_version_name_old = document.querySelector("#lb_version_name_old");
+    it_version_name_new = document.querySelector("#it_version_name_new");
+    lb_min_sdk_old = document.querySelector("#lb_min_sdk_old");
+    it_min_sdk_new = document.querySelector("#it_min_sdk_new");
+    lb_target_sdk_old = document.querySelector("#lb_target_sdk_old");
+    it_target_sdk_new = document.querySelector("#it_target_sdk_new");
+}
+
+// empty keeps the original value
+function get_number(input: HTMLInputElement): number | null | undefined {
+    let value = input.value.trim();
+    if (value == "") return null;
+    if (!/^\d+$/.test(value)) return undefined;
+    return parseInt(value);
 }
 
 async function bind_app_icon_elements() {
 
     icon_old = document.querySelector("#icon_old");
+    icon_round_old = document.querySelector("#icon_round_old");
     icon_new = document.querySelector("#icon_new");
     btn_load_icon = document.querySelector("#btn_load_icon");
 
@@ -159,6 +379,63 @@ async function bind_app_icon_elements() {
         e.preventDefault();
         load_icon();
     });
+
+    cb_adaptive_icon = document.querySelector("#cb_adaptive_icon");
+    it_icon_background_color = document.querySelector("#it_icon_background_color");
+    btn_load_icon_background = document.querySelector("#btn_load_icon_background");
+    lb_icon_background_path = document.querySelector("#lb_icon_background_path");
+    cb_icon_monochrome = document.querySelector("#cb_icon_monochrome");
+    cb_icon_badge = document.querySelector("#cb_icon_badge");
+    it_icon_badge_text = document.querySelector("#it_icon_badge_text");
+    sel_icon_badge_style = document.querySelector("#sel_icon_badge_style");
+    it_icon_badge_color = document.querySelector("#it_icon_badge_color");
+    it_icon_badge_text_color = document.querySelector("#it_icon_badge_text_color");
+
+    btn_load_icon_background?.addEventListener("click", (e) => {
+        e.preventDefault();
+        load_icon_background();
+    });
+}
+
+async function load_icon_background() {
+    let selected = await open({
+        directory: false,
+        multiple: false,
+        filters: [{
+            name: '*.png',
+            extensions: ['png']
+        }],
+    });
+
+    if (Array.isArray(selected)) {
+        selected = selected[0];
+    }
+
+    // cancel clears the image, the color is used again
+    lb_icon_background_path!.textContent = selected ?? "";
+}
+
+function get_adaptive_icon(): object | null {
+    if (!cb_adaptive_icon!.checked) return null;
+
+    let background_path = lb_icon_background_path!.textContent;
+    return {
+        background: background_path
+            ? { type: "image", value: background_path }
+            : { type: "color", value: it_icon_background_color!.value },
+        monochrome: cb_icon_monochrome!.checked,
+    };
+}
+
+function get_icon_badge(): object | null {
+    if (!cb_icon_badge!.checked) return null;
+
+    return {
+        text: it_icon_badge_text!.value.trim(),
+        style: sel_icon_badge_style!.value,
+        color: it_icon_badge_color!.value,
+        text_color: it_icon_badge_text_color!.value,
+    };
 }
 
 async function load_icon() {
@@ -169,8 +446,8 @@ async function load_icon() {
         directory: false,
         multiple: false,
         filters: [{
-            name: '*.png',
-            extensions: ['png']
+            name: '*.png, *.webp, *.svg',
+            extensions: ['png', 'webp', 'jpg', 'jpeg', 'svg']
         }],
     });
 
@@ -193,10 +470,26 @@ async function load_icon() {
 
     info(selected);
 
+    // the extension tells svg sources apart
+    let extension = selected.split('.').pop()!.toLowerCase();
     let cached = await cacheDir();
-    let icon_cached = await join(cached, 'des-icon_' + Date.now() + '.png');
+    let icon_cached = await join(cached, 'des-icon_' + Date.now() + '.' + extension);
     await copyFile(selected, icon_cached);
 
+    let report: any;
+    try {
+        report = await invoke("check_new_icon", {
+            iconPath: icon_cached,
+        });
+    } catch (e) {
+        await alert(describe_error(e));
+        return;
+    }
+    info("check_new_icon: " + JSON.stringify(report));
+    if (report['issues'].length > 0) {
+        await alert('Icon issues:\n' + report['issues'].join('\n'));
+    }
+
     info("icon_cached: " + icon_cached);
     let icon_path = convertFileSrc(icon_cached);
     info("icon_path: " + icon_path);
@@ -205,8 +498,127 @@ async function load_icon() {
     des_icon_path = icon_cached;
 }
 
+function bind_meta_data_elements() {
+    tb_meta_data = document.querySelector("#tb_meta_data");
+    ta_meta_data_add = document.querySelector("#ta_meta_data_add");
+}
+
+function show_meta_data(meta_data: MetaData[]) {
+    meta_data_old = meta_data;
+    tb_meta_data!.innerHTML = "";
+
+    meta_data.forEach((item, index) => {
+        let row = tb_meta_data!.insertRow();
+        row.insertCell().textContent = item.component;
+        row.insertCell().textContent = item.name;
+
+        let value = document.createElement("input");
+        value.id = "it_meta_data_" + index;
+        value.value = item.value ?? item.resource ?? "";
+        row.insertCell().appendChild(value);
+
+        let remove = document.createElement("input");
+        remove.type = "checkbox";
+        remove.id = "cb_meta_data_remove_" + index;
+        remove.title = "remove";
+        row.insertCell().appendChild(remove);
+    });
+}
+
+function get_meta_data_edits(): object[] {
+    let edits: object[] = [];
+
+    meta_data_old.forEach((item, index) => {
+        let value = document.querySelector<HTMLInputElement>("#it_meta_data_" + index)!.value;
+        let remove = document.querySelector<HTMLInputElement>("#cb_meta_data_remove_" + index)!.checked;
+        if (remove) {
+            edits.push({ op: "remove", component: item.component, name: item.name });
+        } else if (value != (item.value ?? item.resource ?? "")) {
+            edits.push({ op: "set", component: item.component, name: item.name, value: value });
+        }
+    });
+
+    for (let line of ta_meta_data_add!.value.split("\n")) {
+        let index = line.indexOf("=");
+        if (index <= 0) continue;
+        let key = line.substring(0, index).trim();
+        let value = line.substring(index + 1).trim();
+        let slash = key.lastIndexOf("/");
+        let component = slash > 0 ? key.substring(0, slash) : "";
+        let name = key.substring(slash + 1);
+        edits.push({ op: "add", component: component, name: name, value: value });
+    }
+
+    return edits;
+}
+
+function bind_channel_elements() {
+    ta_channels = document.querySelector("#ta_channels");
+    btn_load_channel_file = document.querySelector("#btn_load_channel_file");
+    it_output_name_template = document.querySelector("#it_output_name_template");
+    lb_channel_old = document.querySelector("#lb_channel_old");
+    sel_channel_scheme = document.querySelector("#sel_channel_scheme");
+    ta_channel_extras = document.querySelector("#ta_channel_extras");
+
+    btn_load_channel_file?.addEventListener("click", (e) => {
+        e.preventDefault();
+        load_channel_file();
+    });
+}
+
+async function load_channel_file() {
+
+    let selected = await open({
+        directory: false,
+        multiple: false,
+        filters: [{
+            name: '*.txt, *.csv',
+            extensions: ['txt', 'csv']
+        }],
+    });
+
+    if (selected === null) {
+        return;
+    }
+
+    if (Array.isArray(selected)) {
+        selected = selected[0];
+    }
+
+    let channels: string[];
+    try {
+        channels = await invoke<string[]>("load_channel_file", {
+            channelFilePath: selected,
+        });
+    } catch (e) {
+        await alert('Load channel file failed: ' + describe_error(e));
+        return;
+    }
+
+    ta_channels!.value = channels.join("\n");
+}
+
+function get_channels(): string[] {
+    return ta_channels!.value
+        .split("\n")
+        .map((channel) => channel.trim())
+        .filter((channel) => channel.length > 0);
+}
+
+function get_channel_extras(): { [key: string]: string } {
+    let extras: { [key: string]: string } = {};
+    for (let line of ta_channel_extras!.value.split("\n")) {
+        let index = line.indexOf("=");
+        if (index <= 0) continue;
+        extras[line.substring(0, index).trim()] = line.substring(index + 1).trim();
+    }
+    return extras;
+}
+
+type JavaRuntime = { java_home: string, version: string, major: number, vendor: string, source: string, supported: boolean };
+
 async function bind_environments() {
-    lb_jdk_path = document.querySelector("#lb_jdk_path");
+    sel_java_runtime = document.querySelector("#sel_java_runtime");
     btn_load_jdk_path = document.querySelector("#btn_load_jdk_path");
 
     btn_load_jdk_path?.addEventListener("click", (e) => {
@@ -214,9 +626,65 @@ async function bind_environments() {
         load_jdk_path();
     });
 
-    let result = await invoke<string>("get_env", { name: "JAVA_HOME" });
+    sel_java_runtime?.addEventListener("change", () => {
+        select_java_home(sel_java_runtime!.value);
+    });
+
+    btn_clear_decode_cache = document.querySelector("#btn_clear_decode_cache");
+    btn_clear_decode_cache?.addEventListener("click", (e) => {
+        e.preventDefault();
+        clear_decode_cache();
+    });
 
-    lb_jdk_path!.textContent = result;
+    bind_settings_elements();
+    settings = await invoke<Settings>("get_settings");
+    show_settings(settings);
+    await show_java_runtimes();
+}
+
+// the java of the settings when set, else the first supported java is the
+// one repacks use
+async function show_java_runtimes() {
+    let runtimes = await invoke<JavaRuntime[]>("list_java_runtimes");
+    let java_home = settings?.java_home || "";
+
+    sel_java_runtime!.innerHTML = "";
+    for (let runtime of runtimes) {
+        add_java_runtime(runtime);
+    }
+    if (runtimes.length == 0) {
+        let option = document.createElement("option");
+        option.text = "no java found";
+        option.disabled = true;
+        sel_java_runtime!.add(option);
+    }
+
+    let selected = runtimes.find((runtime) => runtime.java_home == java_home)
+        || runtimes.find((runtime) => runtime.supported);
+    if (selected) {
+        sel_java_runtime!.value = selected.java_home;
+    }
+}
+
+function add_java_runtime(runtime: JavaRuntime) {
+    let option = document.createElement("option");
+    option.value = runtime.java_home;
+    option.text = "Java " + runtime.version + (runtime.vendor ? " " + runtime.vendor : "")
+        + " (" + runtime.source + ") " + runtime.java_home
+        + (runtime.supported ? "" : " - too old");
+    option.disabled = !runtime.supported;
+    sel_java_runtime!.add(option);
+}
+
+// saved at once, the other settings keep their saved values
+async function select_java_home(java_home: string) {
+    let saved = await save_settings({ ...settings!, java_home: java_home });
+    if (!saved) {
+        await show_java_runtimes();
+        return null;
+    }
+
+    return java_home;
 }
 
 async function load_jdk_path() {
@@ -225,36 +693,19 @@ async function load_jdk_path() {
     let selected = await open({
         directory: true,
         multiple: false,
-        filters: [{
-            name: 'bin',
-            extensions: ['']
-        }],
     });
 
     info("selected: " + selected);
 
     if (!selected) {
-        if (!lb_jdk_path!.textContent) {
-            // user cancelled the selection
-            await alert('Please select java path');
-            return;
-        }
-        else {
-            selected = lb_jdk_path!.textContent;
-        }
+        // user cancelled the selection
+        return;
     }
 
     if (Array.isArray(selected)) {
         selected = selected[0];
     }
 
-    let fileExist = await exists(selected, { dir: BaseDirectory.AppData });
-
-    if (!fileExist) {
-        await alert('Java path not exist');
-        return;
-    }
-
     // remove bin/java
     if (selected.endsWith(".exe"))
         selected = selected.substring(0, selected.length - 9);
@@ -267,9 +718,177 @@ async function load_jdk_path() {
 
     info(selected);
 
+    let java_home = await select_java_home(selected);
+    if (!java_home) return;
 
-    lb_jdk_path!.textContent = selected;
-    await invoke("set_env", { name: "JAVA_HOME", value: selected });
+    // lists it with its version
+    await show_java_runtimes();
+}
+
+type SignConfig = { keystore_path: string, store_password: string, key_alias: string, key_password: string };
+
+type Settings = {
+    java_home: string,
+    jvm_initial_memory_mb: number,
+    jvm_max_memory_mb: number,
+    output_folder: string,
+    output_name_template: string,
+    sign_config: SignConfig | null,
+    decode_cache_mb: number,
+    tool_timeout_secs: number,
+};
+
+function bind_settings_elements() {
+    it_jvm_initial_memory = document.querySelector("#it_jvm_initial_memory");
+    it_jvm_max_memory = document.querySelector("#it_jvm_max_memory");
+    lb_settings_output_folder = document.querySelector("#lb_settings_output_folder");
+    btn_load_output_folder = document.querySelector("#btn_load_output_folder");
+    btn_clear_output_folder = document.querySelector("#btn_clear_output_folder");
+    it_settings_output_name_template = document.querySelector("#it_settings_output_name_template");
+    it_decode_cache_mb = document.querySelector("#it_decode_cache_mb");
+    it_tool_timeout = document.querySelector("#it_tool_timeout");
+    lb_keystore_path = document.querySelector("#lb_keystore_path");
+    btn_load_keystore = document.querySelector("#btn_load_keystore");
+    btn_clear_keystore = document.querySelector("#btn_clear_keystore");
+    it_key_alias = document.querySelector("#it_key_alias");
+    it_store_password = document.querySelector("#it_store_password");
+    it_key_password = document.querySelector("#it_key_password");
+    btn_save_settings = document.querySelector("#btn_save_settings");
+
+    btn_load_output_folder?.addEventListener("click", async (e) => {
+        e.preventDefault();
+        let selected = await open({ directory: true, multiple: false });
+        if (!selected) return;
+        lb_settings_output_folder!.textContent = Array.isArray(selected) ? selected[0] : selected;
+    });
+    btn_clear_output_folder?.addEventListener("click", (e) => {
+        e.preventDefault();
+        lb_settings_output_folder!.textContent = "";
+    });
+
+    btn_load_keystore?.addEventListener("click", async (e) => {
+        e.preventDefault();
+        let selected = await open({ multiple: false });
+        if (!selected) return;
+        lb_keystore_path!.textContent = Array.isArray(selected) ? selected[0] : selected;
+    });
+    btn_clear_keystore?.addEventListener("click", (e) => {
+        e.preventDefault();
+        lb_keystore_path!.textContent = "";
+    });
+
+    btn_save_settings?.addEventListener("click", async (e) => {
+        e.preventDefault();
+        let edited = get_settings();
+        if (!edited) {
+            await alert('Memory, cache size and timeout must be numbers');
+            return;
+        }
+        if (await save_settings(edited)) {
+            await alert('Settings saved');
+        }
+    });
+}
+
+function show_settings(settings: Settings) {
+    it_jvm_initial_memory!.value = "" + settings.jvm_initial_memory_mb;
+    it_jvm_max_memory!.value = "" + settings.jvm_max_memory_mb;
+    lb_settings_output_folder!.textContent = settings.output_folder;
+    it_settings_output_name_template!.value = settings.output_name_template;
+    it_decode_cache_mb!.value = "" + settings.decode_cache_mb;
+    it_tool_timeout!.value = "" + settings.tool_timeout_secs;
+    lb_keystore_path!.textContent = settings.sign_config?.keystore_path || "";
+    it_key_alias!.value = settings.sign_config?.key_alias || "";
+    it_store_password!.value = settings.sign_config?.store_password || "";
+    it_key_password!.value = settings.sign_config?.key_password || "";
+
+    // repacks without a template of their own use it
+    it_output_name_template!.placeholder = settings.output_name_template || "{package}-{channel}.apk";
+}
+
+// undefined when a number field is not a number
+function get_settings(): Settings | undefined {
+    let jvm_initial_memory_mb = get_number(it_jvm_initial_memory!);
+    let jvm_max_memory_mb = get_number(it_jvm_max_memory!);
+    let decode_cache_mb = get_number(it_decode_cache_mb!);
+    let tool_timeout_secs = get_number(it_tool_timeout!);
+    if (jvm_initial_memory_mb == null || jvm_max_memory_mb == null || decode_cache_mb == null || tool_timeout_secs == null) {
+        return undefined;
+    }
+
+    // no keystore signs with the bundled one
+    let keystore_path = lb_keystore_path!.textContent || "";
+    return {
+        java_home: settings?.java_home || "",
+        jvm_initial_memory_mb: jvm_initial_memory_mb,
+        jvm_max_memory_mb: jvm_max_memory_mb,
+        output_folder: lb_settings_output_folder!.textContent || "",
+        output_name_template: it_settings_output_name_template!.value.trim(),
+        sign_config: keystore_path ? {
+            keystore_path: keystore_path,
+            store_password: it_store_password!.value,
+            key_alias: it_key_alias!.value.trim(),
+            key_password: it_key_password!.value,
+        } : null,
+        decode_cache_mb: decode_cache_mb,
+        tool_timeout_secs: tool_timeout_secs,
+    };
+}
+
+// every field is checked before anything is saved
+async function save_settings(edited: Settings): Promise<boolean> {
+    try {
+        settings = await invoke<Settings>("update_settings", { settings: edited });
+        info("settings: " + JSON.stringify({ ...settings, sign_config: settings.sign_config?.keystore_path }));
+        show_settings(settings);
+        return true;
+    } catch (e) {
+        await alert(describe_error(e));
+        return false;
+    }
+}
+
+// decoded apks are kept so loading the info and repacking decode once
+async function clear_decode_cache() {
+    try {
+        let freed = await invoke<number>("clear_decode_cache");
+        await alert('Decode cache cleared, ' + (freed / 1024 / 1024).toFixed(1) + ' MB freed');
+    } catch (e) {
+        await alert(describe_error(e));
+    }
+}
+
+// undefined when a version field is not a number
+function get_repack_params(apk_file_path: string): object | undefined {
+    let apk_display_name = it_display_name_new!.value;
+    let apk_version_code = get_number(it_version_code_new!);
+    let apk_min_sdk_version = get_number(it_min_sdk_new!);
+    let apk_target_sdk_version = get_number(it_target_sdk_new!);
+    if (apk_version_code === undefined || apk_min_sdk_version === undefined || apk_target_sdk_version === undefined) {
+        return undefined;
+    }
+
+    return {
+        apk_file_path: apk_file_path,
+        apk_package_name: it_package_new!.value,
+        rename_package_references: cb_rename_package_references!.checked,
+        // unchanged keeps the localized labels
+        apk_display_name: apk_display_name == lb_display_name_old!.textContent ? "" : apk_display_name,
+        apk_display_names: get_display_names(),
+        apk_icon_file_path: des_icon_path || "",
+        adaptive_icon: get_adaptive_icon(),
+        icon_badge: get_icon_badge(),
+        apk_version_code: apk_version_code,
+        auto_increase_version_code: cb_auto_increase_version_code!.checked,
+        apk_version_name: it_version_name_new!.value.trim(),
+        apk_min_sdk_version: apk_min_sdk_version,
+        apk_target_sdk_version: apk_target_sdk_version,
+        meta_data_edits: get_meta_data_edits(),
+        channels: get_channels(),
+        output_name_template: it_output_name_template!.value,
+        channel_scheme: sel_channel_scheme!.value || null,
+        channel_extras: get_channel_extras(),
+    };
 }
 
 async function repack(): Promise<boolean> {
@@ -298,19 +917,30 @@ async function repack(): Promise<boolean> {
         return false;
     }
 
-    let result = await invoke<string>("change_content_and_repack_apk", {
-        apkFilePath: apk_file_path,
-        apkPackageName: apk_package_name,
-        apkDisplayName: apk_display_name,
-        apkIconFilePath: apk_icon_file_path
-    });
-
-    if (result != "success") {
-        await alert('Repack failed');
+    let params = get_repack_params(apk_file_path);
+    if (!params) {
+        await alert('Version code and sdk versions must be numbers');
         return false;
     }
 
-    return true;
+    let job_id = await start_job();
+    if (!job_id) return false;
+
+    work_job_id = job_id;
+    ta_job_log!.value = "";
+    btn_cancel_work!.hidden = false;
+    let success = true;
+    try {
+        let outputs = await invoke<string[]>("repack_apk", { jobId: job_id, params: params });
+        info("repacked: " + outputs.join(", "));
+    } catch (e) {
+        await alert('Repack failed: ' + describe_error(e));
+        success = false;
+    }
+    btn_cancel_work!.hidden = true;
+    work_job_id = null;
+
+    return success;
 }
 
 async function start_work() {
@@ -332,6 +962,91 @@ async function start_work() {
     }
 }
 
+const RECIPE_FILTERS = [{ name: 'Recipe', extensions: ['toml', 'json'] }];
+
+async function save_recipe() {
+    let params = get_repack_params(lb_apk_local_path!.textContent || "");
+    if (!params) {
+        await alert('Version code and sdk versions must be numbers');
+        return;
+    }
+
+    let recipe_path = await save({ defaultPath: 'recipe.toml', filters: RECIPE_FILTERS });
+    if (!recipe_path) return;
+
+    try {
+        await invoke("save_repack_recipe", {
+            params: params,
+            recipePath: recipe_path,
+        });
+    } catch (e) {
+        await alert(describe_error(e));
+    }
+}
+
+async function run_recipe() {
+    let apk_file_path = lb_apk_local_path!.textContent;
+    if (!apk_file_path || !await exists(apk_file_path, { dir: BaseDirectory.AppData })) {
+        await alert('Please select an apk file');
+        return;
+    }
+
+    let recipe_path = await open({ multiple: false, filters: RECIPE_FILTERS });
+    if (!recipe_path || Array.isArray(recipe_path)) return;
+
+    let job_id = await start_job();
+    if (!job_id) return;
+
+    btn_start_work!.hidden = true;
+    work_job_id = job_id;
+    ta_job_log!.value = "";
+    btn_cancel_work!.hidden = false;
+    let failure: string | null = null;
+    try {
+        await invoke("repack_apk_with_recipe", {
+            jobId: job_id,
+            apkFilePath: apk_file_path,
+            recipePath: recipe_path,
+        });
+    } catch (e) {
+        failure = describe_error(e);
+    }
+    btn_cancel_work!.hidden = true;
+    work_job_id = null;
+    btn_start_work!.hidden = false;
+
+    await alert(failure ? 'Repack failed: ' + failure : 'Repack success');
+}
+
+type JobProgress = { job_id: string, stage: string, status: string, message: string, elapsed_ms: number | null };
+
+function show_job_progress(progress: JobProgress) {
+    if (progress.job_id != work_job_id) return;
+
+    if (progress.status == "running" || progress.status == "output") {
+        let lines = (ta_job_log!.value + progress.message + "\n").split("\n");
+        ta_job_log!.value = lines.slice(-JOB_LOG_LINES - 1).join("\n");
+        ta_job_log!.scrollTop = ta_job_log!.scrollHeight;
+        if (progress.status == "output") return;
+    }
+
+    if (progress.status == "finished") {
+        lb_job_progress!.textContent = progress.stage + " done in " + ((progress.elapsed_ms || 0) / 1000).toFixed(1) + "s";
+    } else {
+        lb_job_progress!.textContent = progress.stage + (progress.message ? ": " + progress.message : "");
+    }
+}
+
+async function cancel_work() {
+    if (!work_job_id) return;
+    try {
+        await invoke("cancel_job", { jobId: work_job_id });
+        lb_job_progress!.textContent = "cancelled";
+    } catch (e) {
+        await alert(describe_error(e));
+    }
+}
+
 function bind_works() {
     btn_start_work = document.querySelector("#btn_start_work");
     btn_start_work?.addEventListener("click", (e) => {
@@ -339,6 +1054,28 @@ function bind_works() {
         start_work();
     });
 
+    btn_cancel_work = document.querySelector("#btn_cancel_work");
+    btn_cancel_work?.addEventListener("click", (e) => {
+        e.preventDefault();
+        cancel_work();
+    });
+
+    lb_job_progress = document.querySelector("#lb_job_progress");
+    ta_job_log = document.querySelector("#ta_job_log");
+    listen<JobProgress>("job-progress", (event) => show_job_progress(event.payload));
+
+    btn_save_recipe = document.querySelector("#btn_save_recipe");
+    btn_save_recipe?.addEventListener("click", (e) => {
+        e.preventDefault();
+        save_recipe();
+    });
+
+    btn_run_recipe = document.querySelector("#btn_run_recipe");
+    btn_run_recipe?.addEventListener("click", (e) => {
+        e.preventDefault();
+        run_recipe();
+    });
+
     btn_start_work!.hidden = false;
 }
 
@@ -347,7 +1084,10 @@ window.addEventListener("DOMContentLoaded", () => {
     info("DOMContentLoaded");
     bind_apk_elements();
     bind_package_elements();
+    bind_version_elements();
     bind_app_icon_elements();
+    bind_meta_data_elements();
+    bind_channel_elements();
     bind_works();
     bind_environments();
 });